@@ -0,0 +1,219 @@
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::outcome::Outcome;
+use rocket::mtls;
+
+use crate::chord_util;
+use crate::config;
+
+// クライアント向けAPIの認証・認可と、内部RPCの呼び出し元の検証を行うリクエストガード
+// 各エンドポイントのハンドラの引数に加えて用いる
+//
+// - クライアント向けAPI: APIキーを X-Api-Key ヘッダ、もしくは Authorization: Bearer で渡す
+//   キーに与えられたロールがエンドポイントの要求するロール以上であれば受け付ける
+// - 内部RPC: TLSが有効な場合はクラスタのCAが発行したクライアント証明書、
+//   そうでない場合は X-Cluster-Token ヘッダの値が設定と一致することで、クラスタのノードであると判断する
+//
+// クラスタのノードであることが確認できたリクエストは、クライアント向けAPIも全て呼び出せる
+
+pub const API_KEY_HEADER : &str = "X-Api-Key";
+pub const CLUSTER_TOKEN_HEADER : &str = "X-Cluster-Token";
+
+enum ClusterCheck {
+    Verified,
+    // TLSもクラスタトークンも設定されておらず、確認を行えない
+    NotConfigured,
+    Rejected
+}
+
+// 長さ以外の情報がタイミングから漏れないように比較する
+fn secret_equals(a: &str, b: &str) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut diff: u8 = 0;
+    for (x, y) in a.bytes().zip(b.bytes()) {
+        diff |= x ^ y;
+    }
+    return diff == 0;
+}
+
+// バイナリプロトコルのリクエストに含まれるクラスタトークンの検証に用いる
+// (バイナリプロトコルはTLSが無効な場合にのみ用いられる)
+pub fn is_valid_cluster_token(token: &str) -> bool {
+    return judge_cluster_member(check_cluster_token(config::get_cluster_token(), Some(token)), config::is_api_key_auth_enabled());
+}
+
+// 設定されたクラスタトークン expected と、リクエストで渡されたトークン token を照合する
+fn check_cluster_token(expected: Option<String>, token: Option<&str>) -> ClusterCheck {
+    return match expected {
+        None => ClusterCheck::NotConfigured,
+        Some(expected) => match token {
+            Some(token) if secret_equals(expected.as_str(), token) => ClusterCheck::Verified,
+            _ => ClusterCheck::Rejected
+        }
+    };
+}
+
+// クラスタのノードとして受け付けるか
+// APIキーの認証を行う場合は、確認の手段が無ければ受け付けない
+fn judge_cluster_member(check: ClusterCheck, api_key_auth_enabled: bool) -> bool {
+    return match check {
+        ClusterCheck::Verified => true,
+        ClusterCheck::NotConfigured => api_key_auth_enabled == false,
+        ClusterCheck::Rejected => false
+    };
+}
+
+async fn check_cluster_member<'r>(req: &'r Request<'_>) -> ClusterCheck {
+    if config::is_tls_enabled() {
+        // 証明書の検証自体はTLSのハンドシェイク時に行われている
+        return match req.guard::<mtls::Certificate<'r>>().await {
+            Outcome::Success(_) => ClusterCheck::Verified,
+            _ => ClusterCheck::Rejected
+        };
+    }
+
+    return check_cluster_token(config::get_cluster_token(), req.headers().get_one(CLUSTER_TOKEN_HEADER));
+}
+
+fn extract_api_key<'r>(req: &'r Request<'_>) -> Option<&'r str> {
+    match req.headers().get_one(API_KEY_HEADER) {
+        Some(key) => { return Some(key); }
+        None => {}
+    };
+    return match req.headers().get_one("Authorization") {
+        None => None,
+        Some(auth_val) => auth_val.strip_prefix("Bearer ")
+    };
+}
+
+async fn authorize(req: &Request<'_>, required: config::Role) -> request::Outcome<(), ()> {
+    if config::is_api_key_auth_enabled() == false {
+        return Outcome::Success(());
+    }
+
+    match extract_api_key(req) {
+        None => {}
+        Some(key) => {
+            match config::get_role_of_api_key(key) {
+                None => {}
+                Some(role) => {
+                    if role >= required {
+                        return Outcome::Success(());
+                    }
+                    // キーは正しいがロールが不足している
+                    // (クラスタのノードからのリクエストであればその限りではないので下で確認する)
+                    if let ClusterCheck::Verified = check_cluster_member(req).await {
+                        return Outcome::Success(());
+                    }
+                    chord_util::dprint(&("auth_forbidden,".to_string() + req.uri().to_string().as_str() + "," + format!("{:?}", role).as_str()));
+                    return Outcome::Error((Status::Forbidden, ()));
+                }
+            };
+        }
+    };
+
+    if let ClusterCheck::Verified = check_cluster_member(req).await {
+        return Outcome::Success(());
+    }
+    chord_util::dprint(&("auth_unauthorized,".to_string() + req.uri().to_string().as_str()));
+    return Outcome::Error((Status::Unauthorized, ()));
+}
+
+// 参照系のクライアント向けAPIに用いる
+pub struct ReadAccess;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ReadAccess {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        return authorize(req, config::Role::Read).await.map(|_| ReadAccess);
+    }
+}
+
+// 更新系のクライアント向けAPIに用いる
+pub struct WriteAccess;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for WriteAccess {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        return authorize(req, config::Role::Write).await.map(|_| WriteAccess);
+    }
+}
+
+// 管理用のAPIに用いる
+pub struct AdminAccess;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminAccess {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        return authorize(req, config::Role::Admin).await.map(|_| AdminAccess);
+    }
+}
+
+// 内部RPC (ノード間でのみ用いるエンドポイント) に用いる
+// クラスタのノードであることを確認する手段が設定されていない場合は、APIキーの認証も行わない設定でのみ全て通す
+// (load_config で api_keys を指定する場合はTLSかクラスタトークンを必須としているが、念のためここでも拒否する)
+pub struct ClusterMember;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClusterMember {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        if judge_cluster_member(check_cluster_member(req).await, config::is_api_key_auth_enabled()) {
+            return Outcome::Success(ClusterMember);
+        }
+        chord_util::dprint(&("ERROR at ClusterMember::from_request,caller is not a cluster member,".to_string() + req.uri().to_string().as_str()));
+        return Outcome::Error((Status::Unauthorized, ()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_member(expected: Option<&str>, token: Option<&str>, api_key_auth_enabled: bool) -> bool {
+        return judge_cluster_member(check_cluster_token(expected.map(|t| t.to_string()), token), api_key_auth_enabled);
+    }
+
+    #[test]
+    fn secret_equals_compares_whole_value() {
+        assert!(secret_equals("token-abc", "token-abc"));
+        assert!(secret_equals("", ""));
+        assert!(secret_equals("token-abc", "token-abd") == false);
+        // 長さが異なる場合は、一方が他方の先頭部分と一致していても一致しない
+        assert!(secret_equals("token-abc", "token-ab") == false);
+        assert!(secret_equals("token-ab", "token-abc") == false);
+        assert!(secret_equals("token-abc", "") == false);
+    }
+
+    #[test]
+    fn cluster_member_without_token_configured() {
+        // 確認の手段が無い場合は、APIキーの認証を行わない設定でのみ受け付ける
+        assert!(matches!(check_cluster_token(None, Some("anything")), ClusterCheck::NotConfigured));
+        assert!(is_member(None, None, false));
+        assert!(is_member(None, Some("anything"), false));
+        assert!(is_member(None, None, true) == false);
+        assert!(is_member(None, Some("anything"), true) == false);
+    }
+
+    #[test]
+    fn cluster_member_with_token_configured() {
+        for api_key_auth_enabled in [false, true] {
+            assert!(is_member(Some("secret-token"), Some("secret-token"), api_key_auth_enabled));
+            assert!(is_member(Some("secret-token"), Some("secret-tokem"), api_key_auth_enabled) == false);
+            assert!(is_member(Some("secret-token"), Some("secret-token-2"), api_key_auth_enabled) == false);
+            assert!(is_member(Some("secret-token"), Some("secret"), api_key_auth_enabled) == false);
+            assert!(is_member(Some("secret-token"), Some(""), api_key_auth_enabled) == false);
+            // トークンが設定されている場合、ヘッダが無ければ拒否する
+            assert!(is_member(Some("secret-token"), None, api_key_auth_enabled) == false);
+        }
+    }
+}
@@ -0,0 +1,303 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use serde::{Serialize, Deserialize};
+use tokio::sync::Semaphore;
+
+use crate::gval;
+use crate::node_info;
+use crate::chord_util;
+use crate::chord_node;
+use crate::data_store;
+use crate::router;
+use crate::endpoints;
+use crate::config;
+use crate::namespace;
+
+type ArMu<T> = Arc<Mutex<T>>;
+
+// データの一括エクスポートとインポート
+//
+// ファイルの形式は1行に1データ (DataRecord) のJSONとする (JSON Lines)
+// DataStore は元のキーの文字列を保持していないため、エクスポートしたデータはキーのハッシュ値 (data_id) で表す.
+// インポートでは data_id の代わりに key を指定することもでき、その場合は global_put と同様にハッシュ値を求める
+// レプリカは書き込み時に作られるため、エクスポートするのはマスターデータ相当のもののみとする
+//
+// インポートでは、リングを一周して得た各ノードのIDから各データの担当ノードを求めて担当ノードごとに分け、
+// gval::IMPORT_BATCH_LEN 件ずつのバッチを並列に担当ノードへ送る. 担当ノードはバッチ内の各データを
+// 通常の書き込みと同様にレプリカを含めて書き込む. 担当ノードの判定が古い経路情報に基づいていても、
+// 書き込み先は担当ノードでのアドレス解決で決まるため、データの配置は正しく行われる
+// インポートはクラスタの構築やバックアップからの復元に用いるものとし、ネームスペースのクォータのチェックは行わない
+
+// ファイルの1行分
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct DataRecord {
+    // デフォルトのネームスペースは "default" (インポートでは空文字列も可)
+    #[serde(default)]
+    pub namespace: String,
+    // インポート時は key と data_id のどちらかを指定する. 両方指定された場合は data_id を用いる
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_id: Option<u32>,
+    pub val_str: String,
+    // 有効期限 (UNIXTIME, ミリ秒). 0 の場合は期限無し
+    #[serde(default)]
+    pub expire_at: i64,
+    // 0 の場合はネームスペースのレプリケーションファクタを用いる
+    #[serde(default)]
+    pub replication_factor: u32
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct ExportPageRequest {
+    // この値以上の data_id のデータを返す
+    pub start_data_id: u32,
+    pub limit: u32
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct ImportResult {
+    pub imported_count: u64,
+    // 有効期限切れのため書き込まなかったデータの数
+    pub skipped_count: u64,
+    pub failed_count: u64,
+    // 失敗の内容. gval::IMPORT_ERROR_REPORT_MAX 件まで
+    pub errors: Vec<String>
+}
+
+impl Default for ImportResult {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ImportResult {
+    pub fn new() -> ImportResult {
+        ImportResult { imported_count: 0, skipped_count: 0, failed_count: 0, errors: vec![] }
+    }
+
+    fn add_failure(&mut self, count: u64, message: String) {
+        self.failed_count += count;
+        if self.errors.len() < gval::IMPORT_ERROR_REPORT_MAX {
+            self.errors.push(message);
+        }
+    }
+
+    fn merge(&mut self, other: ImportResult) {
+        self.imported_count += other.imported_count;
+        self.skipped_count += other.skipped_count;
+        self.failed_count += other.failed_count;
+        for message in other.errors {
+            if self.errors.len() >= gval::IMPORT_ERROR_REPORT_MAX {
+                break;
+            }
+            self.errors.push(message);
+        }
+    }
+}
+
+// 自ノードが保持しているマスターデータ相当のもののうち、data_id が start_data_id 以上のものを data_id の順に limit 件まで返す
+// 削除済みのデータと有効期限切れのデータは含めない
+pub fn get_local_export_page(data_store: ArMu<data_store::DataStore>, req: ExportPageRequest) -> Vec<chord_util::DataIdAndValue> {
+    let data_store_ref = data_store.lock().unwrap();
+    let mut datas: Vec<chord_util::DataIdAndValue> = data_store_ref.get_all_datas().into_iter()
+        .filter(|iv| iv.replica_idx == 0 && iv.val_str != data_store::DELETED_ENTRY_MARKING_STR && iv.data_id >= req.start_data_id)
+        .cloned()
+        .collect();
+    datas.sort_by_key(|iv| iv.data_id);
+    datas.truncate(req.limit as usize);
+    return datas;
+}
+
+// リング上の全ノードのデータをエクスポートし、JSON Lines の文字列として返す
+// 一部のノードから読み出せなかった場合、結果は不完全となるためエラーとする
+pub async fn export_datas(self_node: ArMu<node_info::NodeInfo>) -> Result<String, chord_util::GeneralError> {
+    let addresses = match router::collect_ring_node_addresses(Arc::clone(&self_node)).await {
+        Err(err) => { return Err(err); }
+        Ok(addresses) => addresses
+    };
+
+    let mut exported: HashSet<(String, u32)> = HashSet::new();
+    let mut lines: Vec<String> = vec![];
+    for address in addresses {
+        let mut start_data_id: u32 = 0;
+        loop {
+            let req = ExportPageRequest { start_data_id: start_data_id, limit: gval::EXPORT_PAGE_LEN };
+            let page = match endpoints::rrpc_call__get_export_page(&address, req).await {
+                Err(err) => {
+                    chord_util::dprint(&("ERROR at export_datas,".to_string() + address.as_str() + "," + err.message.as_str()));
+                    return Err(chord_util::GeneralError::new("failed to export datas of node: ".to_string() + address.as_str(), err.err_code));
+                }
+                Ok(page) => page
+            };
+            let page_len = page.len();
+            for iv in page {
+                start_data_id = iv.data_id;
+                // ノードの参加などの途中で、同じデータを複数のノードが保持している場合がある
+                if exported.insert((iv.namespace.clone(), iv.data_id)) == false {
+                    continue;
+                }
+                let display_ns = if iv.namespace.len() == 0 { namespace::DEFAULT_NAMESPACE_DISPLAY_NAME.to_string() } else { iv.namespace.clone() };
+                let record = DataRecord { namespace: display_ns, key: None, data_id: Some(iv.data_id), val_str: iv.val_str, expire_at: iv.expire_at, replication_factor: iv.replication_factor };
+                match serde_json::to_string(&record) {
+                    Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_INTERNAL_CONTROL_FLOW_PROBLEM)); }
+                    Ok(line) => { lines.push(line); }
+                };
+            }
+            if page_len < gval::EXPORT_PAGE_LEN as usize || start_data_id >= gval::ID_MAX {
+                break;
+            }
+            start_data_id += 1;
+        }
+    }
+
+    let mut ret = lines.join("\n");
+    if lines.len() > 0 {
+        ret.push('\n');
+    }
+    return Ok(ret);
+}
+
+// JSON Lines の文字列をパースし、担当ノードごとに分けて並列に書き込む
+pub async fn import_datas(self_node: ArMu<node_info::NodeInfo>, body: String) -> Result<ImportResult, chord_util::GeneralError> {
+    let mut result = ImportResult::new();
+    let now_millis = chord_util::get_unixtime_in_millis();
+    let mut datas: Vec<chord_util::DataIdAndValue> = vec![];
+    for (line_idx, line) in body.lines().enumerate() {
+        if line.trim().len() == 0 {
+            continue;
+        }
+        let record = match serde_json::from_str::<DataRecord>(line) {
+            Err(err) => {
+                result.add_failure(1, format!("line {}: {}", line_idx + 1, err));
+                continue;
+            }
+            Ok(record) => record
+        };
+        let ns = namespace::resolve_request_namespace(Some(record.namespace));
+        let data_id = match (record.data_id, record.key) {
+            (Some(data_id), _) => data_id,
+            (None, Some(key)) => chord_util::hash_key_in_namespace(ns.as_str(), &key),
+            (None, None) => {
+                result.add_failure(1, format!("line {}: key or data_id is required", line_idx + 1));
+                continue;
+            }
+        };
+        if record.expire_at != 0 && record.expire_at <= now_millis {
+            result.skipped_count += 1;
+            continue;
+        }
+        let mut iv = chord_util::DataIdAndValue::new(data_id, record.val_str);
+        iv.namespace = ns;
+        iv.expire_at = record.expire_at;
+        iv.replication_factor = record.replication_factor;
+        datas.push(iv);
+    }
+
+    // 担当ノードごとに分ける
+    let ring_nodes = match collect_ring_node_ids(Arc::clone(&self_node)).await {
+        Err(err) => { return Err(err); }
+        Ok(ring_nodes) => ring_nodes
+    };
+    let mut groups: HashMap<String, Vec<chord_util::DataIdAndValue>> = HashMap::new();
+    for iv in datas {
+        let owner_idx = ring_nodes.iter().position(|(node_id, _)| *node_id >= iv.data_id).unwrap_or(0);
+        groups.entry(ring_nodes[owner_idx].1.clone()).or_default().push(iv);
+    }
+
+    let semaphore = Arc::new(Semaphore::new(gval::IMPORT_PARALLELISM));
+    let mut handles = vec![];
+    for (address, group_datas) in groups {
+        for batch in group_datas.chunks(gval::IMPORT_BATCH_LEN) {
+            let address = address.clone();
+            let batch = batch.to_vec();
+            let semaphore = Arc::clone(&semaphore);
+            handles.push(tokio::spawn(async move {
+                let _permit = semaphore.acquire().await;
+                let batch_len = batch.len() as u64;
+                return match endpoints::rrpc_call__import_batch(&address, batch).await {
+                    Err(err) => {
+                        let mut batch_result = ImportResult::new();
+                        batch_result.add_failure(batch_len, address + ": " + err.message.as_str());
+                        batch_result
+                    }
+                    Ok(batch_result) => batch_result
+                };
+            }));
+        }
+    }
+    for handle in handles {
+        match handle.await {
+            Err(err) => { result.add_failure(0, err.to_string()); }
+            Ok(batch_result) => { result.merge(batch_result); }
+        };
+    }
+
+    return Ok(result);
+}
+
+// リングを一周して、各ノード (仮想ノード) の (ID, アドレス) をIDの順に並べて返す
+async fn collect_ring_node_ids(self_node: ArMu<node_info::NodeInfo>) -> Result<Vec<(u32, String)>, chord_util::GeneralError> {
+    let addresses = match router::collect_ring_vnode_addresses(Arc::clone(&self_node)).await {
+        Err(err) => { return Err(err); }
+        Ok(addresses) => addresses
+    };
+    let mut ring_nodes: Vec<(u32, String)> = vec![];
+    for address in addresses {
+        match endpoints::rrpc_call__get_node_info(&address).await {
+            Err(err) => { return Err(err); }
+            Ok(ninfo) => { ring_nodes.push((ninfo.node_id, address)); }
+        };
+    }
+    ring_nodes.sort_by_key(|(node_id, _)| *node_id);
+    return Ok(ring_nodes);
+}
+
+// インポートのバッチ1つ分を、自ノードを起点にレプリカを含めて書き込む
+pub async fn import_local_batch(self_node: ArMu<node_info::NodeInfo>, datas: Vec<chord_util::DataIdAndValue>) -> ImportResult {
+    let mut result = ImportResult::new();
+    // ネームスペースごとのレプリケーションファクタ
+    let mut ns_replication_factors: HashMap<String, u32> = HashMap::new();
+    for iv in datas {
+        let replication_factor = if iv.replication_factor != 0 {
+            match config::validate_replication_factor(iv.replication_factor) {
+                Err(err) => {
+                    result.add_failure(1, format!("data_id {}: {}", iv.data_id, err.message));
+                    continue;
+                }
+                Ok(_) => iv.replication_factor
+            }
+        } else if iv.namespace == namespace::SYSTEM_NAMESPACE {
+            gval::SYSTEM_NAMESPACE_REPLICATION_FACTOR
+        } else {
+            match ns_replication_factors.get(&iv.namespace) {
+                Some(rf) => *rf,
+                None => match namespace::get_namespace_config(Arc::clone(&self_node), iv.namespace.as_str()).await {
+                    Err(err) => {
+                        result.add_failure(1, format!("data_id {}: {}", iv.data_id, err.message));
+                        continue;
+                    }
+                    Ok(ns_conf) => {
+                        ns_replication_factors.insert(iv.namespace.clone(), ns_conf.replication_factor);
+                        ns_conf.replication_factor
+                    }
+                }
+            }
+        };
+
+        match chord_node::put_replicas(Arc::clone(&self_node), iv.namespace.as_str(), iv.data_id, iv.val_str, iv.expire_at, replication_factor).await {
+            Err(err) => { result.add_failure(1, format!("data_id {}: {}", iv.data_id, err.message)); }
+            Ok((_, written_count)) => {
+                if written_count == 0 {
+                    result.add_failure(1, format!("data_id {}: no replica was written", iv.data_id));
+                } else {
+                    result.imported_count += 1;
+                }
+            }
+        };
+    }
+    return result;
+}
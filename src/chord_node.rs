@@ -11,29 +11,74 @@ use crate::stabilizer;
 use crate::router;
 use crate::data_store;
 use crate::endpoints;
+use crate::namespace;
+use crate::config;
+use crate::placement;
+use crate::location_cache;
+use crate::transfer;
 
 type ArMu<T> = Arc<Mutex<T>>;
 
-pub fn global_put(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::DataStore>, key_str: String, val_str: String) -> Result<bool, chord_util::GeneralError> {
-    let mut self_node_ref = self_node.lock().unwrap();
-    let self_node_deep_cloned = node_info::partial_clone_from_ref_strong(&self_node_ref);
-    drop(self_node_ref);
+// data_id のデータについて、マスターデータ相当のものと replication_factor - 1 個のレプリカを書き込む
+// 配置方法は設定 (config::ReplicationMode) に従い、いずれの方法でもコピーは互いに異なるノードに配置する
+// (リング上のノード数が replication_factor より少ない場合は、ノード数分のコピーのみとなる)
+// 戻り値は (マスターデータ相当のものが既に存在していたか (書き込めなかった場合は false), 書き込めたコピーの数)
+// マスターデータ相当のものの書き込み先が担当ノードでなかった (ERR_CODE_NOT_TANTOU) 場合や、担当範囲が確定していなかった
+// (ERR_CODE_PRED_IS_NONE) 場合は、gval::NOT_TANTOU_RETRY_MAX 回まで書き込みを再試行する (is_retryable_owner_err)
+// 1回目の再試行では、書き込み先が返した推定の担当ノード (owner_hint) を用いる (apply_owner_hint)
+pub async fn put_replicas(self_node: ArMu<node_info::NodeInfo>, namespace: &str, data_id: u32, val_str: String, expire_at: i64, replication_factor: u32) -> Result<(bool, u32), chord_util::GeneralError> {
+    let mut retry_count: u32 = 0;
+    // 再試行前の書き込みで書き込めたレプリカも残っているため、書き込めたコピーの数は各回のうち最大のものとする
+    let mut max_written_count: u32 = 0;
+    loop {
+        let ret = match config::get_replication_mode() {
+            config::ReplicationMode::SaltedId => put_replicas_salted_id(Arc::clone(&self_node), namespace, data_id, val_str.clone(), expire_at, replication_factor).await,
+            config::ReplicationMode::SuccessorList => put_replicas_successor_list(Arc::clone(&self_node), namespace, data_id, val_str.clone(), expire_at, replication_factor).await
+        };
+        let (is_exist_master, written_count, master_err) = match ret {
+            Err(err) => { return Err(err); }
+            Ok(ret) => ret
+        };
+        max_written_count = std::cmp::max(max_written_count, written_count);
+        let master_err = match master_err {
+            Some(err) if retry_count < gval::NOT_TANTOU_RETRY_MAX => err,
+            _ => { return Ok((is_exist_master, max_written_count)); }
+        };
+        retry_count += 1;
+        chord_util::dprint(&("put_replicas_retry,".to_string() + chord_util::gen_debug_str_of_data(data_id).as_str() + "," + retry_count.to_string().as_str()));
+        if retry_count == 1 && apply_owner_hint(data_id, &master_err).await {
+            continue;
+        }
+        // 担当ノードの変化が stabilize 処理で反映されるのを待つ
+        tokio::time::sleep(std::time::Duration::from_millis(gval::NOT_TANTOU_RETRY_INTERVAL_MILLIS)).await;
+    }
+}
+
+// 戻り値は put_replicas のものに、マスターデータ相当のものの書き込みが再試行の対象となるエラーで失敗した場合のそのエラーを加えたもの
+async fn put_replicas_salted_id(self_node: ArMu<node_info::NodeInfo>, namespace: &str, data_id: u32, val_str: String, expire_at: i64, replication_factor: u32) -> Result<(bool, u32, Option<chord_util::GeneralError>), chord_util::GeneralError> {
+    let self_node_deep_cloned;
+    {
+        let self_node_ref = self_node.lock().unwrap();
+        self_node_deep_cloned = node_info::partial_clone_from_ref_strong(&self_node_ref);
+    }
 
     // 更新に失敗するレプリカがあった場合、それはノードダウンであると（本当にそうか確実ではないが）前提をおいて、
     // 続くレプリカの更新は継続する
-    let data_id = chord_util::hash_str_to_int(&key_str);
-    for idx in 0..(gval::REPLICA_NUM + 1) {
-        let target_id = chord_util::overflow_check_and_conv(data_id as u64 + (gval::REPLICA_ID_DISTANCE as u64) * (idx as u64));
-        let replica_node = match endpoints::rrpc_call__find_successor(&self_node_deep_cloned, target_id){
-            Err(err) => {
-                self_node_ref = self_node.lock().unwrap();
-                node_info::handle_downed_node_info(&mut self_node_ref, &self_node_deep_cloned, &err);
-                drop(self_node_ref);
-                //return Err(err);
-                continue;
-            }
-            Ok(ninfo) => ninfo
+    let mut is_exist_master = false;
+    let mut master_err: Option<chord_util::GeneralError> = None;
+    let mut written_count: u32 = 0;
+    let mut placement = placement::ReplicaPlacement::new(replication_factor);
+    for idx in 0..replication_factor {
+        let target_id = chord_util::calc_replica_data_id(data_id, idx, replication_factor);
+        // 既に他のコピーを書き込んだノードが担当となった場合や、ゾーンの配置ポリシーを満たせない場合は、
+        // 後続のノードに書き込む
+        let replica_node = match resolve_distinct_replica_node(Arc::clone(&self_node), &self_node_deep_cloned, target_id, &placement).await {
+            Err(err) => { continue; }
+            // リング上のノードが全て使われている
+            Ok(None) => { break; }
+            Ok(Some(ninfo)) => ninfo
         };
+        placement.add(&replica_node.address_str, &replica_node.zone);
 
         // chord_util::dprint(&("global_put_1,".to_string() 
         //     + chord_util::gen_debug_str_of_node(&self_node_deep_cloned).as_str() + ","
@@ -43,9 +88,16 @@ pub fn global_put(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_st
         //     + idx.to_string().as_str()
         // ));        
 
-        let is_exist = match endpoints::rrpc_call__put(&node_info::gen_node_info_from_summary(&replica_node), target_id, val_str.clone()){
+        let put_arg = endpoints::Put::new(target_id, val_str.clone(), namespace.to_string(), expire_at, idx, replication_factor);
+        let is_exist = match endpoints::rrpc_call__put(&node_info::gen_node_info_from_summary(&replica_node), put_arg).await{
             Err(err) => {
-                self_node_ref = self_node.lock().unwrap();
+                if err.err_code == chord_util::ERR_CODE_NOT_TANTOU {
+                    location_cache::invalidate_id(target_id);
+                }
+                if idx == 0 && is_retryable_owner_err(&err) {
+                    master_err = Some(err.clone());
+                }
+                let mut self_node_ref = self_node.lock().unwrap();
                 node_info::handle_downed_node_info(&mut self_node_ref, &node_info::gen_node_info_from_summary(&replica_node), &err);
                 drop(self_node_ref);
                 continue;
@@ -53,6 +105,10 @@ pub fn global_put(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_st
             }
             Ok(is_exist) => is_exist
         };
+        written_count += 1;
+        if idx == 0 {
+            is_exist_master = is_exist;
+        }
 
         // chord_util::dprint(&("global_put_2,".to_string() 
         //     + chord_util::gen_debug_str_of_node(&self_node_deep_cloned).as_str() + ","
@@ -63,10 +119,404 @@ pub fn global_put(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_st
         // ));
     }
 
-    return Ok(true);
+    return Ok((is_exist_master, written_count, master_err));
+}
+
+// target_id を担当するノードを返す. ただし配置ポリシー (placement) に合わないノードであった場合は、
+// リングを右回りにたどって最初に見つかった合うノードを返す
+// 一周しても見つからなかった場合は、たどった中で最初に見つかった、まだコピーを配置していないノードを返し、
+// それも無い (リング上の全てのノードに配置済み) 場合は None を返す
+async fn resolve_distinct_replica_node(self_node: ArMu<node_info::NodeInfo>, self_node_deep_cloned: &node_info::NodeInfo, target_id: u32, placement: &placement::ReplicaPlacement) -> Result<Option<node_info::NodeInfoSummary>, chord_util::GeneralError> {
+    let mut lookup_id = target_id;
+    let mut first_node_id: Option<u32> = None;
+    let mut fallback_node: Option<node_info::NodeInfoSummary> = None;
+    for _ in 0..gval::REPLICA_PLACEMENT_WALK_MAX {
+        let ninfo = match router::find_successor_cached(self_node_deep_cloned, lookup_id).await {
+            Err(err) => {
+                let mut self_node_ref = self_node.lock().unwrap();
+                node_info::handle_downed_node_info(&mut self_node_ref, self_node_deep_cloned, &err);
+                return Err(err);
+            }
+            Ok(ninfo) => ninfo
+        };
+        match first_node_id {
+            None => { first_node_id = Some(ninfo.node_id); }
+            Some(node_id) => {
+                if node_id == ninfo.node_id {
+                    // 一周した
+                    break;
+                }
+            }
+        };
+        if placement.is_acceptable(&ninfo.address_str, &ninfo.zone) {
+            return Ok(Some(ninfo));
+        }
+        if fallback_node.is_none() && placement.contains_node(&ninfo.address_str) == false {
+            fallback_node = Some(ninfo.clone());
+        }
+        lookup_id = chord_util::overflow_check_and_conv(ninfo.node_id as u64 + 1);
+    }
+    return Ok(fallback_node);
 }
 
-pub fn put(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::DataStore>, key_id: u32, val_str: String) -> Result<bool, chord_util::GeneralError> {
+// successor_list モードで data_id のコピーを配置するノードを、担当ノードから順に最大 replication_factor 個返す
+// 担当ノードから successor_info_list を取得できなかった場合は、ダウンしているものとみなして
+// 担当を引き継ぐことになる後続のノードを担当ノードとして扱う
+async fn collect_successor_list_replica_nodes(self_node: ArMu<node_info::NodeInfo>, self_node_deep_cloned: &node_info::NodeInfo, data_id: u32, replication_factor: u32) -> Result<Vec<node_info::NodeInfo>, chord_util::GeneralError> {
+    let mut lookup_id = data_id;
+    for _ in 0..gval::REPLICA_NODES_LOOKUP_TRY_MAX {
+        let owner_summary = match router::find_successor_cached(self_node_deep_cloned, lookup_id).await {
+            Err(err) => {
+                let mut self_node_ref = self_node.lock().unwrap();
+                node_info::handle_downed_node_info(&mut self_node_ref, self_node_deep_cloned, &err);
+                return Err(err);
+            }
+            Ok(ninfo) => ninfo
+        };
+        let owner_node = node_info::gen_node_info_from_summary(&owner_summary);
+
+        let owner_succ_list = match endpoints::rrpc_call__get_successor_list(&owner_node.address_str).await {
+            Err(err) => {
+                {
+                    let mut self_node_ref = self_node.lock().unwrap();
+                    node_info::handle_downed_node_info(&mut self_node_ref, &owner_node, &err);
+                }
+                lookup_id = chord_util::overflow_check_and_conv(owner_node.node_id as u64 + 1);
+                continue;
+            }
+            Ok(succ_list) => succ_list
+        };
+
+        // 配置ポリシーに合うノードを successor_info_list の先頭から選び、足りない場合は
+        // ポリシーに合わなかったノードで補う
+        let mut placement = placement::ReplicaPlacement::new(replication_factor);
+        placement.add(&owner_node.address_str, &owner_node.zone);
+        let mut replica_nodes: Vec<node_info::NodeInfo> = vec![owner_node];
+        let mut skipped_nodes: Vec<node_info::NodeInfoEntry> = vec![];
+        for succ_info in &owner_succ_list {
+            if replica_nodes.len() >= replication_factor as usize {
+                break;
+            }
+            if placement.contains_node(&succ_info.address_str) || skipped_nodes.iter().any(|ninfo| ninfo.node_id == succ_info.node_id) {
+                continue;
+            }
+            if placement.is_acceptable(&succ_info.address_str, &succ_info.zone) == false {
+                skipped_nodes.push(succ_info.clone());
+                continue;
+            }
+            placement.add(&succ_info.address_str, &succ_info.zone);
+            replica_nodes.push(node_info::gen_node_info_from_entry(succ_info));
+        }
+        for skipped_node in skipped_nodes {
+            if replica_nodes.len() >= replication_factor as usize {
+                break;
+            }
+            if placement.contains_node(&skipped_node.address_str) {
+                continue;
+            }
+            placement.add(&skipped_node.address_str, &skipped_node.zone);
+            replica_nodes.push(node_info::gen_node_info_from_entry(&skipped_node));
+        }
+        return Ok(replica_nodes);
+    }
+
+    return Err(chord_util::GeneralError::new("owner node of the data is not found".to_string(), chord_util::ERR_CODE_APPROPRIATE_NODE_NOT_FOND));
+}
+
+// 戻り値は put_replicas_salted_id と同様
+async fn put_replicas_successor_list(self_node: ArMu<node_info::NodeInfo>, namespace: &str, data_id: u32, val_str: String, expire_at: i64, replication_factor: u32) -> Result<(bool, u32, Option<chord_util::GeneralError>), chord_util::GeneralError> {
+    let self_node_deep_cloned;
+    {
+        let self_node_ref = self_node.lock().unwrap();
+        self_node_deep_cloned = node_info::partial_clone_from_ref_strong(&self_node_ref);
+    }
+
+    let replica_nodes = match collect_successor_list_replica_nodes(Arc::clone(&self_node), &self_node_deep_cloned, data_id, replication_factor).await {
+        Err(err) => { return Err(err); }
+        Ok(nodes) => nodes
+    };
+
+    // 更新に失敗したノードがあっても続くノードへの書き込みは継続する (put_replicas_salted_id と同様)
+    let mut is_exist_master = false;
+    let mut master_err: Option<chord_util::GeneralError> = None;
+    let mut written_count: u32 = 0;
+    for (idx, replica_node) in replica_nodes.iter().enumerate() {
+        let put_arg = endpoints::Put::new(data_id, val_str.clone(), namespace.to_string(), expire_at, idx as u32, replication_factor);
+        let is_exist = match endpoints::rrpc_call__put(replica_node, put_arg).await {
+            Err(err) => {
+                // 担当範囲のチェックはマスターデータ相当のものの書き込み先でのみ行われる
+                if err.err_code == chord_util::ERR_CODE_NOT_TANTOU && idx == 0 {
+                    location_cache::invalidate_id(data_id);
+                }
+                if idx == 0 && is_retryable_owner_err(&err) {
+                    master_err = Some(err.clone());
+                }
+                let mut self_node_ref = self_node.lock().unwrap();
+                node_info::handle_downed_node_info(&mut self_node_ref, replica_node, &err);
+                continue;
+            }
+            Ok(is_exist) => is_exist
+        };
+        written_count += 1;
+        if idx == 0 {
+            is_exist_master = is_exist;
+        }
+    }
+
+    return Ok((is_exist_master, written_count, master_err));
+}
+
+// ダウンしたノードの担当範囲 [range_from, range_end) を引き継いだ際に、その範囲に置かれていたコピーを書き直し、
+// 各データのコピーの数をレプリケーションファクタまで回復する. 書き直したデータの数を返す
+// 書き込むのはコピーが失われた位置のみで、残っているコピーは上書きしない (put_missing_replicas)
+// successor_list モードでは、引き継いだ範囲のデータのコピーは自身が保持しているため全て回復できる
+// salted_id モードでは、自身が保持しているコピーのうち、他のコピーの位置が範囲内にあるものに限られる
+pub async fn rereplicate_range(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::DataStore>, range_from: u32, range_end: u32) -> u32 {
+    let is_salted_id = config::get_replication_mode() == config::ReplicationMode::SaltedId;
+    let is_in_range = |id: u32| id == range_from || chord_util::exist_between_two_nodes_right_mawari(range_from, range_end, id);
+
+    // 書き直すデータ. data_id はマスターデータ相当のもののID
+    let mut targets: Vec<chord_util::DataIdAndValue> = vec![];
+    {
+        let data_store_ref = data_store.lock().unwrap();
+        for iv in data_store_ref.get_all_datas() {
+            let (master_data_id, is_lost) = if is_salted_id {
+                // レプリケーションファクタが不明なものは他のコピーの位置が分からない
+                if iv.replication_factor == 0 {
+                    continue;
+                }
+                let master_data_id = chord_util::calc_master_data_id(iv.data_id, iv.replica_idx, iv.replication_factor);
+                let is_lost = (0..iv.replication_factor).any(|idx| idx != iv.replica_idx && is_in_range(chord_util::calc_replica_data_id(master_data_id, idx, iv.replication_factor)));
+                (master_data_id, is_lost)
+            } else {
+                (iv.data_id, is_in_range(iv.data_id))
+            };
+            if is_lost == false || targets.iter().any(|target| target.data_id == master_data_id && target.namespace == iv.namespace) {
+                continue;
+            }
+            let mut target = iv.clone();
+            target.data_id = master_data_id;
+            targets.push(target);
+        }
+    }
+
+    let mut rewritten_count: u32 = 0;
+    for target in targets {
+        let replication_factor = if target.replication_factor != 0 {
+            target.replication_factor
+        } else {
+            match namespace::get_namespace_config(Arc::clone(&self_node), target.namespace.as_str()).await {
+                Err(err) => { continue; }
+                Ok(ns_conf) => ns_conf.replication_factor
+            }
+        };
+        match put_missing_replicas(Arc::clone(&self_node), &target, replication_factor).await {
+            Err(err) => {
+                chord_util::dprint(&("rereplicate_range_failed,".to_string() + chord_util::gen_debug_str_of_data(target.data_id).as_str() + "," + err.message.as_str()));
+            }
+            Ok(0) => {}
+            Ok(_) => { rewritten_count += 1; }
+        };
+    }
+    chord_util::dprint(&("rereplicate_range,".to_string() + chord_util::gen_debug_str_of_data(range_from).as_str() + ","
+        + chord_util::gen_debug_str_of_data(range_end).as_str() + "," + rewritten_count.to_string().as_str()));
+    return rewritten_count;
+}
+
+// コピーを配置する位置ごとに (何番目のコピーか, 配置先のノード, 配置先でのID) を返す
+// 配置先のノードが分からなかった位置は含めない
+async fn collect_replica_positions(self_node: ArMu<node_info::NodeInfo>, data_id: u32, replication_factor: u32) -> Result<Vec<(u32, node_info::NodeInfo, u32)>, chord_util::GeneralError> {
+    let self_node_deep_cloned;
+    {
+        let self_node_ref = self_node.lock().unwrap();
+        self_node_deep_cloned = node_info::partial_clone_from_ref_strong(&self_node_ref);
+    }
+
+    let mut positions: Vec<(u32, node_info::NodeInfo, u32)> = vec![];
+    match config::get_replication_mode() {
+        config::ReplicationMode::SaltedId => {
+            let mut placement = placement::ReplicaPlacement::new(replication_factor);
+            for idx in 0..replication_factor {
+                let target_id = chord_util::calc_replica_data_id(data_id, idx, replication_factor);
+                let replica_node = match resolve_distinct_replica_node(Arc::clone(&self_node), &self_node_deep_cloned, target_id, &placement).await {
+                    Err(err) => { continue; }
+                    Ok(None) => { break; }
+                    Ok(Some(ninfo)) => ninfo
+                };
+                placement.add(&replica_node.address_str, &replica_node.zone);
+                positions.push((idx, node_info::gen_node_info_from_summary(&replica_node), target_id));
+            }
+        }
+        config::ReplicationMode::SuccessorList => {
+            let replica_nodes = match collect_successor_list_replica_nodes(Arc::clone(&self_node), &self_node_deep_cloned, data_id, replication_factor).await {
+                Err(err) => { return Err(err); }
+                Ok(nodes) => nodes
+            };
+            for (idx, replica_node) in replica_nodes.into_iter().enumerate() {
+                positions.push((idx as u32, replica_node, data_id));
+            }
+        }
+    };
+    return Ok(positions);
+}
+
+// local_copy (data_id はマスターデータ相当のもののID) のデータについて、各位置のコピーを読み出し、
+// コピーが無かった位置にのみ書き込む. 書き込んだコピーの数を返す
+// 書き込む内容は、残っていたコピーのうち最も前の位置 (マスターデータ相当のものに近い方) のものとし、
+// 1つも残っていなかった場合は local_copy とする. 読み出しに失敗した位置は、コピーの有無が分からないため書き込まない
+async fn put_missing_replicas(self_node: ArMu<node_info::NodeInfo>, local_copy: &chord_util::DataIdAndValue, replication_factor: u32) -> Result<u32, chord_util::GeneralError> {
+    let positions = match collect_replica_positions(Arc::clone(&self_node), local_copy.data_id, replication_factor).await {
+        Err(err) => { return Err(err); }
+        Ok(positions) => positions
+    };
+
+    let is_successor_list = config::get_replication_mode() == config::ReplicationMode::SuccessorList;
+    let mut source: Option<chord_util::DataIdAndValue> = None;
+    let mut missing_positions: Vec<(u32, node_info::NodeInfo, u32)> = vec![];
+    for (idx, replica_node, target_id) in positions {
+        match endpoints::rrpc_call__get(&replica_node, target_id).await {
+            Ok(data_iv) => {
+                if source.is_none() {
+                    source = Some(data_iv);
+                }
+            }
+            // 担当ノードでないノードに置かれるレプリカ (successor_list モード) が無い場合は ERR_CODE_NOT_TANTOU となる
+            Err(err) if err.err_code == chord_util::ERR_CODE_DATA_TO_GET_NOT_FOUND
+                || (err.err_code == chord_util::ERR_CODE_NOT_TANTOU && is_successor_list && idx > 0) => {
+                missing_positions.push((idx, replica_node, target_id));
+            }
+            Err(err) => {
+                let mut self_node_ref = self_node.lock().unwrap();
+                node_info::handle_downed_node_info(&mut self_node_ref, &replica_node, &err);
+            }
+        };
+    }
+
+    let source = source.unwrap_or_else(|| local_copy.clone());
+    let mut written_count: u32 = 0;
+    for (idx, replica_node, target_id) in missing_positions {
+        let put_arg = endpoints::Put::new(target_id, source.val_str.clone(), local_copy.namespace.clone(), source.expire_at, idx, replication_factor);
+        match endpoints::rrpc_call__put(&replica_node, put_arg).await {
+            Err(err) => {
+                let mut self_node_ref = self_node.lock().unwrap();
+                node_info::handle_downed_node_info(&mut self_node_ref, &replica_node, &err);
+            }
+            Ok(_) => { written_count += 1; }
+        };
+    }
+    return Ok(written_count);
+}
+
+// 書き込みごとに指定されたレプリケーションファクタを検証して返す. 指定が無い場合はネームスペースの設定のもの
+fn resolve_replication_factor(ns_conf: &namespace::NamespaceConfig, replication_factor: Option<u32>) -> Result<u32, chord_util::GeneralError> {
+    return match replication_factor {
+        None => Ok(ns_conf.replication_factor),
+        Some(rf) => match config::validate_replication_factor(rf) {
+            Err(err) => Err(err),
+            Ok(_) => Ok(rf)
+        }
+    };
+}
+
+// ttl_sec が None の場合はネームスペースのデフォルトのTTLが用いられる. Some(0) の場合は期限無しとなる
+// replication_factor が None の場合はネームスペースのレプリケーションファクタが用いられる
+// (ネームスペースと異なる値を指定して書き込んだデータは、読み出しと削除の際にも同じ値を指定する必要がある)
+pub async fn global_put(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::DataStore>, namespace: String, key_str: String, val_str: String, ttl_sec: Option<u64>, replication_factor: Option<u32>) -> Result<chord_util::PutResult, chord_util::GeneralError> {
+    let ns_conf = match namespace::get_namespace_config(Arc::clone(&self_node), namespace.as_str()).await {
+        Err(err) => { return Err(err); }
+        Ok(conf) => conf
+    };
+    let replication_factor = match resolve_replication_factor(&ns_conf, replication_factor) {
+        Err(err) => { return Err(err); }
+        Ok(rf) => rf
+    };
+
+    let expire_at = match ns_conf.calc_expire_at(ttl_sec) {
+        Err(err) => { return Err(err); }
+        Ok(expire_at) => expire_at
+    };
+
+    let data_id = chord_util::hash_key_in_namespace(namespace.as_str(), &key_str);
+    let val_len = val_str.len() as i64;
+    if ns_conf.has_quota() {
+        match namespace::check_quota(Arc::clone(&self_node), &ns_conf, data_id, val_len as u64).await {
+            Err(err) => { return Err(err); }
+            Ok(_) => {}
+        };
+    }
+
+    let (is_exist, written_count) = match put_replicas(Arc::clone(&self_node), namespace.as_str(), data_id, val_str, expire_at, replication_factor).await {
+        Err(err) => { return Err(err); }
+        Ok(ret) => ret
+    };
+    if written_count == 0 {
+        return Err(chord_util::GeneralError::new("no replica was written".to_string(), chord_util::ERR_CODE_NO_REPLICA_WRITTEN));
+    }
+    // 上書きの場合の値のサイズの変化は分からないため、新規のキーの場合のみ反映する
+    if is_exist == false {
+        namespace::add_usage_delta(namespace.as_str(), 1, val_len);
+    }
+
+    return Ok(chord_util::PutResult::new(written_count, replication_factor));
+}
+
+// Chordネットワークを右回りにたどった時に、データの id が predecessor の node_id から
+// 自身の node_id の間に位置する場合、そのデータは自身の担当
+fn is_tantou(self_node_ni: &node_info::NodeInfo, id: u32) -> bool {
+    if self_node_ni.predecessor_info.len() == 0 {
+        return false;
+    }
+    return id == self_node_ni.node_id
+        || chord_util::exist_between_two_nodes_right_mawari(self_node_ni.predecessor_info[0].node_id, self_node_ni.node_id, id);
+}
+
+// 担当ノードへの書き込みや問い合わせで、再試行の対象となるエラーか
+// 担当ノードでなかった場合と、ノードの故障の直後で predecessor が無く担当範囲が確定していなかった場合が該当する
+fn is_retryable_owner_err(err: &chord_util::GeneralError) -> bool {
+    return err.err_code == chord_util::ERR_CODE_NOT_TANTOU || err.err_code == chord_util::ERR_CODE_PRED_IS_NONE;
+}
+
+// 担当ノードでなかったノードが返した推定の担当ノード (owner_hint) が data_id を担当していることを確認し、
+// location_cache に記録する. 記録できた場合は true を返し、呼び出し元は待たずに再試行する
+async fn apply_owner_hint(data_id: u32, err: &chord_util::GeneralError) -> bool {
+    let hint_address = match &err.owner_hint {
+        None => { return false; }
+        Some(address) => address
+    };
+    let hinted_node = match endpoints::rrpc_call__get_node_info(hint_address).await {
+        Err(_err) => { return false; }
+        Ok(ninfo) => ninfo
+    };
+    let is_owner = match hinted_node.predecessor_info.first() {
+        None => false,
+        Some(pred_info) => data_id == hinted_node.node_id || chord_util::exist_between_two_nodes_right_mawari(pred_info.node_id, hinted_node.node_id, data_id)
+    };
+    if is_owner == false || hinted_node.successor_info_list.len() == 0 {
+        return false;
+    }
+    location_cache::store(data_id, &node_info::gen_summary_node_info(&hinted_node));
+    return true;
+}
+
+// 担当範囲外の id について、担当ノードと推測されるノードのアドレスを返す
+// 直近に範囲を渡したノード、successor_info_list のうち担当範囲が id を含むノード、predecessor の順に用いる
+fn guess_owner(self_node_ni: &node_info::NodeInfo, id: u32) -> Option<String> {
+    match transfer::find_recent_handover(id) {
+        Some(address) => { return Some(address); }
+        None => {}
+    };
+    let mut prev_id = self_node_ni.node_id;
+    for succ_info in &self_node_ni.successor_info_list {
+        if id == succ_info.node_id || chord_util::exist_between_two_nodes_right_mawari(prev_id, succ_info.node_id, id) {
+            return Some(succ_info.address_str.clone());
+        }
+        prev_id = succ_info.node_id;
+    }
+    return self_node_ni.predecessor_info.first().map(|pred_info| pred_info.address_str.clone());
+}
+
+pub fn put(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::DataStore>, iv_entry: chord_util::DataIdAndValue) -> Result<bool, chord_util::GeneralError> {
+    let key_id = iv_entry.data_id;
     let self_node_ref = self_node.lock().unwrap();
     let self_node_deep_cloned = node_info::partial_clone_from_ref_strong(&self_node_ref);
     drop(self_node_ref);
@@ -82,6 +532,13 @@ pub fn put(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::Da
     // リクエストを受けるという実装も可能だが、stabilize処理で predecessor が生きて
     // いるノードとなるまで下手にデータを持たない方が、データ配置の整合性を壊すリスクが
     // 減りそうな気がするので、そうする
+    // レプリカは担当ノード以外に配置されることがある (successor_list モードの場合や、コピーが同じノードに
+    // 重ならないよう後続のノードに配置した場合) ため、担当範囲のチェックは行わない
+    if iv_entry.replica_idx != 0 {
+        let mut data_store_ref = data_store.lock().unwrap();
+        return Ok(data_store_ref.store_one_iv(iv_entry));
+    }
+
     if self_node_deep_cloned.predecessor_info.len() == 0 {
         return Err(chord_util::GeneralError::new("predecessor is None".to_string(), chord_util::ERR_CODE_PRED_IS_NONE));
     }
@@ -94,13 +551,10 @@ pub fn put(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::Da
     // );
 
     
-    // Chordネットワークを右回りにたどった時に、データの id (key_id) が predecessor の node_id から
-    // 自身の node_id の間に位置する場合、そのデータは自身の担当だが、そうではない場合
-    if chord_util::exist_between_two_nodes_right_mawari(
-        self_node_deep_cloned.predecessor_info[0].node_id,
-        self_node_deep_cloned.node_id, 
-        key_id) == false {
-            return Err(chord_util::GeneralError::new("passed data is out of my tantou range".to_string(), chord_util::ERR_CODE_NOT_TANTOU));
+    // 担当範囲外の場合は、担当ノードと推測されるノードを添えてエラーを返す
+    // (書き込みは受け渡し中の範囲であっても受け付けない. 受け手に渡し終えたデータが古い値で上書きされなくなるため)
+    if is_tantou(&self_node_deep_cloned, key_id) == false {
+        return Err(chord_util::GeneralError::new_not_tantou("passed data is out of my tantou range".to_string(), guess_owner(&self_node_deep_cloned, key_id)));
     }
 
 
@@ -112,7 +566,7 @@ pub fn put(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::Da
     // );
 
     let mut data_store_ref = data_store.lock().unwrap();
-    let ret = data_store_ref.store_one_iv(key_id, val_str);
+    let ret = data_store_ref.store_one_iv(iv_entry);
     drop(data_store_ref);
 
     // chord_util::dprint(
@@ -126,28 +580,97 @@ pub fn put(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::Da
     return Ok(ret);
 }
 
-// 得られた value の文字列を返す
+// data_id のデータを、マスターデータ相当のものから順に replication_factor 個のコピーのいずれかから取得できるまで問い合わせる
 // データの取得に失敗した場合は ERR_CODE_QUERIED_DATA_NOT_FOUND をエラーとして返す
 // 取得対象のデータが削除済みのデータであった場合は DELETED_ENTRY_MARKING_STR が正常値として返る
-pub fn global_get(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::DataStore>, key_str: String) -> Result<chord_util::DataIdAndValue, chord_util::GeneralError> {
+// いずれのコピーも取得できず、マスターデータ相当のものの問い合わせ先が担当ノードでなかった場合や担当範囲が確定していなかった場合は、
+// put_replicas と同様に再試行する
+pub async fn get_replicas(self_node: ArMu<node_info::NodeInfo>, data_id: u32, replication_factor: u32) -> Result<chord_util::DataIdAndValue, chord_util::GeneralError> {
+    let mut retry_count: u32 = 0;
+    loop {
+        let ret = match config::get_replication_mode() {
+            config::ReplicationMode::SaltedId => get_replicas_salted_id(Arc::clone(&self_node), data_id, replication_factor).await,
+            config::ReplicationMode::SuccessorList => get_replicas_successor_list(Arc::clone(&self_node), data_id, replication_factor).await
+        };
+        match ret {
+            Err(err) if is_retryable_owner_err(&err) && retry_count < gval::NOT_TANTOU_RETRY_MAX => {
+                retry_count += 1;
+                chord_util::dprint(&("get_replicas_retry,".to_string() + chord_util::gen_debug_str_of_data(data_id).as_str() + "," + retry_count.to_string().as_str()));
+                if retry_count == 1 && apply_owner_hint(data_id, &err).await {
+                    continue;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(gval::NOT_TANTOU_RETRY_INTERVAL_MILLIS)).await;
+            }
+            _ => { return ret; }
+        };
+    }
+}
 
-    let mut self_node_ref = self_node.lock().unwrap();
-    let self_node_deep_cloned = node_info::partial_clone_from_ref_strong(&self_node_ref);
-    drop(self_node_ref);
+// いずれのコピーも取得できなかった場合のエラー. マスターデータ相当のものの問い合わせが再試行の対象となるエラーで失敗した場合は
+// そのエラーを返し、get_replicas での再試行の対象とする
+fn gen_get_replicas_err(master_err: Option<chord_util::GeneralError>) -> chord_util::GeneralError {
+    return match master_err {
+        Some(err) if is_retryable_owner_err(&err) => err,
+        _ => chord_util::GeneralError::new("QUERIED DATA NOT FOUND".to_string(), chord_util::ERR_CODE_QUERIED_DATA_NOT_FOUND)
+    };
+}
 
-    let data_id = chord_util::hash_str_to_int(&key_str);
-    for idx in 0..(gval::REPLICA_NUM + 1) {
-        let target_id = chord_util::overflow_check_and_conv(data_id as u64 + (gval::REPLICA_ID_DISTANCE as u64) * (idx as u64));
-        let replica_node = match endpoints::rrpc_call__find_successor(&self_node_deep_cloned, target_id){
-            Err(err) => {
-                self_node_ref = self_node.lock().unwrap();
-                node_info::handle_downed_node_info(&mut self_node_ref, &self_node_deep_cloned, &err);
-                drop(self_node_ref);
-                continue;
-                //return Err(err);
-            }
-            Ok(ninfo) => ninfo
+// salted_id モードでは、マスターデータ相当のもの以外のコピーの位置は書き込み時のレプリケーションファクタで決まる
+// マスターデータ相当のものの位置はレプリケーションファクタによらず同じため、まずそれを問い合わせ、取得できれば
+// (コピーには書き込み時の replication_factor が保持されている) そのまま返す. 取得できなかった場合は replication_factor での位置のレプリカに問い合わせ、
+// マスターデータ相当のものの問い合わせ先がデータを持たないことを確認できなかった (ダウンしていた等) 場合は、
+// 書き込み時のレプリケーションファクタが異なる場合に備えて、他のレプリケーションファクタでの位置のレプリカにも問い合わせる
+async fn get_replicas_salted_id(self_node: ArMu<node_info::NodeInfo>, data_id: u32, replication_factor: u32) -> Result<chord_util::DataIdAndValue, chord_util::GeneralError> {
+
+    let self_node_deep_cloned;
+    {
+        let self_node_ref = self_node.lock().unwrap();
+        self_node_deep_cloned = node_info::partial_clone_from_ref_strong(&self_node_ref);
+    }
+
+    let mut queried_ids: Vec<u32> = vec![];
+    let mut master_err: Option<chord_util::GeneralError> = None;
+    match get_salted_id_copies(Arc::clone(&self_node), &self_node_deep_cloned, data_id, 1, &mut queried_ids, &mut master_err).await {
+        Some(data_iv) => { return Ok(data_iv); }
+        None => {}
+    };
+
+    let is_master_absent = match &master_err {
+        Some(err) => err.err_code == chord_util::ERR_CODE_DATA_TO_GET_NOT_FOUND,
+        None => false
+    };
+    let mut rf_candidates: Vec<u32> = vec![replication_factor];
+    if is_master_absent == false {
+        rf_candidates.extend((2..=config::get_max_replication_factor()).rev().filter(|rf| *rf != replication_factor));
+    }
+    for rf in rf_candidates {
+        match get_salted_id_copies(Arc::clone(&self_node), &self_node_deep_cloned, data_id, rf, &mut queried_ids, &mut master_err).await {
+            Some(data_iv) => { return Ok(data_iv); }
+            None => {}
         };
+    }
+
+    return Err(gen_get_replicas_err(master_err));
+}
+
+// replication_factor で書き込まれた場合の位置のコピーに、マスターデータ相当のものから順に問い合わせる
+// queried_ids に含まれる位置は問い合わせ済みとして飛ばし (配置先のノードの決定には含める)、問い合わせた位置は queried_ids に追加する
+// マスターデータ相当のものの問い合わせに失敗した場合は、そのエラーを master_err に設定する
+async fn get_salted_id_copies(self_node: ArMu<node_info::NodeInfo>, self_node_deep_cloned: &node_info::NodeInfo, data_id: u32, replication_factor: u32, queried_ids: &mut Vec<u32>, master_err: &mut Option<chord_util::GeneralError>) -> Option<chord_util::DataIdAndValue> {
+    let mut placement = placement::ReplicaPlacement::new(replication_factor);
+    for idx in 0..replication_factor {
+        let target_id = chord_util::calc_replica_data_id(data_id, idx, replication_factor);
+        // 書き込み時と同じく、配置ポリシーに合わないノードは飛ばして後続のノードに問い合わせる
+        let replica_node = match resolve_distinct_replica_node(Arc::clone(&self_node), self_node_deep_cloned, target_id, &placement).await {
+            Err(_err) => { continue; }
+            Ok(None) => { break; }
+            Ok(Some(ninfo)) => ninfo
+        };
+        placement.add(&replica_node.address_str, &replica_node.zone);
+        if queried_ids.contains(&target_id) {
+            continue;
+        }
+        queried_ids.push(target_id);
 
         // chord_util::dprint(&("global_get_1,".to_string() 
         //     + chord_util::gen_debug_str_of_node(&self_node_deep_cloned).as_str() + ","
@@ -172,9 +695,15 @@ pub fn global_get(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_st
         return False
 */
 
-        let data_iv = match endpoints::rrpc_call__get(&node_info::gen_node_info_from_summary(&replica_node), target_id){
+        match endpoints::rrpc_call__get(&node_info::gen_node_info_from_summary(&replica_node), target_id).await{
             Err(err) => {
-                self_node_ref = self_node.lock().unwrap();
+                if err.err_code == chord_util::ERR_CODE_NOT_TANTOU {
+                    location_cache::invalidate_id(target_id);
+                }
+                if idx == 0 {
+                    *master_err = Some(err.clone());
+                }
+                let mut self_node_ref = self_node.lock().unwrap();
                 node_info::handle_downed_node_info(&mut self_node_ref, &node_info::gen_node_info_from_summary(&replica_node), &err);
                 drop(self_node_ref);
                 continue;
@@ -187,12 +716,62 @@ pub fn global_get(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_st
                     // + chord_util::gen_debug_str_of_data(data_id).as_str() + ","
                     // + chord_util::gen_debug_str_of_data(target_id).as_str() + ","
                     // + idx.to_string().as_str()));
-                return Ok(data_iv); 
+                return Some(data_iv); 
             }
         };
     }
 
-    return Err(chord_util::GeneralError::new("QUERIED DATA NOT FOUND".to_string(), chord_util::ERR_CODE_QUERIED_DATA_NOT_FOUND));
+    return None;
+}
+
+async fn get_replicas_successor_list(self_node: ArMu<node_info::NodeInfo>, data_id: u32, replication_factor: u32) -> Result<chord_util::DataIdAndValue, chord_util::GeneralError> {
+    let self_node_deep_cloned;
+    {
+        let self_node_ref = self_node.lock().unwrap();
+        self_node_deep_cloned = node_info::partial_clone_from_ref_strong(&self_node_ref);
+    }
+
+    let replica_nodes = match collect_successor_list_replica_nodes(Arc::clone(&self_node), &self_node_deep_cloned, data_id, replication_factor).await {
+        Err(err) => { return Err(err); }
+        Ok(nodes) => nodes
+    };
+
+    let mut master_err: Option<chord_util::GeneralError> = None;
+    for (idx, replica_node) in replica_nodes.iter().enumerate() {
+        match endpoints::rrpc_call__get(replica_node, data_id).await {
+            Err(err) => {
+                // レプリカの配置先は担当ノードではないため、キャッシュの無効化と再試行はマスターデータ相当のものの問い合わせ先についてのみ行う
+                if err.err_code == chord_util::ERR_CODE_NOT_TANTOU && idx == 0 {
+                    location_cache::invalidate_id(data_id);
+                }
+                if idx == 0 {
+                    master_err = Some(err.clone());
+                }
+                let mut self_node_ref = self_node.lock().unwrap();
+                node_info::handle_downed_node_info(&mut self_node_ref, replica_node, &err);
+                continue;
+            }
+            Ok(data_iv) => { return Ok(data_iv); }
+        };
+    }
+
+    return Err(gen_get_replicas_err(master_err));
+}
+
+// 得られた value の文字列を返す
+// replication_factor には書き込み時に指定したものを渡す. None の場合はネームスペースの設定のものとみなす
+pub async fn global_get(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::DataStore>, namespace: String, key_str: String, replication_factor: Option<u32>) -> Result<chord_util::DataIdAndValue, chord_util::GeneralError> {
+    let ns_conf = match namespace::get_namespace_config(Arc::clone(&self_node), namespace.as_str()).await {
+        Err(err) => { return Err(err); }
+        Ok(conf) => conf
+    };
+    let replication_factor = match resolve_replication_factor(&ns_conf, replication_factor) {
+        Err(err) => { return Err(err); }
+        Ok(rf) => rf
+    };
+
+    let data_id = chord_util::hash_key_in_namespace(namespace.as_str(), &key_str);
+    return get_replicas(self_node, data_id, replication_factor).await;
 }
 
 pub fn get(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::DataStore>, key_id: u32) -> Result<chord_util::DataIdAndValue, chord_util::GeneralError> {
@@ -221,17 +800,6 @@ pub fn get(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::Da
         + chord_util::gen_debug_str_of_data(key_id).as_str())
     );
 
-/*    
-    // Chordネットワークを右回りにたどった時に、データの id (key_id) が predecessor の node_id から
-    // 自身の node_id の間に位置する場合、そのデータは自身の担当だが、そうではない場合
-    if chord_util::exist_between_two_nodes_right_mawari(
-        self_node_deep_cloned.predecessor_info[0].node_id,
-        self_node_deep_cloned.node_id, 
-        key_id) == false {
-            return Err(chord_util::GeneralError::new("passed data is out of my tantou range".to_string(), chord_util::ERR_CODE_NOT_TANTOU));
-    }
-*/
-
     chord_util::dprint(
         &("get_3,".to_string()
         + chord_util::gen_debug_str_of_node(&self_node_deep_cloned).as_str() + ","
@@ -241,10 +809,20 @@ pub fn get(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::Da
     let data_store_ref = data_store.lock().unwrap();
     let ret_val = match data_store_ref.get(key_id){
         Err(err) => {
+            // 担当範囲外のデータは、担当ノードが別にある可能性があるためその旨を返す
+            if is_tantou(&self_node_deep_cloned, key_id) == false {
+                return Err(chord_util::GeneralError::new_not_tantou("queried data is out of my tantou range".to_string(), guess_owner(&self_node_deep_cloned, key_id)));
+            }
             return Err(err);
         }
         Ok(data_iv) => {
-            if data_iv.val_str == data_store::DELETED_ENTRY_MARKING_STR.to_string() {
+            // レプリカは担当範囲に関わらず返す. マスターデータ相当のものは、担当範囲内のものか、
+            // 他のノードに渡してから gval::OWNERSHIP_GRACE_MILLIS 以内の範囲のもの (渡し終えるまでの間に読み出しが
+            // 失敗しないようにするため) のみ返す
+            if data_iv.replica_idx == 0 && is_tantou(&self_node_deep_cloned, key_id) == false && transfer::find_recent_handover(key_id).is_none() {
+                return Err(chord_util::GeneralError::new_not_tantou("queried data is out of my tantou range".to_string(), guess_owner(&self_node_deep_cloned, key_id)));
+            }
+            if data_iv.val_str == data_store::DELETED_ENTRY_MARKING_STR {
                 return Err(chord_util::GeneralError::new(data_store::DELETED_ENTRY_MARKING_STR.to_string(), chord_util::ERR_CODE_DATA_TO_GET_IS_DELETED));
             }
             data_iv
@@ -263,14 +841,21 @@ pub fn get(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::Da
     return Ok(ret_val);
 }
 
-pub fn global_delete(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::DataStore>, key_str: String) -> Result<bool, chord_util::GeneralError> {
-    match global_get(Arc::clone(&self_node), Arc::clone(&data_store), key_str.clone()){
+// replication_factor は global_get と同様
+pub async fn global_delete(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::DataStore>, namespace: String, key_str: String, replication_factor: Option<u32>) -> Result<bool, chord_util::GeneralError> {
+    match global_get(Arc::clone(&self_node), Arc::clone(&data_store), namespace.clone(), key_str.clone(), replication_factor).await{
         Err(err) => { return Err(err); }
         Ok(data_iv) => {
-            match global_put(Arc::clone(&self_node), Arc::clone(&data_store), key_str, data_store::DELETED_ENTRY_MARKING_STR.to_string()){
+            // 削除済みのマークは、データが書き込まれた際と同じ位置のコピーに書き込む
+            let written_rf = if data_iv.replication_factor != 0 { Some(data_iv.replication_factor) } else { replication_factor };
+            match global_put(Arc::clone(&self_node), Arc::clone(&data_store), namespace.clone(), key_str, data_store::DELETED_ENTRY_MARKING_STR.to_string(), None, written_rf).await{
                 Err(err) => { return Err(err); }
-                Ok(is_exist) => {
-                    return Ok(is_exist);
+                Ok(_) => {
+                    if data_iv.val_str != data_store::DELETED_ENTRY_MARKING_STR {
+                        // global_put では上書きとして扱われているので、削除したキーの分をここで反映する
+                        namespace::add_usage_delta(namespace.as_str(), -1, -(data_iv.val_str.len() as i64));
+                    }
+                    return Ok(true);
                 }
             }
         }
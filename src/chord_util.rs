@@ -28,12 +28,45 @@ type ArMu<T> = Arc<Mutex<T>>;
 #[derive(Debug, Clone)]
 pub struct DataIdAndValue {
     pub data_id : u32,
-    pub val_str : String
+    pub val_str : String,
+    // データが属するネームスペース. 空文字列はデフォルトのネームスペースを表す
+    #[serde(default)]
+    pub namespace : String,
+    // 有効期限 (UNIXTIME, ミリ秒). 0 の場合は期限無し
+    #[serde(default)]
+    pub expire_at : i64,
+    // 何番目のレプリカか. 0 はマスターデータ相当のもの
+    #[serde(default)]
+    pub replica_idx : u32,
+    // 書き込み時のレプリケーションファクタ. 0 の場合は不明 (ネームスペースの設定に従う)
+    #[serde(default)]
+    pub replication_factor : u32
 }
 
 impl DataIdAndValue {
     pub fn new(data_id : u32, val_str : String) -> DataIdAndValue {
-        DataIdAndValue {data_id : data_id, val_str : val_str}
+        DataIdAndValue {data_id : data_id, val_str : val_str, namespace : "".to_string(), expire_at : 0, replica_idx : 0, replication_factor : 0}
+    }
+
+    pub fn is_expired(&self, now_millis: i64) -> bool {
+        return self.expire_at != 0 && self.expire_at <= now_millis;
+    }
+}
+
+// global_put の結果
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct PutResult {
+    // 書き込みに成功したコピーの数. コピーは互いに異なるノードに配置されるため、データを保持するノードの数と等しい
+    pub replica_count : u32,
+    // 要求されたレプリケーションファクタ. replica_count がこれより小さい場合、一部のコピーの書き込みに失敗したか
+    // リング上のノード数が足りていない
+    pub replication_factor : u32
+}
+
+impl PutResult {
+    pub fn new(replica_count : u32, replication_factor : u32) -> PutResult {
+        PutResult {replica_count : replica_count, replication_factor : replication_factor}
     }
 }
 
@@ -48,6 +81,16 @@ pub const ERR_CODE_NOT_TANTOU: u32 = 6;
 pub const ERR_CODE_QUERIED_DATA_NOT_FOUND: u32 = 7;
 pub const ERR_CODE_DATA_TO_GET_NOT_FOUND: u32 = 8;
 pub const ERR_CODE_DATA_TO_GET_IS_DELETED: u32 = 9;
+pub const ERR_CODE_CONFIG_LOAD_FAILED: u32 = 10;
+pub const ERR_CODE_CIRCUIT_BREAKER_OPEN: u32 = 11;
+pub const ERR_CODE_UNAUTHORIZED: u32 = 12;
+pub const ERR_CODE_NAMESPACE_NOT_FOUND: u32 = 13;
+pub const ERR_CODE_NAMESPACE_ALREADY_EXISTS: u32 = 14;
+pub const ERR_CODE_QUOTA_EXCEEDED: u32 = 15;
+pub const ERR_CODE_INVALID_ARGUMENT: u32 = 16;
+pub const ERR_CODE_NO_REPLICA_WRITTEN: u32 = 17;
+pub const ERR_CODE_REBALANCE_IN_PROGRESS: u32 = 18;
+pub const ERR_CODE_INVALID_STABILIZER_STATE: u32 = 19;
 
 #[derive(Serialize, Deserialize)]
 #[derive(Debug, Clone)]
@@ -56,14 +99,27 @@ pub struct GeneralError {
     pub line : usize,
     pub column: usize,
     pub err_code: u32,
+    // ERR_CODE_NOT_TANTOU の場合に、担当ノードと推測されるノードのアドレス (推測できなければ None)
+    #[serde(default)]
+    pub owner_hint: Option<String>,
 }
 
 impl GeneralError {
     pub fn new(message: String, err_code: u32) -> GeneralError {
-        GeneralError {message: message, line: 0, column: 0, err_code: err_code}
+        GeneralError {message: message, line: 0, column: 0, err_code: err_code, owner_hint: None}
+    }
+
+    pub fn new_not_tantou(message: String, owner_hint: Option<String>) -> GeneralError {
+        GeneralError {message: message, line: 0, column: 0, err_code: ERR_CODE_NOT_TANTOU, owner_hint: owner_hint}
     }
 }
 
+// RPCの相手に到達できなかったことを示すエラーか
+// 通信の失敗に加えて、サーキットブレーカーによって呼び出しが行われなかった場合も含む
+pub fn is_unreachable_err(err: &GeneralError) -> bool {
+    return err.err_code == ERR_CODE_HTTP_REQUEST_ERR || err.err_code == ERR_CODE_CIRCUIT_BREAKER_OPEN;
+}
+
 impl std::fmt::Display for GeneralError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         return write!(f, "({})", self.message);
@@ -94,12 +150,39 @@ pub fn hash_str_to_int(input_str : &String) -> u32 {
     return hash_val_u32;
 }
 
+// ネームスペースに属するキーのハッシュ値を返す
+// 異なるネームスペースの同じキーが同じIDとならないよう、ネームスペース名と区切りのバイトを前置してハッシュをとる
+// 区切りには UTF-8 の文字列中に現れない 0xFF を用いるため、キーの文字列の内容によらず衝突しない
+// デフォルトのネームスペース (空文字列) の場合は hash_str_to_int と同じ値となる
+pub fn hash_key_in_namespace(namespace : &str, key_str : &String) -> u32 {
+    if namespace.len() == 0 {
+        return hash_str_to_int(key_str);
+    }
+
+    let mut hasher = DefaultHasher::new();
+    for elem in namespace.as_bytes() {
+        hasher.write_u8(*elem);
+    }
+    hasher.write_u8(0xFF);
+    for elem in key_str.as_bytes() {
+        hasher.write_u8(*elem);
+    }
+
+    return hasher.finish() as u32;
+}
+
 pub fn get_unixtime_in_nanos() -> i32{
     let now = SystemTime::now();
     let unixtime = now.duration_since(UNIX_EPOCH).expect("back to the future");
     return unixtime.subsec_nanos() as i32;
 }
 
+pub fn get_unixtime_in_millis() -> i64 {
+    let now = SystemTime::now();
+    let unixtime = now.duration_since(UNIX_EPOCH).expect("back to the future");
+    return unixtime.as_millis() as i64;
+}
+
 // UNIXTIME（ナノ秒精度）にいくつか値を加算した値からアドレス文字列を生成する
 pub fn gen_address_str() -> String{
     return (get_unixtime_in_nanos() + 10).to_string();
@@ -114,6 +197,26 @@ pub fn overflow_check_and_conv(id : u64) -> u32 {
     return ret_id as u32;
 }
 
+// レプリケーションファクタが replication_factor の場合の、replica_idx 番目のコピーを配置するID
+// コピーはID空間上に等間隔に配置する (replica_idx が 0 のマスターデータ相当のものは data_id そのもの)
+pub fn calc_replica_data_id(data_id : u32, replica_idx : u32, replication_factor : u32) -> u32 {
+    if replica_idx == 0 || replication_factor <= 1 {
+        return data_id;
+    }
+    let distance = (gval::ID_SPACE_RANGE / replication_factor) as u64;
+    return overflow_check_and_conv(data_id as u64 + distance * (replica_idx as u64));
+}
+
+// calc_replica_data_id の逆. replica_idx 番目のコピーのIDからマスターデータ相当のもののIDを求める
+pub fn calc_master_data_id(replica_data_id : u32, replica_idx : u32, replication_factor : u32) -> u32 {
+    if replica_idx == 0 || replication_factor <= 1 {
+        return replica_data_id;
+    }
+    let distance = (gval::ID_SPACE_RANGE / replication_factor) as u64;
+    let modulus = (gval::ID_MAX + 1) as u64;
+    return overflow_check_and_conv(replica_data_id as u64 + modulus - distance * (replica_idx as u64));
+}
+
 pub fn conv_id_to_ratio_str(id : u32) -> String {
     let ratio = (id as f64 / gval::ID_MAX as f64) * 100.0;
     return format!("{:.4}", ratio);
@@ -133,7 +236,7 @@ pub fn calc_distance_between_nodes_left_mawari(base_id : u32, target_id : u32) -
     if slided_base_id < 0 {
         // マイナスの値をとった場合は値0を通り越しているので
         // それにあった値に置き換える
-        slided_base_id = (gval::ID_MAX as i64) + (slided_base_id as i64);
+        slided_base_id += gval::ID_MAX as i64;
     }
 
     // 0を跨いだ場合の考慮はされているのであとは単純に値の大きな方から小さな方との差
@@ -157,7 +260,7 @@ pub fn calc_distance_between_nodes_right_mawari(base_id : u32, target_id : u32)
     if slided_target_id < 0 {
         // マイナスの値をとった場合は値0を通り越しているので
         // それにあった値に置き換える
-        slided_target_id = (gval::ID_MAX as i64) + (slided_target_id as i64);
+        slided_target_id += gval::ID_MAX as i64;
     }
 
     // 0を跨いだ場合の考慮はされているので、あとは単純に値の大きな方から小さな方との差
@@ -171,11 +274,7 @@ pub fn exist_between_two_nodes_right_mawari(from_id : u32, end_id : u32, target_
     let distance_end = calc_distance_between_nodes_right_mawari(from_id, end_id);
     let distance_target = calc_distance_between_nodes_right_mawari(from_id, target_id);
 
-    if distance_target < distance_end {
-        return true;
-    } else {
-        return false;
-    }
+    return distance_target < distance_end;
 }
 
 // TODO: (rustr) グローバル定数を見て、ファイルに書き出すフラグが立っていたら、ファイルに書くようにする (dprint)
@@ -188,12 +287,17 @@ pub fn dprint(print_str : &String) {
 }
 
 pub fn gen_debug_str_of_node(node_info : &node_info::NodeInfo) -> String {
-    return node_info.born_id.to_string() + &",".to_string() + &format!("{:X}", node_info.node_id) + &",".to_string()
+    return node_info.born_id.to_string() + "," + &format!("{:X}", node_info.node_id) + ","
        + &conv_id_to_ratio_str(node_info.node_id);
 }
 
+pub fn gen_debug_str_of_entry(entry : &node_info::NodeInfoEntry) -> String {
+    return entry.born_id.to_string() + "," + &format!("{:X}", entry.node_id) + ","
+       + &conv_id_to_ratio_str(entry.node_id);
+}
+
 pub fn gen_debug_str_of_data(data_id : u32) -> String {
-    return format!("{:X}", data_id) + &",".to_string() + &conv_id_to_ratio_str(data_id);
+    return format!("{:X}", data_id) + "," + &conv_id_to_ratio_str(data_id);
 }
 
 pub fn get_node_info(self_node: ArMu<node_info::NodeInfo>) -> node_info::NodeInfo {
@@ -202,6 +306,25 @@ pub fn get_node_info(self_node: ArMu<node_info::NodeInfo>) -> node_info::NodeInf
     return ret;
 }
 
+// 以下の3つは経路表の一部のみを返す. 経路表全体が必要でない場合は get_node_info の代わりに用いる
+pub fn get_successor(self_node: ArMu<node_info::NodeInfo>) -> Result<node_info::NodeInfoEntry, GeneralError> {
+    let self_node_ref = self_node.lock().unwrap();
+    return match self_node_ref.successor_info_list.first() {
+        None => Err(GeneralError::new("successor is unknown".to_string(), ERR_CODE_NODE_IS_DOWNED)),
+        Some(succ_info) => Ok(succ_info.clone())
+    };
+}
+
+pub fn get_predecessor(self_node: ArMu<node_info::NodeInfo>) -> Option<node_info::NodeInfoEntry> {
+    let self_node_ref = self_node.lock().unwrap();
+    return self_node_ref.predecessor_info.first().cloned();
+}
+
+pub fn get_successor_list(self_node: ArMu<node_info::NodeInfo>) -> Vec<node_info::NodeInfoEntry> {
+    let self_node_ref = self_node.lock().unwrap();
+    return self_node_ref.successor_info_list.clone();
+}
+
 pub fn iv_clone_from_ref(iv_ref: &DataIdAndValue) -> DataIdAndValue {
-    return DataIdAndValue::new(iv_ref.data_id, iv_ref.val_str.clone());
+    return iv_ref.clone();
 }
@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config;
+use crate::chord_util;
+
+// ピアごとのサーキットブレーカー
+// 連続してRPCが失敗したピアへの呼び出しは一定時間 (open状態の間) 行わずに即座にエラーとする
+// open状態の時間が過ぎたら、1つの呼び出しだけを試行として通し (half-open状態)、
+// 成功すれば元に戻し、失敗すれば再度open状態とする
+
+struct BreakerState {
+    consecutive_failures: u32,
+    // open状態となった時刻. closed状態の場合は None
+    opened_at: Option<Instant>,
+    // half-open状態で試行の呼び出しを通しているか
+    is_probing: bool
+}
+
+lazy_static! {
+    // キーはピアの address_str
+    static ref BREAKERS : Mutex<HashMap<String, BreakerState>> = Mutex::new(HashMap::new());
+}
+
+// address のピアへの呼び出しを行ってよいかを返す
+pub fn allow_request(address: &String) -> bool {
+    let open_millis = config::get_config().circuit_breaker_open_millis;
+    let mut breakers_ref = BREAKERS.lock().unwrap();
+    let state = match breakers_ref.get_mut(address) {
+        None => { return true; }
+        Some(state) => state
+    };
+
+    match state.opened_at {
+        None => { return true; }
+        Some(opened_at) => {
+            if opened_at.elapsed() < Duration::from_millis(open_millis) || state.is_probing {
+                return false;
+            }
+            // half-open状態として、この呼び出しだけを通す
+            state.is_probing = true;
+            return true;
+        }
+    };
+}
+
+pub fn record_success(address: &String) {
+    let mut breakers_ref = BREAKERS.lock().unwrap();
+    breakers_ref.remove(address);
+}
+
+// 失敗を記録する. 今回の失敗でopen状態となった場合は true を返す
+pub fn record_failure(address: &str) -> bool {
+    let threshold = config::get_config().circuit_breaker_failure_threshold;
+    let mut breakers_ref = BREAKERS.lock().unwrap();
+    let state = breakers_ref.entry(address.to_string()).or_insert(BreakerState { consecutive_failures: 0, opened_at: None, is_probing: false });
+    state.consecutive_failures += 1;
+
+    if state.is_probing || (state.opened_at.is_none() && state.consecutive_failures >= threshold) {
+        state.opened_at = Some(Instant::now());
+        state.is_probing = false;
+        chord_util::dprint(&("circuit_breaker_opened,".to_string() + address + "," + state.consecutive_failures.to_string().as_str()));
+        return true;
+    }
+    return false;
+}
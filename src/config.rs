@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::fs::File;
+use std::io::Read;
+use serde::{Serialize, Deserialize};
+
+use crate::chord_util;
+use crate::gval;
+
+// 起動時に読み込む設定
+// 設定ファイル(JSON)はコマンドライン引数で指定し、指定が無い場合や記述の無い項目はデフォルト値となる
+// gvalモジュールの定数と異なり、ノードごとに値を変えられるものを扱う
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct RpcTimeout {
+    pub connect_millis: u64,
+    pub request_millis: u64
+}
+
+impl RpcTimeout {
+    pub fn new(connect_millis: u64, request_millis: u64) -> RpcTimeout {
+        RpcTimeout { connect_millis: connect_millis, request_millis: request_millis }
+    }
+}
+
+// stabilize処理の実行間隔の下限と上限
+// 経路表に変化の無い状態が続くと上限に向けて間隔を広げ、ノードの参加や離脱を検知すると下限に戻す
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct StabilizeInterval {
+    pub min_millis: u64,
+    pub max_millis: u64
+}
+
+impl StabilizeInterval {
+    pub fn new(min_millis: u64, max_millis: u64) -> StabilizeInterval {
+        StabilizeInterval { min_millis: min_millis, max_millis: max_millis }
+    }
+}
+
+// 各パスはPEM形式のファイルを指す
+// cert_path, key_path はリスナのサーバ証明書と、他ノードへのRPCの際にクライアント証明書として提示するものを兼ねる
+// 他ノードをIPアドレスで指定するため、証明書のSANにはノードのIPアドレスを含めること
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+    // クラスタのノードの証明書を発行したCAの証明書
+    // 他ノードのサーバ証明書の検証と、内部RPCを呼び出してきたノードのクライアント証明書の検証に用いる
+    pub ca_cert_path: String
+}
+
+// クライアント向けAPIのロール. 上位のロールは下位のロールの権限を全て含む (read < write < admin)
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Read,
+    Write,
+    Admin
+}
+
+// データのコピーの配置方法. クラスタの全ノードで同じものを設定すること
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplicationMode {
+    // data_id から等間隔にずらしたIDのそれぞれを担当するノードに配置する. コピーごとに find_successor が必要
+    SaltedId,
+    // data_id を担当するノードと、そのノードの successor_info_list 上の後続のノードに配置する
+    // 問い合わせは1回で済み、コピーは必ず異なるノードに配置されるが、コピーの数は successor_info_list の長さ + 1 が上限となる
+    SuccessorList
+}
+
+// find_successor による名前解決の方法
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LookupMode {
+    // 問い合わせ元のノードが、経由する各ノードに closest_preceding_finger を問い合わせて探索を進める
+    Iterative,
+    // 各ノードが、次に経由するノードへ問い合わせを転送する. 担当ノードが分かったノードから結果が返される
+    Recursive
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct ApiKeyConfig {
+    pub key: String,
+    pub role: Role
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+#[serde(default)]
+pub struct NodeConfig {
+    // rpc_timeouts に記述の無いRPCに用いる
+    // HTTPでのRPCの接続タイムアウトはピアごとのClientに対して設定するため、常にこちらの connect_millis を用いる
+    pub rpc_default_timeout: RpcTimeout,
+    // キーはRPCのメソッド名 (HTTPの場合のエンドポイントのパス)
+    pub rpc_timeouts: HashMap<String, RpcTimeout>,
+    // 冪等なRPCが失敗した場合のリトライ回数の上限
+    pub rpc_retry_max: u32,
+    // リトライ間隔は base * 2^(リトライ回数 - 1) を上限とした乱数 (ただし max を超えない)
+    pub rpc_retry_base_backoff_millis: u64,
+    pub rpc_retry_max_backoff_millis: u64,
+    // 連続してこの回数RPCが失敗したピアへの呼び出しは、一定時間の間 行わずにエラーとする
+    pub circuit_breaker_failure_threshold: u32,
+    pub circuit_breaker_open_millis: u64,
+    // 指定した場合、リスナと他ノードへのRPCでTLSを用い、内部RPCはCAが発行した証明書を提示したノードからのみ受け付ける
+    // 省略時は平文のHTTPとなる
+    pub tls: Option<TlsConfig>,
+    // クライアント向けAPIで受け付けるAPIキー(トークン)と、それぞれに与えるロール
+    // 空の場合は認証を行わない
+    pub api_keys: Vec<ApiKeyConfig>,
+    // TLSを用いない場合に、内部RPCの呼び出し元がクラスタのノードであることの確認に用いる共有の秘密値
+    // 全ノードで同じ値を設定する. TLSも用いず、これも省略した場合は確認を行わない
+    // (api_keys を指定する場合は、TLSかこれのいずれかが必須)
+    pub cluster_token: Option<String>,
+    // レプリケーションファクタ (マスターデータ相当のものを含めたデータのコピーの数)
+    // ネームスペースの作成時や書き込み時に指定が無い場合に default_replication_factor が用いられ、
+    // 指定できる値の上限は max_replication_factor となる
+    pub default_replication_factor: u32,
+    pub max_replication_factor: u32,
+    pub replication_mode: ReplicationMode,
+    // 自ノードから開始する名前解決の方法. ノードごとに異なっていてもよい
+    pub lookup_mode: LookupMode,
+    // このノードが属する障害ドメイン (ゾーンやラック) のラベル
+    pub zone: String,
+    // 各データのコピーが少なくともこの数のゾーンにまたがるよう配置する (レプリケーションファクタの方が小さい場合はそちら)
+    // クラスタの全ノードで同じ値を設定すること. ゾーンの数が足りない場合は、可能な範囲で異なるゾーンに配置する
+    pub min_replica_zones: u32,
+    // このプロセスでホストする仮想ノードの数は vnode_count * vnode_weight (四捨五入) となる
+    // vnode_count はクラスタの全ノードで同じ値とし、マシンの性能に応じて vnode_weight を変えることを想定する
+    pub vnode_count: u32,
+    pub vnode_weight: f64,
+    // stabilize_successor (successor_info_list の更新を含む) の実行間隔
+    pub stabilize_successor_interval: StabilizeInterval,
+    // stabilize_finger_table の実行間隔 (フィンガーテーブルの1エントリごと)
+    pub stabilize_finger_interval: StabilizeInterval,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        let mut rpc_timeouts = HashMap::new();
+        rpc_timeouts.insert("get_node_info".to_string(), RpcTimeout::new(2000, 5000));
+        rpc_timeouts.insert("get_successor".to_string(), RpcTimeout::new(2000, 5000));
+        rpc_timeouts.insert("get_predecessor".to_string(), RpcTimeout::new(2000, 5000));
+        rpc_timeouts.insert("get_successor_list".to_string(), RpcTimeout::new(2000, 5000));
+        rpc_timeouts.insert("get_rpc_protocol_info".to_string(), RpcTimeout::new(2000, 5000));
+        rpc_timeouts.insert("closest_preceding_finger".to_string(), RpcTimeout::new(2000, 5000));
+        rpc_timeouts.insert("check_predecessor".to_string(), RpcTimeout::new(2000, 30000));
+        rpc_timeouts.insert("probe_peer".to_string(), RpcTimeout::new(2000, 30000));
+        // 問い合わせ先のノードでさらにRPCが行われるもの
+        rpc_timeouts.insert("find_successor".to_string(), RpcTimeout::new(2000, 30000));
+        rpc_timeouts.insert("find_successor_recursive".to_string(), RpcTimeout::new(2000, 30000));
+        rpc_timeouts.insert("pass_datas".to_string(), RpcTimeout::new(2000, 60000));
+        rpc_timeouts.insert("import_batch".to_string(), RpcTimeout::new(2000, 120000));
+
+        NodeConfig {
+            rpc_default_timeout: RpcTimeout::new(2000, 10000),
+            rpc_timeouts: rpc_timeouts,
+            rpc_retry_max: 2,
+            rpc_retry_base_backoff_millis: 50,
+            rpc_retry_max_backoff_millis: 1000,
+            circuit_breaker_failure_threshold: 3,
+            circuit_breaker_open_millis: 5000,
+            tls: None,
+            api_keys: vec![],
+            cluster_token: None,
+            default_replication_factor: 7,
+            max_replication_factor: 7,
+            replication_mode: ReplicationMode::SaltedId,
+            lookup_mode: LookupMode::Iterative,
+            zone: "".to_string(),
+            min_replica_zones: 1,
+            vnode_count: 1,
+            vnode_weight: 1.0,
+            stabilize_successor_interval: StabilizeInterval::new(500, 2000),
+            stabilize_finger_interval: StabilizeInterval::new(100, 1000),
+        }
+    }
+}
+
+lazy_static! {
+    static ref NODE_CONFIG : RwLock<NodeConfig> = RwLock::new(NodeConfig::default());
+}
+
+pub fn load_config(path: &String) -> Result<bool, chord_util::GeneralError> {
+    let mut file = match File::open(path) {
+        Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_CONFIG_LOAD_FAILED)); }
+        Ok(file) => file
+    };
+    let mut text = String::new();
+    match file.read_to_string(&mut text) {
+        Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_CONFIG_LOAD_FAILED)); }
+        Ok(_) => {}
+    };
+    let loaded = match serde_json::from_str::<NodeConfig>(&text) {
+        Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_CONFIG_LOAD_FAILED)); }
+        Ok(conf) => conf
+    };
+
+    match &loaded.tls {
+        None => {}
+        Some(tls_conf) => {
+            // 起動後にRPCの発行時点で気付くことのないよう、ファイルが読めることをここで確認しておく
+            for path in [&tls_conf.cert_path, &tls_conf.key_path, &tls_conf.ca_cert_path] {
+                match std::fs::metadata(path) {
+                    Err(err) => { return Err(chord_util::GeneralError::new(path.clone() + ": " + err.to_string().as_str(), chord_util::ERR_CODE_CONFIG_LOAD_FAILED)); }
+                    Ok(_) => {}
+                };
+            }
+        }
+    };
+
+    if loaded.max_replication_factor < 1 || loaded.max_replication_factor > gval::REPLICATION_FACTOR_LIMIT {
+        return Err(chord_util::GeneralError::new("max_replication_factor must be 1 to ".to_string() + gval::REPLICATION_FACTOR_LIMIT.to_string().as_str(), chord_util::ERR_CODE_CONFIG_LOAD_FAILED));
+    }
+    if loaded.default_replication_factor < 1 || loaded.default_replication_factor > loaded.max_replication_factor {
+        return Err(chord_util::GeneralError::new("default_replication_factor must be 1 to max_replication_factor".to_string(), chord_util::ERR_CODE_CONFIG_LOAD_FAILED));
+    }
+
+    // 内部RPCが誰からでも呼べる状態では、APIキーの認証を迂回できてしまう
+    if loaded.api_keys.len() > 0 && loaded.tls.is_none() && loaded.cluster_token.is_none() {
+        return Err(chord_util::GeneralError::new("api_keys requires tls or cluster_token to be set".to_string(), chord_util::ERR_CODE_CONFIG_LOAD_FAILED));
+    }
+
+    for (name, interval) in [("stabilize_successor_interval", &loaded.stabilize_successor_interval), ("stabilize_finger_interval", &loaded.stabilize_finger_interval)] {
+        if interval.min_millis < 1 || interval.min_millis > interval.max_millis {
+            return Err(chord_util::GeneralError::new(name.to_string() + ": min_millis must be 1 to max_millis", chord_util::ERR_CODE_CONFIG_LOAD_FAILED));
+        }
+    }
+
+    let mut config_ref = NODE_CONFIG.write().unwrap();
+    *config_ref = loaded;
+    return Ok(true);
+}
+
+pub fn get_config() -> NodeConfig {
+    let config_ref = NODE_CONFIG.read().unwrap();
+    return (*config_ref).clone();
+}
+
+pub fn get_rpc_timeout(method: &str) -> RpcTimeout {
+    let config_ref = NODE_CONFIG.read().unwrap();
+    return match config_ref.rpc_timeouts.get(method) {
+        None => config_ref.rpc_default_timeout.clone(),
+        Some(timeout) => timeout.clone()
+    };
+}
+
+pub fn get_tls_config() -> Option<TlsConfig> {
+    let config_ref = NODE_CONFIG.read().unwrap();
+    return config_ref.tls.clone();
+}
+
+pub fn is_tls_enabled() -> bool {
+    let config_ref = NODE_CONFIG.read().unwrap();
+    return config_ref.tls.is_some();
+}
+
+// 他ノードへのRPCのURLに用いるスキーム
+pub fn get_peer_url_scheme() -> &'static str {
+    if is_tls_enabled() {
+        return "https";
+    }
+    return "http";
+}
+
+pub fn get_cluster_token() -> Option<String> {
+    let config_ref = NODE_CONFIG.read().unwrap();
+    return config_ref.cluster_token.clone();
+}
+
+// APIキーの認証を行うか
+pub fn is_api_key_auth_enabled() -> bool {
+    let config_ref = NODE_CONFIG.read().unwrap();
+    return config_ref.api_keys.len() > 0;
+}
+
+// key に与えられているロールを返す. 登録されていないキーの場合は None
+pub fn get_role_of_api_key(key: &str) -> Option<Role> {
+    let config_ref = NODE_CONFIG.read().unwrap();
+    for api_key in config_ref.api_keys.iter() {
+        if api_key.key == key {
+            return Some(api_key.role);
+        }
+    }
+    return None;
+}
+
+pub fn get_default_replication_factor() -> u32 {
+    let config_ref = NODE_CONFIG.read().unwrap();
+    return config_ref.default_replication_factor;
+}
+
+pub fn get_max_replication_factor() -> u32 {
+    let config_ref = NODE_CONFIG.read().unwrap();
+    return config_ref.max_replication_factor;
+}
+
+pub fn get_replication_mode() -> ReplicationMode {
+    let config_ref = NODE_CONFIG.read().unwrap();
+    return config_ref.replication_mode;
+}
+
+pub fn get_lookup_mode() -> LookupMode {
+    let config_ref = NODE_CONFIG.read().unwrap();
+    return config_ref.lookup_mode;
+}
+
+pub fn get_zone() -> String {
+    let config_ref = NODE_CONFIG.read().unwrap();
+    return config_ref.zone.clone();
+}
+
+pub fn get_min_replica_zones() -> u32 {
+    let config_ref = NODE_CONFIG.read().unwrap();
+    return config_ref.min_replica_zones;
+}
+
+pub fn get_stabilize_successor_interval() -> StabilizeInterval {
+    let config_ref = NODE_CONFIG.read().unwrap();
+    return config_ref.stabilize_successor_interval.clone();
+}
+
+pub fn get_stabilize_finger_interval() -> StabilizeInterval {
+    let config_ref = NODE_CONFIG.read().unwrap();
+    return config_ref.stabilize_finger_interval.clone();
+}
+
+// 書き込みやネームスペースの作成時に指定されたレプリケーションファクタが設定できる範囲内か確認する
+pub fn validate_replication_factor(replication_factor: u32) -> Result<bool, chord_util::GeneralError> {
+    let max_rf = get_max_replication_factor();
+    if replication_factor < 1 || replication_factor > max_rf {
+        return Err(chord_util::GeneralError::new("replication_factor must be 1 to ".to_string() + max_rf.to_string().as_str(), chord_util::ERR_CODE_INVALID_ARGUMENT));
+    }
+    return Ok(true);
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{Semaphore, OwnedSemaphorePermit};
+
+use crate::gval;
+use crate::chord_util;
+use crate::tcp_rpc;
+use crate::config;
+
+// ノード間のHTTPでのRPCに用いるコネクションをピアごとにプールする
+// reqwest::Client は内部にkeep-aliveされたコネクションのプールを持つため、
+// ピアごとにClientを1つ生成して使いまわす. 同時に発行できるリクエスト数もピアごとに制限する
+
+struct PeerConn {
+    client: reqwest::Client,
+    // 同時リクエスト数の上限に達しているピアへのリクエストを発行したいタスクはここで待ち合わせる
+    permits: Arc<Semaphore>
+}
+
+lazy_static! {
+    // キーはピアの address_str
+    static ref PEER_CONNS : Mutex<HashMap<String, PeerConn>> = Mutex::new(HashMap::new());
+}
+
+// acquire で得られ、dropされた時点でピアへのリクエストの枠を返却する
+pub struct PeerConnGuard {
+    _permit: OwnedSemaphorePermit,
+    pub client: reqwest::Client
+}
+
+fn read_pem_file(path: &String) -> Result<Vec<u8>, chord_util::GeneralError> {
+    return match std::fs::read(path) {
+        Err(err) => Err(chord_util::GeneralError::new(path.clone() + ": " + err.to_string().as_str(), chord_util::ERR_CODE_CONFIG_LOAD_FAILED)),
+        Ok(bytes) => Ok(bytes)
+    };
+}
+
+// TLSが有効な場合は、クラスタのCAが発行した証明書のみを信頼し、自身の証明書をクライアント証明書として提示する
+fn apply_tls_config(builder: reqwest::ClientBuilder, tls_conf: &config::TlsConfig) -> Result<reqwest::ClientBuilder, chord_util::GeneralError> {
+    let ca_cert = match read_pem_file(&tls_conf.ca_cert_path) {
+        Err(err) => { return Err(err); }
+        Ok(pem) => match reqwest::Certificate::from_pem(&pem) {
+            Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_CONFIG_LOAD_FAILED)); }
+            Ok(cert) => cert
+        }
+    };
+
+    // Identity::from_pem は証明書と秘密鍵を1つのPEMとして受け取る
+    let mut identity_pem = match read_pem_file(&tls_conf.cert_path) { Err(err) => { return Err(err); } Ok(pem) => pem };
+    identity_pem.push(b'\n');
+    match read_pem_file(&tls_conf.key_path) {
+        Err(err) => { return Err(err); }
+        Ok(pem) => { identity_pem.extend(pem); }
+    };
+    let identity = match reqwest::Identity::from_pem(&identity_pem) {
+        Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_CONFIG_LOAD_FAILED)); }
+        Ok(identity) => identity
+    };
+
+    return Ok(builder
+        .use_rustls_tls()
+        .tls_built_in_root_certs(false)
+        .add_root_certificate(ca_cert)
+        .identity(identity));
+}
+
+// リクエストのタイムアウトはRPCの種類ごとにリクエスト単位で設定する
+fn build_client() -> Result<reqwest::Client, chord_util::GeneralError> {
+    let mut builder = reqwest::Client::builder()
+    .connect_timeout(Duration::from_millis(config::get_config().rpc_default_timeout.connect_millis))
+    .pool_idle_timeout(Duration::from_secs(gval::PEER_CONN_IDLE_TIMEOUT_SEC))
+    .pool_max_idle_per_host(gval::PEER_CONN_MAX_IDLE_PER_PEER)
+    .tcp_keepalive(Duration::from_secs(gval::PEER_CONN_TCP_KEEPALIVE_SEC));
+
+    match config::get_tls_config() {
+        None => {}
+        Some(tls_conf) => {
+            builder = match apply_tls_config(builder, &tls_conf) {
+                Err(err) => { return Err(err); }
+                Ok(tls_builder) => tls_builder
+            };
+        }
+    };
+
+    let client = match builder.build(){
+        Err(err) => {
+            return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR));
+        },
+        Ok(got_client) => got_client
+    };
+
+    return Ok(client);
+}
+
+// address のピアへリクエストを発行する枠を確保し、そのピア用のClientを返す
+// 同時リクエスト数が上限に達している場合は枠が空くまで待つ
+pub async fn acquire(address: &String) -> Result<PeerConnGuard, chord_util::GeneralError> {
+    // 待ち合わせの間はロックを保持しないよう、Clientとセマフォを取り出してからロックを解放する
+    let (client, permits) = {
+        let mut conns_ref = PEER_CONNS.lock().unwrap();
+        if conns_ref.contains_key(address) == false {
+            let client = match build_client() {
+                Err(err) => { return Err(err); }
+                Ok(client) => client
+            };
+            conns_ref.insert(address.clone(), PeerConn { client: client, permits: Arc::new(Semaphore::new(gval::MAX_CONCURRENT_RPCS_PER_PEER)) });
+        }
+        let peer_conn = conns_ref.get(address).unwrap();
+        (peer_conn.client.clone(), Arc::clone(&peer_conn.permits))
+    };
+
+    let permit = match permits.acquire_owned().await {
+        Err(err) => {
+            // evict_peerでセマフォがcloseされた
+            return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR));
+        }
+        Ok(permit) => permit
+    };
+
+    return Ok(PeerConnGuard { _permit: permit, client: client });
+}
+
+// ダウンしたと判断されたピアのコネクションを破棄する
+// 実行中のリクエストが保持しているClientは、それらが終わった時点で解放される
+pub fn evict_peer(address: &String) {
+    let mut conns_ref = PEER_CONNS.lock().unwrap();
+    match conns_ref.remove(address) {
+        None => {}
+        Some(peer_conn) => {
+            // 枠の空きを待っているタスクを待ち合わせから解放する
+            peer_conn.permits.close();
+        }
+    };
+    drop(conns_ref);
+
+    tcp_rpc::forget_peer(address);
+}
@@ -2,6 +2,7 @@ use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::cell::RefCell;
 use std::ops::Range;
+use serde::{Serialize, Deserialize};
 
 use crate::gval;
 use crate::chord_node;
@@ -15,21 +16,46 @@ pub const DELETED_ENTRY_MARKING_STR : &str = "THIS_KEY_IS_DELETED";
 
 type ArMu<T> = Arc<Mutex<T>>;
 
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct NamespaceUsage {
+    pub key_count: u64,
+    // 値の文字列のバイト数の合計
+    pub bytes: u64
+}
+
+impl Default for NamespaceUsage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NamespaceUsage {
+    pub fn new() -> NamespaceUsage {
+        NamespaceUsage { key_count: 0, bytes: 0 }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct DataStore {
     // Keyはハッシュを通されたものなので元データの値とは異なる
     stored_data : HashMap<String, chord_util::DataIdAndValue>,
 }
 
+impl Default for DataStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DataStore {
     pub fn new() -> DataStore {
         let sd = HashMap::new();
         DataStore {stored_data : sd}
     }
 
-    pub fn store_one_iv(& mut self, data_id: u32, value_str: String) -> bool {
-        let iv_entry = chord_util::DataIdAndValue::new(data_id, value_str);
-        match self.stored_data.insert(data_id.to_string(), iv_entry){
+    pub fn store_one_iv(& mut self, iv_entry: chord_util::DataIdAndValue) -> bool {
+        match self.stored_data.insert(iv_entry.data_id.to_string(), iv_entry){
             None => { return false; }
             Some(_old_val) => { return true; }
         };
@@ -41,7 +67,11 @@ impl DataStore {
                 return Err(chord_util::GeneralError::new("GET REQUESTED DATA IS NOT FOUND".to_string(), chord_util::ERR_CODE_DATA_TO_GET_NOT_FOUND));
             }
             Some(data_iv) => {
-                return Ok(chord_util::iv_clone_from_ref(&data_iv));
+                // 有効期限切れのデータは存在しないものとして扱う (実際の削除は purge_expired_datas で行う)
+                if data_iv.is_expired(chord_util::get_unixtime_in_millis()) {
+                    return Err(chord_util::GeneralError::new("GET REQUESTED DATA IS NOT FOUND".to_string(), chord_util::ERR_CODE_DATA_TO_GET_NOT_FOUND));
+                }
+                return Ok(chord_util::iv_clone_from_ref(data_iv));
             }
         }
     }
@@ -52,26 +82,157 @@ impl DataStore {
 
     pub fn store_iv_with_vec(&mut self, iv_vec: Vec<chord_util::DataIdAndValue>){
         for each_iv in iv_vec {
-            self.store_one_iv(each_iv.data_id, each_iv.val_str);
+            self.store_one_iv(each_iv);
         }
     }
 
-    // 自身のノードIDとpredecessorのノードIDを指定すると、自身の担当範囲外のデータを削除し、同時に削除したデータ
-    // のリストが返る
-    pub fn get_and_delete_iv_with_pred_self_id(&mut self, pred_id: u32, self_id: u32) -> Vec<chord_util::DataIdAndValue> {
+    // 他のノードから範囲単位で転送されてきたデータを格納し、格納した数を返す
+    // 既にマスターデータ相当のもの (有効期限切れのものを除く) を保持しているデータは、転送元のものより新しいため上書きしない
+    pub fn store_transferred_datas(&mut self, iv_vec: Vec<chord_util::DataIdAndValue>) -> u32 {
+        let now_millis = chord_util::get_unixtime_in_millis();
+        let mut stored_count: u32 = 0;
+        for each_iv in iv_vec {
+            match self.stored_data.get(&each_iv.data_id.to_string()) {
+                Some(existing) if existing.replica_idx == 0 && existing.is_expired(now_millis) == false => { continue; }
+                _ => {}
+            };
+            self.store_one_iv(each_iv);
+            stored_count += 1;
+        }
+        return stored_count;
+    }
+
+    // 有効期限切れのものを除く全てのデータを返す
+    pub fn get_all_datas(&self) -> Vec<&chord_util::DataIdAndValue> {
+        let now_millis = chord_util::get_unixtime_in_millis();
+        return self.stored_data.values().filter(|iv| iv.is_expired(now_millis) == false).collect();
+    }
+
+    // 有効期限切れのデータを削除し、削除した数を返す
+    pub fn purge_expired_datas(&mut self) -> usize {
+        let now_millis = chord_util::get_unixtime_in_millis();
+        let before_len = self.stored_data.len();
+        self.stored_data.retain(|_, iv| iv.is_expired(now_millis) == false);
+        return before_len - self.stored_data.len();
+    }
+
+    // namespace に属するデータを全て削除し、削除した数を返す
+    pub fn remove_namespace_datas(&mut self, namespace: &str) -> usize {
+        let before_len = self.stored_data.len();
+        self.stored_data.retain(|_, iv| iv.namespace != namespace);
+        return before_len - self.stored_data.len();
+    }
+
+    // ネームスペースごとの使用量を返す
+    // レプリカを重複して数えないよう、マスターデータ相当のもの (replica_idx が 0) のみを数え、削除済みのデータは数えない
+    pub fn get_namespace_usages(&self) -> HashMap<String, NamespaceUsage> {
+        let mut usages: HashMap<String, NamespaceUsage> = HashMap::new();
+        for iv in self.stored_data.values() {
+            if iv.replica_idx != 0 || iv.val_str == DELETED_ENTRY_MARKING_STR {
+                continue;
+            }
+            let usage = usages.entry(iv.namespace.clone()).or_default();
+            usage.key_count += 1;
+            usage.bytes += iv.val_str.len() as u64;
+        }
+        return usages;
+    }
+
+    // [range_from, range_end) の範囲のIDのデータを data_id の順に返す. 削除はしない
+    // masters_only が true の場合はマスターデータ相当のもの (replica_idx が 0 のもの) のみを返す
+    pub fn get_datas_in_range(&self, range_from: u32, range_end: u32, masters_only: bool) -> Vec<chord_util::DataIdAndValue> {
         let mut ret_vec: Vec<chord_util::DataIdAndValue> = vec![];
-        for (key, value) in &self.stored_data {
-            let data_id: u32 = key.parse().unwrap();
-            if chord_util::exist_between_two_nodes_right_mawari(self_id, pred_id, data_id) == true {
-                ret_vec.push((*value).clone());                
+        for iv in self.get_all_datas() {
+            if masters_only && iv.replica_idx != 0 {
+                continue;
+            }
+            if chord_util::exist_between_two_nodes_right_mawari(range_from, range_end, iv.data_id) == true {
+                ret_vec.push(iv.clone());
             }
         }
-        // ret_vecに詰めたデータを self.stored_data から削除する
-        for entry in &ret_vec {
-            self.remove_one_data(entry.data_id);
+        ret_vec.sort_by_key(|iv| iv.data_id);
+        return ret_vec;
+    }
+
+    pub fn remove_datas(&mut self, data_ids: &Vec<u32>) {
+        for data_id in data_ids {
+            self.remove_one_data(*data_id);
         }
+    }
 
-        return ret_vec;
-    }    
+    // successor_list モードのレプリケーションで、担当ノードが変わったデータのレプリカの位置を replica_idx に付け直す
+    // 書き込み時のレプリケーションファクタ以上の位置となったものは、コピーの数を超えるため削除する
+    // (レプリケーションファクタが不明なものは削除しない). マスターデータ相当のものは対象としない
+    // 付け直した数と削除した数の合計を返す. 同じ引数で複数回呼ばれても結果は変わらない
+    pub fn reindex_replicas(&mut self, data_ids: &Vec<u32>, replica_idx: u32) -> u32 {
+        let mut changed_count: u32 = 0;
+        for data_id in data_ids {
+            let key = data_id.to_string();
+            let is_over_rf = match self.stored_data.get_mut(&key) {
+                Some(iv) if iv.replica_idx != 0 => {
+                    if iv.replication_factor != 0 && replica_idx >= iv.replication_factor {
+                        true
+                    } else {
+                        if iv.replica_idx != replica_idx {
+                            iv.replica_idx = replica_idx;
+                            changed_count += 1;
+                        }
+                        false
+                    }
+                }
+                _ => false
+            };
+            if is_over_rf {
+                self.stored_data.remove(&key);
+                changed_count += 1;
+            }
+        }
+        return changed_count;
+    }
 
+    // successor_list モードのレプリケーションで、他のノードに渡したマスターデータ相当のものを
+    // 新たな担当ノードの successor としてのレプリカに切り替える
+    pub fn demote_datas(&mut self, data_ids: &Vec<u32>) {
+        for data_id in data_ids {
+            match self.stored_data.get_mut(&data_id.to_string()) {
+                Some(iv) if iv.replica_idx == 0 => { iv.replica_idx = 1; }
+                _ => {}
+            };
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gen_copy(data_id: u32, replica_idx: u32, replication_factor: u32) -> chord_util::DataIdAndValue {
+        let mut iv = chord_util::DataIdAndValue::new(data_id, "v".to_string());
+        iv.replica_idx = replica_idx;
+        iv.replication_factor = replication_factor;
+        return iv;
+    }
+
+    #[test]
+    fn reindex_replicas_shifts_and_drops_over_rf() {
+        let mut data_store = DataStore::new();
+        data_store.store_one_iv(gen_copy(1, 1, 3));
+        data_store.store_one_iv(gen_copy(2, 2, 3));
+        data_store.store_one_iv(gen_copy(3, 0, 3));
+        data_store.store_one_iv(gen_copy(4, 2, 0));
+
+        assert_eq!(data_store.reindex_replicas(&vec![1, 3], 2), 1);
+        assert_eq!(data_store.get(1).unwrap().replica_idx, 2);
+        // マスターデータ相当のものは対象としない
+        assert_eq!(data_store.get(3).unwrap().replica_idx, 0);
+
+        assert_eq!(data_store.reindex_replicas(&vec![2, 4], 3), 2);
+        assert!(data_store.get(2).is_err());
+        // レプリケーションファクタが不明なものは削除しない
+        assert_eq!(data_store.get(4).unwrap().replica_idx, 3);
+
+        // 同じ引数で再度呼んでも変わらない
+        assert_eq!(data_store.reindex_replicas(&vec![1], 2), 0);
+    }
 }
@@ -1,12 +1,17 @@
 use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
 use std::cell::{RefCell, Ref, RefMut};
 use std::time::Duration;
 
-use rocket_contrib::json::Json;
+use rocket::serde::json::Json;
 use rocket::State;
-use rocket::config::{Config, Environment};
+use rocket::Data;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::data::{Limits, ToByteUnit};
 use reqwest::Error;
 use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
 
 use chord_util::GeneralError;
 
@@ -17,24 +22,48 @@ use crate::chord_util;
 use crate::data_store;
 use crate::router;
 use crate::stabilizer;
+use crate::tcp_rpc;
+use crate::conn_pool;
+use crate::config;
+use crate::circuit_breaker;
+use crate::auth;
+use crate::namespace;
+use crate::placement;
+use crate::rebalance;
+use crate::transfer;
+use crate::bulk;
+use crate::stabilize_rate;
+use crate::stabilizer_service;
+use crate::failure_detector;
+use crate::auth::{ClusterMember, ReadAccess, WriteAccess, AdminAccess};
+use crate::vnode;
+use crate::vnode::TargetVnode;
 
 type ArMu<T> = Arc<Mutex<T>>;
 
-// urlは "http://から始まるものにすること"
-fn http_get_request(url_str: &str) -> Result<String, chord_util::GeneralError> {
-    let client = match reqwest::blocking::Client::builder()
-    .timeout(Duration::from_secs(10000))
-    .build(){
-        Err(err) => {
-            chord_util::dprint(&("ERROR at http_get_request(1)".to_string() + url_str));
-            return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR));
-        },
-        Ok(got_client) => got_client
+// クラスタトークンが設定されている場合は、内部RPCの呼び出し元であることを示すためにヘッダに付与する
+fn with_cluster_token(req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    return match config::get_cluster_token() {
+        None => req,
+        Some(token) => req.header(auth::CLUSTER_TOKEN_HEADER, token)
     };
+}
+
+// 宛先が0番以外の仮想ノードの場合は、ヘッダでそれを指定する
+fn with_vnode_header(req: reqwest::RequestBuilder, vnode_idx: u32) -> reqwest::RequestBuilder {
+    if vnode_idx == 0 {
+        return req;
+    }
+    return req.header(vnode::VNODE_HEADER, vnode_idx.to_string());
+}
 
-    let resp = match client.get(url_str)
+// urlは gen_peer_url で生成したものを渡すこと
+// client は conn_pool から得た宛先ピア用のものを渡す
+async fn http_get_request(client: &reqwest::Client, url_str: &str, vnode_idx: u32, timeout: &config::RpcTimeout) -> Result<String, chord_util::GeneralError> {
+    let resp = match with_vnode_header(with_cluster_token(client.get(url_str)), vnode_idx)
     //.header(reqwest::header::CONTENT_TYPE, "application/json")
-    .send(){
+    .timeout(Duration::from_millis(timeout.request_millis))
+    .send().await{
         Err(err) => { 
             chord_util::dprint(&("ERROR at http_get_request(2)".to_string() + url_str));
             return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR));
@@ -42,7 +71,7 @@ fn http_get_request(url_str: &str) -> Result<String, chord_util::GeneralError> {
         Ok(response) => response
     };
 
-    let ret = match resp.text(){
+    let ret = match resp.text().await{
         Err(err) => {
             chord_util::dprint(&("ERROR at http_get_request(3)".to_string() + url_str));
             return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR));
@@ -53,23 +82,14 @@ fn http_get_request(url_str: &str) -> Result<String, chord_util::GeneralError> {
     return Ok(ret);
 }
 
-// urlは "http://から始まるものにすること"
+// urlは gen_peer_url で生成したものを渡すこと
 // json_str は JSONの文字列表現をそのまま渡せばよい
-fn http_post_request(url_str: &str, json_str: String) -> Result<String, chord_util::GeneralError> {
-    //let client = reqwest::blocking::Client::new();
-    let client = match reqwest::blocking::Client::builder()
-    .timeout(Duration::from_secs(10000))
-    .build(){
-        Err(err) => { 
-            chord_util::dprint(&("ERROR at http_post_request(1)".to_string() + url_str));
-            return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR));
-        },
-        Ok(got_client) => got_client
-    };
-
-    let resp = match client.post(url_str)
+// client は conn_pool から得た宛先ピア用のものを渡す
+async fn http_post_request(client: &reqwest::Client, url_str: &str, vnode_idx: u32, json_str: String, timeout: &config::RpcTimeout) -> Result<String, chord_util::GeneralError> {
+    let resp = match with_vnode_header(with_cluster_token(client.post(url_str)), vnode_idx)
     .header(reqwest::header::CONTENT_TYPE, "application/json")
-    .body(json_str).send(){
+    .timeout(Duration::from_millis(timeout.request_millis))
+    .body(json_str).send().await{
         Err(err) => {
             chord_util::dprint(&("ERROR at http_post_request(2)".to_string() + url_str));
             return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR));
@@ -77,7 +97,7 @@ fn http_post_request(url_str: &str, json_str: String) -> Result<String, chord_ut
         Ok(response) => response        
     };
 
-    let ret = match resp.text(){
+    let ret = match resp.text().await{
         Err(err) => {
             chord_util::dprint(&("ERROR at http_post_request(3)".to_string() + url_str));
             return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR));
@@ -88,8 +108,298 @@ fn http_post_request(url_str: &str, json_str: String) -> Result<String, chord_ut
     return Ok(ret);
 }
 
+// 内部RPCのエンドポイントをマウントするパス. クライアント向けのAPIは / 直下に置く
+const INTERNAL_API_BASE : &str = "/internal";
+
+// address_str で指定されたノード (仮想ノードの場合はそれをホストする物理ノード) の method に対応するエンドポイントのURLを返す
+// TLSが有効な場合は https となる
+fn gen_peer_url(address: &str, method: &str) -> String {
+    return config::get_peer_url_scheme().to_string() + "://" + address + INTERNAL_API_BASE + "/" + method;
+}
+
+// address_str で指定されたノードとのRPCで利用するプロトコルを返す
+// まだネゴシエーションしていないピアであれば /get_rpc_protocol_info を問い合わせて決定する
+async fn get_peer_rpc_protocol(client: &reqwest::Client, address: &String) -> tcp_rpc::PeerRpcProtocol {
+    if tcp_rpc::is_binary_rpc_enabled() == false {
+        return tcp_rpc::PeerRpcProtocol::Http;
+    }
+
+    match tcp_rpc::get_cached_peer_protocol(address) {
+        None => {}
+        Some(proto) => { return proto; }
+    };
+
+    let req_rslt = http_get_request(client, &gen_peer_url(address, "get_rpc_protocol_info"), 0, &config::get_rpc_timeout("get_rpc_protocol_info")).await;
+    let res_text = match req_rslt {
+        Err(err) => {
+            // 相手がダウンしている可能性があるため、ネゴシエーション結果は記録しない
+            return tcp_rpc::PeerRpcProtocol::Http;
+        }
+        Ok(text) => text
+    };
+
+    match serde_json::from_str::<tcp_rpc::RpcProtocolInfo>(&res_text) {
+        Err(err) => {
+            // バイナリプロトコル対応以前のノードはエンドポイントを持たないのでHTTPでやり取りする
+            let http_only_info = tcp_rpc::RpcProtocolInfo { binary_rpc_version: 0, binary_rpc_port: -1 };
+            return tcp_rpc::set_peer_protocol_from_info(address, &http_only_info);
+        }
+        Ok(info) => {
+            return tcp_rpc::set_peer_protocol_from_info(address, &info);
+        }
+    };
+}
+
+// 失敗した場合にリトライしてよいRPC
+// 問い合わせ先の状態を変更しないものに限る
+// receive_data_chunk は同じチャンクを複数回受け取っても結果が変わらない. reindex_replicas も同様
+// find_successor_recursive は転送先でもリトライが行われ、段数に応じて試行が膨らむため含めない
+const IDEMPOTENT_RPC_METHODS : [&str; 14] = ["get_node_info", "get_successor", "get_predecessor", "get_successor_list", "find_successor", "closest_preceding_finger", "get", "get_namespace_usages", "get_replica_locations", "get_node_load", "receive_data_chunk", "reindex_replicas", "get_export_page", "probe_peer"];
+
+fn is_idempotent_rpc(method: &str) -> bool {
+    return IDEMPOTENT_RPC_METHODS.contains(&method);
+}
+
+// attempt回目のリトライの前に待つ時間（ミリ秒）を返す
+// 複数のノードから同時にリトライが集中しないよう、上限までの範囲の乱数とする (full jitter)
+fn calc_retry_backoff_millis(attempt: u32) -> u64 {
+    let conf = config::get_config();
+    let mut backoff = conf.rpc_retry_base_backoff_millis.saturating_mul(1u64 << (attempt - 1).min(16));
+    if backoff > conf.rpc_retry_max_backoff_millis {
+        backoff = conf.rpc_retry_max_backoff_millis;
+    }
+    return chord_util::get_rnd_int_with_limit(backoff as u32 + 1) as u64;
+}
+
+// 1回分のRPCを発行する
+// arg が None の場合は引数の無いRPCとして扱い、HTTPの場合はGETリクエストとなる
+// address は物理ノードのアドレスとし、宛先の仮想ノードは vnode_idx で指定する
+async fn rpc_request_once<A: Serialize + Sync, R: DeserializeOwned>(address: &String, vnode_idx: u32, method: &str, arg: Option<&A>, timeout: &config::RpcTimeout) -> Result<R, chord_util::GeneralError> {
+    let peer_conn = match conn_pool::acquire(address).await {
+        Err(err) => { return Err(err); }
+        Ok(guard) => guard
+    };
+
+    match get_peer_rpc_protocol(&peer_conn.client, address).await {
+        tcp_rpc::PeerRpcProtocol::Binary(bin_addr) => {
+            let serialized = match arg {
+                None => bincode::serialize(&()),
+                Some(arg_ref) => bincode::serialize(arg_ref)
+            };
+            let payload = match serialized {
+                Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)) },
+                Ok(bytes) => bytes
+            };
+            let resp_bytes = match tcp_rpc::call(&bin_addr, vnode_idx, method, payload, timeout).await {
+                Err(err) => {
+                    // 次回の呼び出し時にネゴシエーションからやり直す
+                    tcp_rpc::forget_peer(address);
+                    return Err(err);
+                }
+                Ok(bytes) => bytes
+            };
+            return match bincode::deserialize::<R>(&resp_bytes) {
+                Err(err) => Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)),
+                Ok(ret) => Ok(ret)
+            };
+        }
+        tcp_rpc::PeerRpcProtocol::Http => {
+            let url_str = gen_peer_url(address, method);
+            let req_rslt = match arg {
+                None => http_get_request(&peer_conn.client, &url_str, vnode_idx, timeout).await,
+                Some(arg_ref) => {
+                    http_post_request(
+                        &peer_conn.client,
+                        &url_str,
+                        vnode_idx,
+                        match serde_json::to_string(arg_ref){
+                            Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)) },
+                            Ok(text) => text
+                        },
+                        timeout).await
+                }
+            };
+            let res_text = match req_rslt {
+                Err(err) => { return Err(err); }
+                Ok(text) => text
+            };
+            return match serde_json::from_str::<R>(&res_text) {
+                Err(err) => Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)),
+                Ok(ret) => Ok(ret)
+            };
+        }
+    }
+}
+
+// RPCを発行し、レスポンスをデシリアライズした結果を返す
+// 通信に失敗した場合、冪等なRPCであれば間隔を空けてリトライする
+// サーキットブレーカーと failure_detector には、リトライを含めた1回の呼び出しにつき1回だけ失敗を記録する
+// 連続して失敗しているピアへの呼び出しはサーキットブレーカーによって行われずにエラーとなる
+// (このエラーを受けた呼び出し元は、通常の通信失敗と同様に handle_downed_node_info で経路情報に反映する.
+//  いずれのエラーかは chord_util::is_unreachable_err で判定する)
+// タイムアウトは config の method ごとの設定に従う. 呼び出しごとに指定する場合は rpc_request_with_timeout を用いる
+async fn rpc_request<A: Serialize + Sync, R: DeserializeOwned>(vnode_address: &str, method: &str, arg: Option<&A>) -> Result<R, chord_util::GeneralError> {
+    return rpc_request_with_timeout::<A, R>(vnode_address, method, arg, config::get_rpc_timeout(method)).await;
+}
+
+async fn rpc_request_with_timeout<A: Serialize + Sync, R: DeserializeOwned>(vnode_address: &str, method: &str, arg: Option<&A>, timeout: config::RpcTimeout) -> Result<R, chord_util::GeneralError> {
+    let (physical_address, vnode_idx) = vnode::split_vnode_address(vnode_address);
+    let address = &physical_address;
+    let max_attempts = if is_idempotent_rpc(method) { config::get_config().rpc_retry_max + 1 } else { 1 };
+
+    let mut attempt: u32 = 0;
+    loop {
+        if circuit_breaker::allow_request(address) == false {
+            if attempt == 0 {
+                return Err(chord_util::GeneralError::new("circuit breaker is open: ".to_string() + address.as_str(), chord_util::ERR_CODE_CIRCUIT_BREAKER_OPEN));
+            }
+            // リトライの途中でopen状態となった (half-open状態の試行が失敗した場合を含む)
+            circuit_breaker::record_failure(address);
+            failure_detector::report_failure(address);
+            return Err(chord_util::GeneralError::new("circuit breaker is open: ".to_string() + address.as_str(), chord_util::ERR_CODE_CIRCUIT_BREAKER_OPEN));
+        }
+
+        match rpc_request_once::<A, R>(address, vnode_idx, method, arg, &timeout).await {
+            Err(err) => {
+                attempt += 1;
+                if attempt >= max_attempts {
+                    circuit_breaker::record_failure(address);
+                    failure_detector::report_failure(address);
+                    return Err(err);
+                }
+                chord_util::dprint(&("rpc_request_retry,".to_string() + address.as_str() + "," + method + "," + attempt.to_string().as_str()));
+                tokio::time::sleep(Duration::from_millis(calc_retry_backoff_millis(attempt))).await;
+            }
+            Ok(ret) => {
+                circuit_breaker::record_success(address);
+                failure_detector::record_heartbeat(address);
+                return Ok(ret);
+            }
+        };
+    }
+}
+
+// method はHTTPの場合のエンドポイントのパスと同じ文字列とする
+// rrpc_call__* から呼び出されるため、アドレスの型はそれらに合わせて &String とする
+#[allow(clippy::ptr_arg)]
+async fn rpc_post_request<A: Serialize + Sync, R: DeserializeOwned>(address: &String, method: &str, arg: &A) -> Result<R, chord_util::GeneralError> {
+    return rpc_request::<A, R>(address, method, Some(arg)).await;
+}
+
+// 引数の無いRPC用. HTTPの場合はGETリクエストとなる
+#[allow(clippy::ptr_arg)]
+async fn rpc_get_request<R: DeserializeOwned>(address: &String, method: &str) -> Result<R, chord_util::GeneralError> {
+    return rpc_request::<(), R>(address, method, None).await;
+}
+
+fn decode_rpc_arg<A: DeserializeOwned>(payload: &[u8]) -> Result<A, chord_util::GeneralError> {
+    return match bincode::deserialize::<A>(payload) {
+        Err(err) => Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)),
+        Ok(arg) => Ok(arg)
+    };
+}
+
+fn encode_rpc_ret<R: Serialize>(ret: &R) -> Result<Vec<u8>, chord_util::GeneralError> {
+    return match bincode::serialize(ret) {
+        Err(err) => Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)),
+        Ok(bytes) => Ok(bytes)
+    };
+}
+
+// バイナリプロトコルで受け付けたRPCを、HTTPの各エンドポイントと同じ処理に振り分ける
+// 戻り値はHTTPの場合にレスポンスとして返すものと同じ型の値をシリアライズしたもの
+pub async fn dispatch_binary_rpc(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::DataStore>, method: &str, payload: &[u8]) -> Result<Vec<u8>, chord_util::GeneralError> {
+    match method {
+        "check_predecessor" => {
+            let caller_node_ni = match decode_rpc_arg::<node_info::NodeInfoEntry>(payload) { Err(err) => { return Err(err); } Ok(arg) => arg };
+            return encode_rpc_ret(&stabilizer::check_predecessor(self_node, data_store, caller_node_ni).await);
+        }
+        "set_routing_infos_force" => {
+            let args = match decode_rpc_arg::<SetRoutingInfosForce>(payload) { Err(err) => { return Err(err); } Ok(arg) => arg };
+            stabilizer::set_routing_infos_force(self_node, data_store, args.predecessor_info, args.successor_info_0, args.ftable_enry_0).await;
+            return encode_rpc_ret(&Ok::<bool, chord_util::GeneralError>(true));
+        }
+        "find_successor" => {
+            let id = match decode_rpc_arg::<u32>(payload) { Err(err) => { return Err(err); } Ok(arg) => arg };
+            return encode_rpc_ret(&router::find_successor(self_node, id).await);
+        }
+        "find_successor_recursive" => {
+            let lookup = match decode_rpc_arg::<router::RecursiveLookup>(payload) { Err(err) => { return Err(err); } Ok(arg) => arg };
+            return encode_rpc_ret(&router::find_successor_recursive(self_node, lookup).await);
+        }
+        "closest_preceding_finger" => {
+            let id = match decode_rpc_arg::<u32>(payload) { Err(err) => { return Err(err); } Ok(arg) => arg };
+            return encode_rpc_ret(&router::closest_preceding_finger(self_node, id).await);
+        }
+        "put" => {
+            let args = match decode_rpc_arg::<Put>(payload) { Err(err) => { return Err(err); } Ok(arg) => arg };
+            return encode_rpc_ret(&chord_node::put(self_node, data_store, args.into_data_iv()));
+        }
+        "get" => {
+            let key_id = match decode_rpc_arg::<u32>(payload) { Err(err) => { return Err(err); } Ok(arg) => arg };
+            return encode_rpc_ret(&chord_node::get(self_node, data_store, key_id));
+        }
+        "pass_datas" => {
+            let pass_datas = match decode_rpc_arg::<Vec<chord_util::DataIdAndValue>>(payload) { Err(err) => { return Err(err); } Ok(arg) => arg };
+            return encode_rpc_ret(&stabilizer::pass_datas(self_node, data_store, pass_datas));
+        }
+        "receive_data_chunk" => {
+            let chunk = match decode_rpc_arg::<transfer::DataChunk>(payload) { Err(err) => { return Err(err); } Ok(arg) => arg };
+            return encode_rpc_ret(&transfer::receive_data_chunk(data_store, chunk));
+        }
+        "reindex_replicas" => {
+            let arg = match decode_rpc_arg::<transfer::ReindexReplicas>(payload) { Err(err) => { return Err(err); } Ok(arg) => arg };
+            return encode_rpc_ret(&transfer::reindex_replicas(data_store, arg));
+        }
+        "get_node_info" => {
+            return encode_rpc_ret(&chord_util::get_node_info(self_node));
+        }
+        "get_successor" => {
+            return encode_rpc_ret(&chord_util::get_successor(self_node));
+        }
+        "get_predecessor" => {
+            return encode_rpc_ret(&chord_util::get_predecessor(self_node));
+        }
+        "get_successor_list" => {
+            return encode_rpc_ret(&chord_util::get_successor_list(self_node));
+        }
+        "drop_namespace_datas" => {
+            let namespace_name = match decode_rpc_arg::<String>(payload) { Err(err) => { return Err(err); } Ok(arg) => arg };
+            return encode_rpc_ret(&namespace::drop_local_namespace_datas(data_store, namespace_name));
+        }
+        "get_namespace_usages" => {
+            return encode_rpc_ret(&namespace::get_local_namespace_usages(data_store));
+        }
+        "get_replica_locations" => {
+            return encode_rpc_ret(&placement::get_local_replica_locations(self_node, data_store));
+        }
+        "get_node_load" => {
+            return encode_rpc_ret(&rebalance::get_local_node_load(self_node, data_store));
+        }
+        "probe_peer" => {
+            let target_address = match decode_rpc_arg::<String>(payload) { Err(err) => { return Err(err); } Ok(arg) => arg };
+            return encode_rpc_ret(&failure_detector::probe_directly(&target_address).await);
+        }
+        "move_node_id" => {
+            let new_id = match decode_rpc_arg::<u32>(payload) { Err(err) => { return Err(err); } Ok(arg) => arg };
+            return encode_rpc_ret(&rebalance::move_node_id(self_node, data_store, new_id).await);
+        }
+        "get_export_page" => {
+            let req = match decode_rpc_arg::<bulk::ExportPageRequest>(payload) { Err(err) => { return Err(err); } Ok(arg) => arg };
+            return encode_rpc_ret(&bulk::get_local_export_page(data_store, req));
+        }
+        "import_batch" => {
+            let datas = match decode_rpc_arg::<Vec<chord_util::DataIdAndValue>>(payload) { Err(err) => { return Err(err); } Ok(arg) => arg };
+            return encode_rpc_ret(&bulk::import_local_batch(self_node, datas).await);
+        }
+        _ => {
+            return Err(chord_util::GeneralError::new("unknown rpc method: ".to_string() + method, chord_util::ERR_CODE_NOT_IMPLEMENTED));
+        }
+    }
+}
+
 #[get("/")]
-fn index() { //-> Json<node_info::NodeInfo> {
+async fn index(admin_access: AdminAccess) { //-> Json<node_info::NodeInfo> {
     // let mut node_info = node_info::NodeInfo::new();
     
     // node_info.node_id = 100;
@@ -104,22 +414,22 @@ fn index() { //-> Json<node_info::NodeInfo> {
 }
 
 #[get("/result-type")]
-fn result_type() -> Json<Result<node_info::NodeInfo, chord_util::GeneralError>> {
+async fn result_type(admin_access: AdminAccess) -> Json<Result<node_info::NodeInfo, chord_util::GeneralError>> {
     let mut node_info = node_info::NodeInfo::new();
     
     node_info.node_id = 100;
     node_info.address_str = "kanbayashi".to_string();
     node_info.born_id = 77;
     node_info.successor_info_list = vec![];
-    node_info.successor_info_list.push(node_info.clone());    
+    node_info.successor_info_list.push(node_info::gen_entry(&node_info));    
     node_info.predecessor_info = vec![];
-    node_info.predecessor_info.push(node_info::partial_clone_from_ref_strong(&node_info));
+    node_info.predecessor_info.push(node_info::gen_entry(&node_info));
 
     Json(Ok(node_info))
 }
 
 #[get("/get-param-test?<param1>&<param2>")]
-fn get_param_test(param1: String, param2: String) -> Json<node_info::NodeInfo> {
+async fn get_param_test(param1: String, param2: String, admin_access: AdminAccess) -> Json<node_info::NodeInfo> {
     let mut node_info = node_info::NodeInfo::new();
 
     println!("{:?}", param1);
@@ -129,29 +439,24 @@ fn get_param_test(param1: String, param2: String) -> Json<node_info::NodeInfo> {
     node_info.address_str = "kanbayashi".to_string();
     node_info.born_id = 77;
     node_info.successor_info_list = vec![];
-    node_info.successor_info_list.push(node_info.clone());    
+    node_info.successor_info_list.push(node_info::gen_entry(&node_info));    
     node_info.predecessor_info = vec![];
-    node_info.predecessor_info.push(node_info::partial_clone_from_ref_strong(&node_info));
+    node_info.predecessor_info.push(node_info::gen_entry(&node_info));
 
     Json(node_info)
 }
 
 #[post("/deserialize", data = "<node_info>")]
-pub fn deserialize_test(self_node: State<ArMu<node_info::NodeInfo>>, data_store: State<ArMu<data_store::DataStore>>, node_info: Json<node_info::NodeInfo>) -> String {
+pub async fn deserialize_test(self_node: &State<ArMu<node_info::NodeInfo>>, data_store: &State<ArMu<data_store::DataStore>>, node_info: Json<node_info::NodeInfo>, admin_access: AdminAccess) -> String {
     println!("{:?}", self_node.lock().unwrap());
     println!("{:?}", node_info.address_str);
     println!("{:?}", node_info);
     format!("Accepted post request! {:?}", node_info.0)
 }
 
-pub fn rrpc_call__check_predecessor(self_node: &node_info::NodeInfo, caller_node_ni: &node_info::NodeInfo) -> Result<bool, chord_util::GeneralError> {
-    let req_rslt = http_post_request(
-        &("http://".to_string() + self_node.address_str.as_str() + "/check_predecessor"),
-        match serde_json::to_string(caller_node_ni){
-            Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)) },
-            Ok(text) => text
-        });
-    
+pub async fn rrpc_call__check_predecessor(self_node: &node_info::NodeInfo, caller_node_ni: &node_info::NodeInfoEntry) -> Result<bool, chord_util::GeneralError> {
+    let req_rslt = rpc_post_request::<node_info::NodeInfoEntry, Result<bool, chord_util::GeneralError>>(&self_node.address_str, "check_predecessor", caller_node_ni).await;
+
     match req_rslt {
         Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)) },
         Ok(resp) => { return Ok(true) }
@@ -159,43 +464,29 @@ pub fn rrpc_call__check_predecessor(self_node: &node_info::NodeInfo, caller_node
 }
 
 #[post("/check_predecessor", data = "<caller_node_ni>")]
-pub fn rrpc__check_predecessor(self_node: State<ArMu<node_info::NodeInfo>>, data_store: State<ArMu<data_store::DataStore>>, caller_node_ni: Json<node_info::NodeInfo>) -> Json<Result<bool, chord_util::GeneralError>> {
-    return Json(stabilizer::check_predecessor(Arc::clone(&self_node), Arc::clone(&data_store), caller_node_ni.0));
+pub async fn rrpc__check_predecessor(self_node: TargetVnode, data_store: &State<ArMu<data_store::DataStore>>, caller_node_ni: Json<node_info::NodeInfoEntry>, cluster_member: ClusterMember) -> Json<Result<bool, chord_util::GeneralError>> {
+    return Json(stabilizer::check_predecessor(Arc::clone(&self_node), Arc::clone(data_store), caller_node_ni.0).await);
 }
 
-pub fn rrpc_call__set_routing_infos_force(self_node: &node_info::NodeInfo, predecessor_info: node_info::NodeInfo, successor_info_0: node_info::NodeInfo , ftable_enry_0: node_info::NodeInfo) -> Result<bool, chord_util::GeneralError> {
+pub async fn rrpc_call__set_routing_infos_force(self_node: &node_info::NodeInfo, predecessor_info: node_info::NodeInfoEntry, successor_info_0: node_info::NodeInfoEntry , ftable_enry_0: node_info::NodeInfoEntry) -> Result<bool, chord_util::GeneralError> {
     let rpc_arg = SetRoutingInfosForce::new(predecessor_info, successor_info_0, ftable_enry_0);
 
-    let req_rslt = http_post_request(
-        &("http://".to_string() + self_node.address_str.as_str() + "/set_routing_infos_force"),
-        match serde_json::to_string(&rpc_arg){
-            Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)) },
-            Ok(text) => text
-        });
+    let req_rslt = rpc_post_request::<SetRoutingInfosForce, Result<bool, chord_util::GeneralError>>(&self_node.address_str, "set_routing_infos_force", &rpc_arg).await;
 
     return Ok(true);
 }
 
 #[post("/set_routing_infos_force", data = "<rpc_args>")]
-pub fn rrpc__set_routing_infos_force(self_node: State<ArMu<node_info::NodeInfo>>, rpc_args: Json<SetRoutingInfosForce>){
+pub async fn rrpc__set_routing_infos_force(self_node: TargetVnode, data_store: &State<ArMu<data_store::DataStore>>, rpc_args: Json<SetRoutingInfosForce>, cluster_member: ClusterMember) -> Json<Result<bool, chord_util::GeneralError>> {
     let args = rpc_args.0;
-    return stabilizer::set_routing_infos_force(Arc::clone(&self_node), args.predecessor_info, args.successor_info_0, args.ftable_enry_0);
+    stabilizer::set_routing_infos_force(Arc::clone(&self_node), Arc::clone(data_store), args.predecessor_info, args.successor_info_0, args.ftable_enry_0).await;
+    return Json(Ok(true));
 }
 
-pub fn rrpc_call__find_successor(self_node: &node_info::NodeInfo, id : u32) -> Result<node_info::NodeInfoSummary, chord_util::GeneralError> {
-    let req_rslt = http_post_request(
-        &("http://".to_string() + self_node.address_str.as_str() + "/find_successor"),
-        match serde_json::to_string(&id){
-            Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)) },
-            Ok(text) => text
-        });
-    
-    let req_rslt_ref = &(match req_rslt{
-        Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)) },
-        Ok(ninfo) => ninfo
-    });
+pub async fn rrpc_call__find_successor(self_node: &node_info::NodeInfo, id : u32) -> Result<node_info::NodeInfoSummary, chord_util::GeneralError> {
+    let req_rslt = rpc_post_request::<u32, Result<node_info::NodeInfoSummary, chord_util::GeneralError>>(&self_node.address_str, "find_successor", &id).await;
 
-    let ret_ninfo = match serde_json::from_str::<Result<node_info::NodeInfoSummary, chord_util::GeneralError>>(req_rslt_ref){
+    let ret_ninfo = match req_rslt{
         Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)) },
         Ok(ninfo) => ninfo
     };
@@ -205,150 +496,83 @@ pub fn rrpc_call__find_successor(self_node: &node_info::NodeInfo, id : u32) -> R
 
 // idで識別されるデータを担当するノードの名前解決を行う
 #[post("/find_successor", data = "<id>")]
-pub fn rrpc__find_successor(self_node: State<ArMu<node_info::NodeInfo>>, id : Json<u32>) -> Json<Result<node_info::NodeInfoSummary, chord_util::GeneralError>> {
-    return Json(router::find_successor(Arc::clone(&self_node), id.0));
+pub async fn rrpc__find_successor(self_node: TargetVnode, id : Json<u32>, cluster_member: ClusterMember) -> Json<Result<node_info::NodeInfoSummary, chord_util::GeneralError>> {
+    return Json(router::find_successor(Arc::clone(&self_node), id.0).await);
 }
 
-pub fn rrpc_call__closest_preceding_finger(self_node: &node_info::NodeInfoSummary, id : u32) -> Result<node_info::NodeInfoSummary, chord_util::GeneralError> {
-    let req_rslt = http_post_request(
-        &("http://".to_string() + self_node.address_str.as_str() + "/closest_preceding_finger"),
-        match serde_json::to_string(&id){
-            Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)) },
-            Ok(text) => text
-        });
-    
-    let res_text = match req_rslt {
-        Err(err) => {
-            return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR));
-        }
-        Ok(text) => {text}
-    };
-
-    let ret_ninfo = match match serde_json::from_str::<Result<node_info::NodeInfoSummary, chord_util::GeneralError>>(&res_text){
-        Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR))},
-        Ok(result_ninfo) => result_ninfo
-    }{
-        Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR))},
-        Ok(ninfo) => ninfo
+// timeout_millis は転送元の lookup の残り時間. 接続のタイムアウトもこれを超えないようにする
+pub async fn rrpc_call__find_successor_recursive(self_node: &node_info::NodeInfoSummary, lookup: &router::RecursiveLookup, timeout_millis: u64) -> Result<router::RecursiveLookupResult, chord_util::GeneralError> {
+    let conf_timeout = config::get_rpc_timeout("find_successor_recursive");
+    let timeout = config::RpcTimeout::new(std::cmp::min(conf_timeout.connect_millis, timeout_millis), timeout_millis);
+    let req_rslt = rpc_request_with_timeout::<router::RecursiveLookup, Result<router::RecursiveLookupResult, chord_util::GeneralError>>(&self_node.address_str, "find_successor_recursive", Some(lookup), timeout).await;
+    return match req_rslt {
+        Err(err) => Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)),
+        Ok(result) => result
     };
-
-    return Ok(ret_ninfo);
 }
 
-#[post("/closest_preceding_finger", data = "<id>")]
-pub fn rrpc__closest_preceding_finger(self_node: State<ArMu<node_info::NodeInfo>>, id : Json<u32>) -> Json<Result<node_info::NodeInfoSummary, chord_util::GeneralError>> {
-    return Json(router::closest_preceding_finger(Arc::clone(&self_node), id.0));
+// 再帰モードの名前解決. 担当ノードが分からなければ次のノードへ転送する
+#[post("/find_successor_recursive", data = "<lookup>")]
+pub async fn rrpc__find_successor_recursive(self_node: TargetVnode, lookup: Json<router::RecursiveLookup>, cluster_member: ClusterMember) -> Json<Result<router::RecursiveLookupResult, chord_util::GeneralError>> {
+    return Json(router::find_successor_recursive(Arc::clone(&self_node), lookup.0).await);
 }
 
-pub fn rrpc_call__global_put(self_node: &node_info::NodeInfo, key_str: String, val_str: String) -> Result<bool, chord_util::GeneralError> {
-    let rpc_arg = GlobalPut::new(key_str, val_str);
+pub async fn rrpc_call__closest_preceding_finger(self_node: &node_info::NodeInfoSummary, id : u32) -> Result<node_info::NodeInfoSummary, chord_util::GeneralError> {
+    let req_rslt = rpc_post_request::<u32, Result<node_info::NodeInfoSummary, chord_util::GeneralError>>(&self_node.address_str, "closest_preceding_finger", &id).await;
 
-    let req_rslt = http_post_request(
-        &("http://".to_string() + self_node.address_str.as_str() + "/global_put"),
-        match serde_json::to_string(&rpc_arg){
-            Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)) },
-            Ok(text) => text
-        });
-    
-    let res_text = match req_rslt {
-        Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR))},
-        Ok(text) => text
+    // 問い合わせ先で発生したエラー (successor が分からない場合など) はエラーコードをそのまま返す
+    return match req_rslt {
+        Err(err) => Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)),
+        Ok(result_ninfo) => result_ninfo
     };
-
-    match match serde_json::from_str::<Result<bool, chord_util::GeneralError>>(&res_text){
-        Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR))},
-        Ok(result) => result
-    }{
-        Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR))},
-        Ok(is_exist) => { return Ok(is_exist); }
-    }
 }
 
-#[post("/global_put", data = "<rpc_args>")]
-pub fn rrpc__global_put(self_node: State<ArMu<node_info::NodeInfo>>, data_store: State<ArMu<data_store::DataStore>>, rpc_args: Json<GlobalPut>) -> Json<Result<bool, chord_util::GeneralError>> {
-    return Json(chord_node::global_put(Arc::clone(&self_node), Arc::clone(&data_store), rpc_args.0.key_str, rpc_args.0.val_str));
+#[post("/closest_preceding_finger", data = "<id>")]
+pub async fn rrpc__closest_preceding_finger(self_node: TargetVnode, id : Json<u32>, cluster_member: ClusterMember) -> Json<Result<node_info::NodeInfoSummary, chord_util::GeneralError>> {
+    return Json(router::closest_preceding_finger(Arc::clone(&self_node), id.0).await);
 }
 
-pub fn rrpc_call__put(self_node: &node_info::NodeInfo, key_id: u32, val_str: String) -> Result<bool, chord_util::GeneralError> {
-    let rpc_arg = Put::new(key_id, val_str);
+// ns を省略した場合はデフォルトのネームスペースとなる (global_get, global_delete も同様)
+// global_get, global_delete の rf には、書き込み時にネームスペースと異なるレプリケーションファクタを指定した場合にその値を渡す
+#[post("/global_put?<ns>", data = "<rpc_args>")]
+pub async fn rrpc__global_put(self_node: &State<ArMu<node_info::NodeInfo>>, data_store: &State<ArMu<data_store::DataStore>>, rpc_args: Json<GlobalPut>, ns: Option<String>, write_access: WriteAccess) -> Json<Result<chord_util::PutResult, chord_util::GeneralError>> {
+    let args = rpc_args.0;
+    return Json(chord_node::global_put(Arc::clone(self_node), Arc::clone(data_store), namespace::resolve_request_namespace(ns), args.key_str, args.val_str, args.ttl_sec, args.replication_factor).await);
+}
 
-    let req_rslt = http_post_request(
-        &("http://".to_string() + self_node.address_str.as_str() + "/put"),
-        match serde_json::to_string(&rpc_arg){
-            Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)) },
-            Ok(text) => text
-        });
-    
-    let res_text = match req_rslt {
-        Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR))},
-        Ok(text) => text
-    };
+pub async fn rrpc_call__put(self_node: &node_info::NodeInfo, rpc_arg: Put) -> Result<bool, chord_util::GeneralError> {
+    let req_rslt = rpc_post_request::<Put, Result<bool, chord_util::GeneralError>>(&self_node.address_str, "put", &rpc_arg).await;
 
-    match match serde_json::from_str::<Result<bool, chord_util::GeneralError>>(&res_text){
+    // 問い合わせ先で発生したエラー (担当範囲外など) はそのまま返し、通信のエラーと区別できるようにする
+    // (通信のエラー以外で handle_downed_node_info によりノードダウンとして扱われないようにするため)
+    match match req_rslt {
         Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR))},
         Ok(result) => result
     }{
-        Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR))},
+        Err(err) => { return Err(err) },
         Ok(is_exist) => { return Ok(is_exist) }
     }
 }
 
 #[post("/put", data = "<rpc_args>")]
-pub fn rrpc__put(self_node: State<ArMu<node_info::NodeInfo>>, data_store: State<ArMu<data_store::DataStore>>, rpc_args: Json<Put>) -> Json<Result<bool, chord_util::GeneralError>> {
-    return Json(chord_node::put(Arc::clone(&self_node), Arc::clone(&data_store), rpc_args.0.key_id, rpc_args.0.val_str));
+pub async fn rrpc__put(self_node: TargetVnode, data_store: &State<ArMu<data_store::DataStore>>, rpc_args: Json<Put>, cluster_member: ClusterMember) -> Json<Result<bool, chord_util::GeneralError>> {
+    return Json(chord_node::put(Arc::clone(&self_node), Arc::clone(data_store), rpc_args.0.into_data_iv()));
 }
 
-pub fn rrpc_call__global_get(self_node: &node_info::NodeInfo, key_str: String) -> Result<chord_util::DataIdAndValue, chord_util::GeneralError> {
-    let req_rslt = http_post_request(
-        &("http://".to_string() + self_node.address_str.as_str() + "/global_get"),
-        match serde_json::to_string(&key_str){
-            Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)) },
-            Ok(text) => text
-        });
-    
-    let res_text = match req_rslt {
-        Err(err) => {
-            return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR));
-        }
-        Ok(text) => {text}
-    };
-
-    let ret_iv = match match serde_json::from_str::<Result<chord_util::DataIdAndValue, chord_util::GeneralError>>(&res_text){
-        Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR))},
-        Ok(result_iv) => result_iv
-    }{
-        Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR))},
-        Ok(data_iv) => data_iv
-    };
-
-    return Ok(ret_iv);
+#[post("/global_get?<ns>&<rf>", data = "<key_str>")]
+pub async fn rrpc__global_get(self_node: &State<ArMu<node_info::NodeInfo>>, data_store: &State<ArMu<data_store::DataStore>>, key_str: Json<String>, ns: Option<String>, rf: Option<u32>, read_access: ReadAccess) -> Json<Result<chord_util::DataIdAndValue, chord_util::GeneralError>> {
+    return Json(chord_node::global_get(Arc::clone(self_node), Arc::clone(data_store), namespace::resolve_request_namespace(ns), key_str.0, rf).await);
 }
 
-#[post("/global_get", data = "<key_str>")]
-pub fn rrpc__global_get(self_node: State<ArMu<node_info::NodeInfo>>, data_store: State<ArMu<data_store::DataStore>>, key_str: Json<String>) -> Json<Result<chord_util::DataIdAndValue, chord_util::GeneralError>> {
-    return Json(chord_node::global_get(Arc::clone(&self_node), Arc::clone(&data_store), key_str.0));
-}
+pub async fn rrpc_call__get(self_node: &node_info::NodeInfo, key_id: u32) -> Result<chord_util::DataIdAndValue, chord_util::GeneralError> {
+    let req_rslt = rpc_post_request::<u32, Result<chord_util::DataIdAndValue, chord_util::GeneralError>>(&self_node.address_str, "get", &key_id).await;
 
-pub fn rrpc_call__get(self_node: &node_info::NodeInfo, key_id: u32) -> Result<chord_util::DataIdAndValue, chord_util::GeneralError> {
-    let req_rslt = http_post_request(
-        &("http://".to_string() + self_node.address_str.as_str() + "/get"),
-        match serde_json::to_string(&key_id){
-            Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)) },
-            Ok(text) => text
-        });
-    
-    let res_text = match req_rslt {
-        Err(err) => {
-            return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR));
-        }
-        Ok(text) => {text}
-    };
-
-    let ret_iv = match match serde_json::from_str::<Result<chord_util::DataIdAndValue, chord_util::GeneralError>>(&res_text){
+    // データが存在しない場合などのエラーはそのまま返す (rrpc_call__put と同様)
+    let ret_iv = match match req_rslt {
         Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR))},
         Ok(result_iv) => result_iv
     }{
-        Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR))},
+        Err(err) => { return Err(err) },
         Ok(data_iv) => data_iv
     };
 
@@ -356,26 +580,14 @@ pub fn rrpc_call__get(self_node: &node_info::NodeInfo, key_id: u32) -> Result<ch
 }
 
 #[post("/get", data = "<key_id>")]
-pub fn rrpc__get(self_node: State<ArMu<node_info::NodeInfo>>, data_store: State<ArMu<data_store::DataStore>>, key_id: Json<u32>) -> Json<Result<chord_util::DataIdAndValue, chord_util::GeneralError>> {
-    return Json(chord_node::get(Arc::clone(&self_node), Arc::clone(&data_store), key_id.0));
+pub async fn rrpc__get(self_node: TargetVnode, data_store: &State<ArMu<data_store::DataStore>>, key_id: Json<u32>, cluster_member: ClusterMember) -> Json<Result<chord_util::DataIdAndValue, chord_util::GeneralError>> {
+    return Json(chord_node::get(Arc::clone(&self_node), Arc::clone(data_store), key_id.0));
 }
 
-pub fn rrpc_call__pass_datas(self_node: &node_info::NodeInfo, pass_datas: Vec<chord_util::DataIdAndValue>) -> Result<bool, chord_util::GeneralError> {
-    let req_rslt = http_post_request(
-        &("http://".to_string() + self_node.address_str.as_str() + "/pass_datas"),
-        match serde_json::to_string(&pass_datas){
-            Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)) },
-            Ok(text) => text
-        });
-    
-    let res_text = match req_rslt {
-        Err(err) => {
-            return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR));
-        }
-        Ok(text) => {text}
-    };
+pub async fn rrpc_call__pass_datas(self_node: &node_info::NodeInfo, pass_datas: Vec<chord_util::DataIdAndValue>) -> Result<bool, chord_util::GeneralError> {
+    let req_rslt = rpc_post_request::<Vec<chord_util::DataIdAndValue>, Result<bool, chord_util::GeneralError>>(&self_node.address_str, "pass_datas", &pass_datas).await;
 
-    let ret_bool = match match serde_json::from_str::<Result<bool, chord_util::GeneralError>>(&res_text){
+    let ret_bool = match match req_rslt {
         Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR))},
         Ok(result) => result
     }{
@@ -387,67 +599,85 @@ pub fn rrpc_call__pass_datas(self_node: &node_info::NodeInfo, pass_datas: Vec<ch
 }
 
 #[post("/pass_datas", data = "<pass_datas>")]
-pub fn rrpc__pass_datas(self_node: State<ArMu<node_info::NodeInfo>>, data_store: State<ArMu<data_store::DataStore>>, pass_datas: Json<Vec<chord_util::DataIdAndValue>>) -> Json<Result<bool, chord_util::GeneralError>> {
-    return Json(stabilizer::pass_datas(Arc::clone(&self_node), Arc::clone(&data_store), pass_datas.0));
+pub async fn rrpc__pass_datas(self_node: TargetVnode, data_store: &State<ArMu<data_store::DataStore>>, pass_datas: Json<Vec<chord_util::DataIdAndValue>>, cluster_member: ClusterMember) -> Json<Result<bool, chord_util::GeneralError>> {
+    return Json(stabilizer::pass_datas(Arc::clone(&self_node), Arc::clone(data_store), pass_datas.0));
 }
 
-pub fn rrpc_call__global_delete(self_node: &node_info::NodeInfo, key_str: String) -> Result<bool, chord_util::GeneralError> {
-    let req_rslt = http_post_request(
-        &("http://".to_string() + self_node.address_str.as_str() + "/global_delete"),
-        match serde_json::to_string(&key_str){
-            Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)) },
-            Ok(text) => text
-        });
-    
-    let res_text = match req_rslt {
-        Err(err) => {
-            return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR));
-        }
-        Ok(text) => {text}
+// 受け手が返した ack (チャンクの通し番号) を返す
+pub async fn rrpc_call__receive_data_chunk(self_node: &node_info::NodeInfo, chunk: &transfer::DataChunk) -> Result<u32, chord_util::GeneralError> {
+    let req_rslt = rpc_post_request::<transfer::DataChunk, Result<u32, chord_util::GeneralError>>(&self_node.address_str, "receive_data_chunk", chunk).await;
+
+    return match req_rslt {
+        Err(err) => Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)),
+        Ok(result) => result
     };
+}
 
-    let is_exist = match match serde_json::from_str::<Result<bool, chord_util::GeneralError>>(&res_text){
-        Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR))},
-        Ok(result_bool) => result_bool
-    }{
-        Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR))},
-        Ok(wrapped_bool) => wrapped_bool
+#[post("/receive_data_chunk", data = "<chunk>")]
+pub async fn rrpc__receive_data_chunk(data_store: &State<ArMu<data_store::DataStore>>, chunk: Json<transfer::DataChunk>, cluster_member: ClusterMember) -> Json<Result<u32, chord_util::GeneralError>> {
+    return Json(transfer::receive_data_chunk(Arc::clone(data_store), chunk.0));
+}
+
+pub async fn rrpc_call__reindex_replicas(address: &String, arg: &transfer::ReindexReplicas) -> Result<u32, chord_util::GeneralError> {
+    let req_rslt = rpc_post_request::<transfer::ReindexReplicas, Result<u32, chord_util::GeneralError>>(address, "reindex_replicas", arg).await;
+
+    return match req_rslt {
+        Err(err) => Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)),
+        Ok(result) => result
     };
+}
 
-    return Ok(is_exist);
+// successor_list モードで担当ノードが変わったデータのレプリカの位置を付け直す
+#[post("/reindex_replicas", data = "<arg>")]
+pub async fn rrpc__reindex_replicas(data_store: &State<ArMu<data_store::DataStore>>, arg: Json<transfer::ReindexReplicas>, cluster_member: ClusterMember) -> Json<Result<u32, chord_util::GeneralError>> {
+    return Json(transfer::reindex_replicas(Arc::clone(data_store), arg.0));
 }
 
-#[post("/global_delete", data = "<key_str>")]
-pub fn rrpc__global_delete(self_node: State<ArMu<node_info::NodeInfo>>, data_store: State<ArMu<data_store::DataStore>>, key_str: Json<String>) -> Json<Result<bool, chord_util::GeneralError>> {
-    return Json(chord_node::global_delete(Arc::clone(&self_node), Arc::clone(&data_store), key_str.0));
+#[post("/global_delete?<ns>&<rf>", data = "<key_str>")]
+pub async fn rrpc__global_delete(self_node: &State<ArMu<node_info::NodeInfo>>, data_store: &State<ArMu<data_store::DataStore>>, key_str: Json<String>, ns: Option<String>, rf: Option<u32>, write_access: WriteAccess) -> Json<Result<bool, chord_util::GeneralError>> {
+    return Json(chord_node::global_delete(Arc::clone(self_node), Arc::clone(data_store), namespace::resolve_request_namespace(ns), key_str.0, rf).await);
 }
 
 // ブラウザから試すためのエンドポイント
-#[get("/global_put_simple?<key>&<val>")]
-pub fn rrpc__global_put_simple(self_node: State<ArMu<node_info::NodeInfo>>, data_store: State<ArMu<data_store::DataStore>>, key: String, val: String) -> Json<Result<bool, chord_util::GeneralError>> {
-    return Json(chord_node::global_put(Arc::clone(&self_node), Arc::clone(&data_store), key, val));
+#[get("/global_put_simple?<key>&<val>&<ns>&<ttl_sec>&<rf>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn rrpc__global_put_simple(self_node: &State<ArMu<node_info::NodeInfo>>, data_store: &State<ArMu<data_store::DataStore>>, key: String, val: String, ns: Option<String>, ttl_sec: Option<u64>, rf: Option<u32>, write_access: WriteAccess) -> Json<Result<chord_util::PutResult, chord_util::GeneralError>> {
+    return Json(chord_node::global_put(Arc::clone(self_node), Arc::clone(data_store), namespace::resolve_request_namespace(ns), key, val, ttl_sec, rf).await);
 }
 
 // ブラウザから試すためのエンドポイント
-#[get("/global_get_simple?<key>")]
-pub fn rrpc__global_get_simple(self_node: State<ArMu<node_info::NodeInfo>>, data_store: State<ArMu<data_store::DataStore>>, key: String) -> Json<Result<chord_util::DataIdAndValue, chord_util::GeneralError>> {
-    return Json(chord_node::global_get(Arc::clone(&self_node), Arc::clone(&data_store), key));
+#[get("/global_get_simple?<key>&<ns>&<rf>")]
+pub async fn rrpc__global_get_simple(self_node: &State<ArMu<node_info::NodeInfo>>, data_store: &State<ArMu<data_store::DataStore>>, key: String, ns: Option<String>, rf: Option<u32>, read_access: ReadAccess) -> Json<Result<chord_util::DataIdAndValue, chord_util::GeneralError>> {
+    return Json(chord_node::global_get(Arc::clone(self_node), Arc::clone(data_store), namespace::resolve_request_namespace(ns), key, rf).await);
 }
 
 // ブラウザから試すためのエンドポイント
-#[get("/global_delete_simple?<key>")]
-pub fn rrpc__global_delete_simple(self_node: State<ArMu<node_info::NodeInfo>>, data_store: State<ArMu<data_store::DataStore>>, key: String) -> Json<Result<bool, chord_util::GeneralError>> {
-    return Json(chord_node::global_delete(Arc::clone(&self_node), Arc::clone(&data_store), key));
+#[get("/global_delete_simple?<key>&<ns>&<rf>")]
+pub async fn rrpc__global_delete_simple(self_node: &State<ArMu<node_info::NodeInfo>>, data_store: &State<ArMu<data_store::DataStore>>, key: String, ns: Option<String>, rf: Option<u32>, write_access: WriteAccess) -> Json<Result<bool, chord_util::GeneralError>> {
+    return Json(chord_node::global_delete(Arc::clone(self_node), Arc::clone(data_store), namespace::resolve_request_namespace(ns), key, rf).await);
 }
 
-pub fn rrpc_call__get_node_info(address : &String) -> Result<node_info::NodeInfo, GeneralError> {
-    let req_rslt = http_get_request(&("http://".to_string() + address.as_str() + "/get_node_info"));
-    let ret_ninfo = match serde_json::from_str::<node_info::NodeInfo>(&(
-        match req_rslt{
-            Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)) },
-            Ok(text) => text
-        }
-    )){
+// 名前解決を行い、経由したノードと各ノードでの所要時間を返す
+// key (ns で指定したネームスペースのキー) か id のどちらかを指定する. mode には "iterative" か "recursive" を指定でき、省略時は config の lookup_mode となる
+#[get("/trace_lookup?<key>&<ns>&<id>&<mode>")]
+pub async fn rrpc__trace_lookup(self_node: &State<ArMu<node_info::NodeInfo>>, key: Option<String>, ns: Option<String>, id: Option<u32>, mode: Option<String>, read_access: ReadAccess) -> Json<Result<router::LookupTrace, chord_util::GeneralError>> {
+    let lookup_mode = match mode.as_deref() {
+        None => config::get_lookup_mode(),
+        Some("iterative") => config::LookupMode::Iterative,
+        Some("recursive") => config::LookupMode::Recursive,
+        Some(other) => { return Json(Err(chord_util::GeneralError::new("unknown lookup mode: ".to_string() + other, chord_util::ERR_CODE_INVALID_ARGUMENT))); }
+    };
+    let target_id = match (id, key) {
+        (Some(id), _) => id,
+        (None, Some(key)) => chord_util::hash_key_in_namespace(namespace::resolve_request_namespace(ns).as_str(), &key),
+        (None, None) => { return Json(Err(chord_util::GeneralError::new("key or id is required".to_string(), chord_util::ERR_CODE_INVALID_ARGUMENT))); }
+    };
+    return Json(router::trace_lookup(Arc::clone(self_node), target_id, lookup_mode).await);
+}
+
+pub async fn rrpc_call__get_node_info(address : &String) -> Result<node_info::NodeInfo, GeneralError> {
+    let req_rslt = rpc_get_request::<node_info::NodeInfo>(address, "get_node_info").await;
+    let ret_ninfo = match req_rslt {
         Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)) },
         Ok(ninfo) => ninfo
     };
@@ -456,76 +686,374 @@ pub fn rrpc_call__get_node_info(address : &String) -> Result<node_info::NodeInfo
 }
 
 #[get("/get_node_info")]
-pub fn rrpc__get_node_info(self_node: State<ArMu<node_info::NodeInfo>>) -> Json<node_info::NodeInfo> {
+pub async fn rrpc__get_node_info(self_node: TargetVnode, cluster_member: ClusterMember) -> Json<node_info::NodeInfo> {
     return Json(chord_util::get_node_info(Arc::clone(&self_node)));
 }
 
+// address のノードの successor_info_list の先頭を得る
+pub async fn rrpc_call__get_successor(address : &String) -> Result<node_info::NodeInfoEntry, GeneralError> {
+    let req_rslt = rpc_get_request::<Result<node_info::NodeInfoEntry, GeneralError>>(address, "get_successor").await;
+    return match req_rslt {
+        Err(err) => Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)),
+        Ok(succ_info) => succ_info
+    };
+}
+
+#[get("/get_successor")]
+pub async fn rrpc__get_successor(self_node: TargetVnode, cluster_member: ClusterMember) -> Json<Result<node_info::NodeInfoEntry, GeneralError>> {
+    return Json(chord_util::get_successor(Arc::clone(&self_node)));
+}
+
+// address のノードの predecessor を得る. 未設定の場合は None
+pub async fn rrpc_call__get_predecessor(address : &String) -> Result<Option<node_info::NodeInfoEntry>, GeneralError> {
+    let req_rslt = rpc_get_request::<Option<node_info::NodeInfoEntry>>(address, "get_predecessor").await;
+    return match req_rslt {
+        Err(err) => Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)),
+        Ok(pred_info) => Ok(pred_info)
+    };
+}
+
+#[get("/get_predecessor")]
+pub async fn rrpc__get_predecessor(self_node: TargetVnode, cluster_member: ClusterMember) -> Json<Option<node_info::NodeInfoEntry>> {
+    return Json(chord_util::get_predecessor(Arc::clone(&self_node)));
+}
+
+pub async fn rrpc_call__get_successor_list(address : &String) -> Result<Vec<node_info::NodeInfoEntry>, GeneralError> {
+    let req_rslt = rpc_get_request::<Vec<node_info::NodeInfoEntry>>(address, "get_successor_list").await;
+    return match req_rslt {
+        Err(err) => Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)),
+        Ok(succ_list) => Ok(succ_list)
+    };
+}
+
+#[get("/get_successor_list")]
+pub async fn rrpc__get_successor_list(self_node: TargetVnode, cluster_member: ClusterMember) -> Json<Vec<node_info::NodeInfoEntry>> {
+    return Json(chord_util::get_successor_list(Arc::clone(&self_node)));
+}
+
+// ノード間RPCのプロトコルのネゴシエーションに用いる
+#[get("/get_rpc_protocol_info")]
+pub async fn rrpc__get_rpc_protocol_info(cluster_member: ClusterMember) -> Json<tcp_rpc::RpcProtocolInfo> {
+    return Json(tcp_rpc::get_rpc_protocol_info());
+}
+
+pub async fn rrpc_call__drop_namespace_datas(address: &String, namespace_name: String) -> Result<u64, chord_util::GeneralError> {
+    let req_rslt = rpc_post_request::<String, Result<u64, chord_util::GeneralError>>(address, "drop_namespace_datas", &namespace_name).await;
+
+    let removed_count = match match req_rslt {
+        Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR))},
+        Ok(result) => result
+    }{
+        Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR))},
+        Ok(count) => count
+    };
+
+    return Ok(removed_count);
+}
+
+#[post("/drop_namespace_datas", data = "<namespace_name>")]
+pub async fn rrpc__drop_namespace_datas(data_store: &State<ArMu<data_store::DataStore>>, namespace_name: Json<String>, cluster_member: ClusterMember) -> Json<Result<u64, chord_util::GeneralError>> {
+    return Json(namespace::drop_local_namespace_datas(Arc::clone(data_store), namespace_name.0));
+}
+
+pub async fn rrpc_call__get_namespace_usages(address: &String) -> Result<HashMap<String, data_store::NamespaceUsage>, chord_util::GeneralError> {
+    let req_rslt = rpc_get_request::<HashMap<String, data_store::NamespaceUsage>>(address, "get_namespace_usages").await;
+    return match req_rslt {
+        Err(err) => Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)),
+        Ok(usages) => Ok(usages)
+    };
+}
+
+#[get("/get_namespace_usages")]
+pub async fn rrpc__get_namespace_usages(data_store: &State<ArMu<data_store::DataStore>>, cluster_member: ClusterMember) -> Json<HashMap<String, data_store::NamespaceUsage>> {
+    return Json(namespace::get_local_namespace_usages(Arc::clone(data_store)));
+}
+
+#[post("/create_namespace", data = "<ns_conf>")]
+pub async fn rrpc__create_namespace(self_node: &State<ArMu<node_info::NodeInfo>>, ns_conf: Json<namespace::NamespaceConfig>, admin_access: AdminAccess) -> Json<Result<bool, chord_util::GeneralError>> {
+    return Json(namespace::create_namespace(Arc::clone(self_node), ns_conf.0).await);
+}
+
+// 作成されたネームスペースの一覧を返す. デフォルトのネームスペースは含まない
+#[get("/list_namespaces")]
+pub async fn rrpc__list_namespaces(self_node: &State<ArMu<node_info::NodeInfo>>, read_access: ReadAccess) -> Json<Result<Vec<namespace::NamespaceConfig>, chord_util::GeneralError>> {
+    return Json(namespace::list_namespaces(Arc::clone(self_node)).await);
+}
+
+// ネームスペースを削除し、そのネームスペースのデータをリング上の全ノードから削除する
+#[post("/drop_namespace", data = "<namespace_name>")]
+pub async fn rrpc__drop_namespace(self_node: &State<ArMu<node_info::NodeInfo>>, namespace_name: Json<String>, admin_access: AdminAccess) -> Json<Result<namespace::DropNamespaceResult, chord_util::GeneralError>> {
+    return Json(namespace::drop_namespace(Arc::clone(self_node), namespace_name.0).await);
+}
+
+pub async fn rrpc_call__get_replica_locations(address: &String) -> Result<placement::NodeReplicaLocations, chord_util::GeneralError> {
+    let req_rslt = rpc_get_request::<placement::NodeReplicaLocations>(address, "get_replica_locations").await;
+    return match req_rslt {
+        Err(err) => Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)),
+        Ok(locations) => Ok(locations)
+    };
+}
+
+#[get("/get_replica_locations")]
+pub async fn rrpc__get_replica_locations(self_node: TargetVnode, data_store: &State<ArMu<data_store::DataStore>>, cluster_member: ClusterMember) -> Json<placement::NodeReplicaLocations> {
+    return Json(placement::get_local_replica_locations(Arc::clone(&self_node), Arc::clone(data_store)));
+}
+
+// コピーが min_replica_zones 個以上のゾーンにまたがっていないデータの一覧を返す
+#[get("/replica_placement_report")]
+pub async fn rrpc__replica_placement_report(self_node: &State<ArMu<node_info::NodeInfo>>, admin_access: AdminAccess) -> Json<Result<placement::PlacementReport, chord_util::GeneralError>> {
+    return Json(placement::build_placement_report(Arc::clone(self_node)).await);
+}
+
+// このプロセスの各仮想ノードの stabilize処理の現在の実行間隔 (監視用)
+#[get("/stabilize_rate")]
+pub async fn rrpc__stabilize_rate(admin_access: AdminAccess) -> Json<Result<Vec<stabilize_rate::StabilizeRate>, chord_util::GeneralError>> {
+    return Json(Ok(stabilize_rate::get_stabilize_rates()));
+}
+
+// stabilize処理のタスクの状態と、各タスクの処理の回数、エラーの回数
+#[get("/stabilizer_status")]
+pub async fn rrpc__stabilizer_status(admin_access: AdminAccess) -> Json<Result<stabilizer_service::ServiceStatus, chord_util::GeneralError>> {
+    return Json(Ok(stabilizer_service::get_status()));
+}
+
+// action には start, stop, pause, resume のいずれかを指定する
+#[post("/stabilizer_control?<action>")]
+pub async fn rrpc__stabilizer_control(action: String, admin_access: AdminAccess) -> Json<Result<stabilizer_service::ServiceStatus, chord_util::GeneralError>> {
+    return match action.as_str() {
+        "start" => Json(stabilizer_service::start()),
+        "stop" => Json(stabilizer_service::stop().await),
+        "pause" => Json(stabilizer_service::pause()),
+        "resume" => Json(stabilizer_service::resume()),
+        _ => Json(Err(chord_util::GeneralError::new("action must be start, stop, pause or resume".to_string(), chord_util::ERR_CODE_INVALID_ARGUMENT)))
+    };
+}
+
+// このプロセスの全仮想ノードについて、stabilize処理を直ちに1回行う
+#[post("/stabilize_now")]
+pub async fn rrpc__stabilize_now(admin_access: AdminAccess) -> Json<Result<stabilizer_service::ServiceStatus, chord_util::GeneralError>> {
+    return Json(Ok(stabilizer_service::run_round_now().await));
+}
+
+pub async fn rrpc_call__get_node_load(address: &String) -> Result<rebalance::NodeLoad, chord_util::GeneralError> {
+    let req_rslt = rpc_get_request::<rebalance::NodeLoad>(address, "get_node_load").await;
+    return match req_rslt {
+        Err(err) => Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)),
+        Ok(load) => Ok(load)
+    };
+}
+
+#[get("/get_node_load")]
+pub async fn rrpc__get_node_load(self_node: TargetVnode, data_store: &State<ArMu<data_store::DataStore>>, cluster_member: ClusterMember) -> Json<rebalance::NodeLoad> {
+    return Json(rebalance::get_local_node_load(Arc::clone(&self_node), Arc::clone(data_store)));
+}
+
+// 呼び出し先のノードから target_address のノードへの応答の有無の確認を依頼する (failure_detector の間接的な確認)
+pub async fn rrpc_call__probe_peer(address: &String, target_address: &String) -> Result<bool, chord_util::GeneralError> {
+    let req_rslt = rpc_post_request::<String, bool>(address, "probe_peer", target_address).await;
+    return match req_rslt {
+        Err(err) => Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)),
+        Ok(is_alive) => Ok(is_alive)
+    };
+}
+
+#[post("/probe_peer", data = "<target_address>")]
+pub async fn rrpc__probe_peer(target_address: Json<String>, cluster_member: ClusterMember) -> Json<bool> {
+    return Json(failure_detector::probe_directly(&target_address.0).await);
+}
+
+// 故障検知で記録している各ピアの状態 (監視用)
+#[get("/failure_detector_status")]
+pub async fn rrpc__failure_detector_status(admin_access: AdminAccess) -> Json<Result<Vec<failure_detector::PeerHealth>, chord_util::GeneralError>> {
+    return Json(Ok(failure_detector::get_peer_healths()));
+}
+
+// 移動先のIDが不正な場合などのエラーはそのまま返す
+pub async fn rrpc_call__move_node_id(address: &String, new_id: u32) -> Result<bool, chord_util::GeneralError> {
+    let req_rslt = rpc_post_request::<u32, Result<bool, chord_util::GeneralError>>(address, "move_node_id", &new_id).await;
+    return match req_rslt {
+        Err(err) => Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)),
+        Ok(result) => result
+    };
+}
+
+#[post("/move_node_id", data = "<new_id>")]
+pub async fn rrpc__move_node_id(self_node: TargetVnode, data_store: &State<ArMu<data_store::DataStore>>, new_id: Json<u32>, cluster_member: ClusterMember) -> Json<Result<bool, chord_util::GeneralError>> {
+    return Json(rebalance::move_node_id(Arc::clone(&self_node), Arc::clone(data_store), new_id.0).await);
+}
+
+// リング上の各ノードのキーの数が均等になるようにノードIDを移動する
+// dry_run に true を指定した場合は移動先のIDを求めるのみ
+#[post("/rebalance_ring?<dry_run>")]
+pub async fn rrpc__rebalance_ring(self_node: &State<ArMu<node_info::NodeInfo>>, dry_run: Option<bool>, admin_access: AdminAccess) -> Json<Result<rebalance::RebalanceResult, chord_util::GeneralError>> {
+    return Json(rebalance::rebalance_ring(Arc::clone(self_node), dry_run.unwrap_or(false)).await);
+}
+
+pub async fn rrpc_call__get_export_page(address: &String, req: bulk::ExportPageRequest) -> Result<Vec<chord_util::DataIdAndValue>, chord_util::GeneralError> {
+    let req_rslt = rpc_post_request::<bulk::ExportPageRequest, Vec<chord_util::DataIdAndValue>>(address, "get_export_page", &req).await;
+    return match req_rslt {
+        Err(err) => Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)),
+        Ok(datas) => Ok(datas)
+    };
+}
+
+#[post("/get_export_page", data = "<req>")]
+pub async fn rrpc__get_export_page(data_store: &State<ArMu<data_store::DataStore>>, req: Json<bulk::ExportPageRequest>, cluster_member: ClusterMember) -> Json<Vec<chord_util::DataIdAndValue>> {
+    return Json(bulk::get_local_export_page(Arc::clone(data_store), req.0));
+}
+
+pub async fn rrpc_call__import_batch(address: &String, datas: Vec<chord_util::DataIdAndValue>) -> Result<bulk::ImportResult, chord_util::GeneralError> {
+    let req_rslt = rpc_post_request::<Vec<chord_util::DataIdAndValue>, bulk::ImportResult>(address, "import_batch", &datas).await;
+    return match req_rslt {
+        Err(err) => Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)),
+        Ok(result) => Ok(result)
+    };
+}
+
+#[post("/import_batch", data = "<datas>")]
+pub async fn rrpc__import_batch(self_node: TargetVnode, datas: Json<Vec<chord_util::DataIdAndValue>>, cluster_member: ClusterMember) -> Json<bulk::ImportResult> {
+    return Json(bulk::import_local_batch(Arc::clone(&self_node), datas.0).await);
+}
+
+// クラスタの全データを JSON Lines 形式で返す
+#[get("/export")]
+pub async fn rrpc__export(self_node: &State<ArMu<node_info::NodeInfo>>, admin_access: AdminAccess) -> Result<String, status::Custom<Json<chord_util::GeneralError>>> {
+    return match bulk::export_datas(Arc::clone(self_node)).await {
+        Err(err) => Err(status::Custom(Status::ServiceUnavailable, Json(err))),
+        Ok(body) => Ok(body)
+    };
+}
+
+// JSON Lines 形式のデータを読み込んでクラスタに書き込む. /export の出力をそのまま渡すことができる
+#[post("/import", data = "<body>")]
+pub async fn rrpc__import(self_node: &State<ArMu<node_info::NodeInfo>>, body: Data<'_>, admin_access: AdminAccess) -> Json<Result<bulk::ImportResult, chord_util::GeneralError>> {
+    let body_str = match body.open(gval::IMPORT_MAX_BODY_MIB.mebibytes()).into_string().await {
+        Err(err) => { return Json(Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_INVALID_ARGUMENT))); }
+        Ok(body_str) => body_str
+    };
+    if body_str.is_complete() == false {
+        return Json(Err(chord_util::GeneralError::new("request body is too large".to_string(), chord_util::ERR_CODE_INVALID_ARGUMENT)));
+    }
+    return Json(bulk::import_datas(Arc::clone(self_node), body_str.into_inner()).await);
+}
+
 // ブラウザからアドレス解決を試すためのエンドポイント
 // 与えられた0から100の整数の100分の1をID空間のサイズ（最大値）にかけた
 // 値をIDとして、find_successorした結果を返す
 // 問い合わせはまず自身に対してかける
 #[get("/resolve_id_val?<percentage>")]
-pub fn rrpc__resolve_id_val(self_node: State<ArMu<node_info::NodeInfo>>, percentage : String) -> Json<node_info::NodeInfoSummary> {
+pub async fn rrpc__resolve_id_val(self_node: &State<ArMu<node_info::NodeInfo>>, percentage : String, read_access: ReadAccess) -> Json<node_info::NodeInfoSummary> {
     let percentage_num: f32 = percentage.parse().unwrap();
     let id = ((percentage_num / 100.0) as f64) * (gval::ID_MAX as f64);
-    Json(router::find_successor(Arc::clone(&self_node), id as u32).unwrap())
+    Json(router::find_successor(Arc::clone(self_node), id as u32).await.unwrap())
 }
 
-pub fn rest_api_server_start(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::DataStore>, bind_addr: String, bind_port_num: i32){
-    let config = Config::build(Environment::Production)
-    .address(bind_addr)
-    .read_timeout(10000)
-    .write_timeout(10000)
-    .keep_alive(10000)
-    .port(bind_port_num as u16)
-    .workers(256)
-    .finalize()
-    .unwrap();
+pub async fn rest_api_server_start(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::DataStore>, bind_addr: String, bind_port_num: i32){
+    // pass_datas などでは大きなJSONを受け取るため、JSONのサイズの上限はバイナリプロトコルのフレーム長の上限と揃える
+    let mut figment = rocket::Config::figment()
+    .merge(("address", bind_addr))
+    .merge(("port", bind_port_num as u16))
+    .merge(("keep_alive", 10))
+    .merge(("log_level", rocket::config::LogLevel::Critical))
+    .merge(("limits", Limits::default().limit("json", (gval::BINARY_RPC_MAX_FRAME_LEN as u64).bytes())));
+
+    // TLSが有効な場合、クライアント証明書の提示は任意とし、内部RPCのハンドラで ClusterMember ガードによりチェックする
+    // (クライアント向けのエンドポイントは証明書を持たないクライアントからも利用できるようにするため)
+    match config::get_tls_config() {
+        None => {}
+        Some(tls_conf) => {
+            figment = figment
+            .merge(("tls.certs", tls_conf.cert_path))
+            .merge(("tls.key", tls_conf.key_path))
+            .merge(("tls.mutual.ca_certs", tls_conf.ca_cert_path))
+            .merge(("tls.mutual.mandatory", false));
+        }
+    };
 
-    let app = rocket::custom(config);
+    let app = rocket::custom(figment);
     
-    app.manage(self_node)
+    let launch_rslt = app.manage(self_node)
        .manage(data_store)
        .mount(
-           "/", 
+           "/",
             routes![
                 index,
                 get_param_test,
                 deserialize_test,
                 result_type,
-                rrpc__check_predecessor,
-                rrpc__set_routing_infos_force,
-                rrpc__find_successor,
-                rrpc__closest_preceding_finger,                
-                rrpc__get_node_info,
                 rrpc__resolve_id_val,
                 rrpc__global_put,
-                rrpc__put,
                 rrpc__global_get,
-                rrpc__get,
                 rrpc__global_delete,
                 rrpc__global_put_simple,
                 rrpc__global_get_simple,
                 rrpc__global_delete_simple,
-                rrpc__pass_datas
+                rrpc__create_namespace,
+                rrpc__list_namespaces,
+                rrpc__drop_namespace,
+                rrpc__replica_placement_report,
+                rrpc__stabilize_rate,
+                rrpc__stabilizer_status,
+                rrpc__stabilizer_control,
+                rrpc__stabilize_now,
+                rrpc__failure_detector_status,
+                rrpc__rebalance_ring,
+                rrpc__export,
+                rrpc__import,
+                rrpc__trace_lookup
+            ]
+        )
+       .mount(
+           INTERNAL_API_BASE,
+            routes![
+                rrpc__check_predecessor,
+                rrpc__set_routing_infos_force,
+                rrpc__find_successor,
+                rrpc__find_successor_recursive,
+                rrpc__closest_preceding_finger,
+                rrpc__get_node_info,
+                rrpc__get_successor,
+                rrpc__get_predecessor,
+                rrpc__get_successor_list,
+                rrpc__get_rpc_protocol_info,
+                rrpc__put,
+                rrpc__get,
+                rrpc__pass_datas,
+                rrpc__receive_data_chunk,
+                rrpc__reindex_replicas,
+                rrpc__drop_namespace_datas,
+                rrpc__get_namespace_usages,
+                rrpc__get_replica_locations,
+                rrpc__get_node_load,
+                rrpc__move_node_id,
+                rrpc__probe_peer,
+                rrpc__get_export_page,
+                rrpc__import_batch
             ]
         )
-       .launch();
+       .launch().await;
+
+    match launch_rslt {
+        Err(err) => {
+            chord_util::dprint(&("ERROR at rest_api_server_start,".to_string() + err.to_string().as_str()));
+        }
+        Ok(_) => {}
+    };
 }
 
 #[derive(Serialize, Deserialize)]
 #[derive(Debug, Clone)]
 pub struct SetRoutingInfosForce{
-    predecessor_info: node_info::NodeInfo,
-    successor_info_0: node_info::NodeInfo,
-    ftable_enry_0: node_info::NodeInfo
+    predecessor_info: node_info::NodeInfoEntry,
+    successor_info_0: node_info::NodeInfoEntry,
+    ftable_enry_0: node_info::NodeInfoEntry
 }
 
 impl SetRoutingInfosForce {
     pub fn new(
-        predecessor_info: node_info::NodeInfo,
-        successor_info_0: node_info::NodeInfo,
-        ftable_enry_0: node_info::NodeInfo) -> SetRoutingInfosForce 
+        predecessor_info: node_info::NodeInfoEntry,
+        successor_info_0: node_info::NodeInfoEntry,
+        ftable_enry_0: node_info::NodeInfoEntry) -> SetRoutingInfosForce 
     {
         SetRoutingInfosForce {
             predecessor_info: predecessor_info, 
@@ -539,17 +1067,27 @@ impl SetRoutingInfosForce {
 #[derive(Debug, Clone)]
 pub struct GlobalPut {
     key_str: String,
-    val_str: String
+    val_str: String,
+    // 省略した場合はネームスペースのデフォルトのTTLが用いられる. 0 の場合は期限無し
+    #[serde(default)]
+    ttl_sec: Option<u64>,
+    // 省略した場合はネームスペースのレプリケーションファクタが用いられる
+    #[serde(default)]
+    replication_factor: Option<u32>
 }
 
 impl GlobalPut {
     pub fn new(
         key_str: String,
-        val_str: String) -> GlobalPut
+        val_str: String,
+        ttl_sec: Option<u64>,
+        replication_factor: Option<u32>) -> GlobalPut
     {
         GlobalPut {
             key_str: key_str, 
-            val_str: val_str
+            val_str: val_str,
+            ttl_sec: ttl_sec,
+            replication_factor: replication_factor
         }
     }
 }
@@ -558,17 +1096,33 @@ impl GlobalPut {
 #[derive(Debug, Clone)]
 pub struct Put {
     key_id: u32,
-    val_str: String
+    val_str: String,
+    namespace: String,
+    expire_at: i64,
+    replica_idx: u32,
+    replication_factor: u32
 }
 
 impl Put {
     pub fn new(
         key_id: u32,
-        val_str: String) -> Put
+        val_str: String,
+        namespace: String,
+        expire_at: i64,
+        replica_idx: u32,
+        replication_factor: u32) -> Put
     {
         Put {
             key_id: key_id, 
-            val_str: val_str
+            val_str: val_str,
+            namespace: namespace,
+            expire_at: expire_at,
+            replica_idx: replica_idx,
+            replication_factor: replication_factor
         }
     }
+
+    fn into_data_iv(self) -> chord_util::DataIdAndValue {
+        return chord_util::DataIdAndValue { data_id: self.key_id, val_str: self.val_str, namespace: self.namespace, expire_at: self.expire_at, replica_idx: self.replica_idx, replication_factor: self.replication_factor };
+    }
 }
\ No newline at end of file
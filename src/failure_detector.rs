@@ -0,0 +1,310 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Instant;
+use serde::{Deserialize, Serialize};
+
+use crate::gval;
+use crate::node_info;
+use crate::chord_util;
+use crate::endpoints;
+use crate::vnode;
+
+// ピアの故障検知 (プロセスで共有. キーはピアの物理アドレス)
+//
+// RPCの応答を受けるごとに前回の応答からの間隔を記録し (直近 gval::FAILURE_DETECTOR_WINDOW_SIZE 回分)、
+// phi-accrual 方式で、最後の応答からの経過時間がどの程度疑わしいか (phi) を求める
+// 応答の間隔は指数分布に従うものとし、phi = 経過時間 / 平均の間隔 * log10(e) とする
+// ピアの状態は以下のいずれか
+//   Alive: 応答がある
+//   Suspected: RPCが失敗した. 経路情報からは取り除かず、経路の選択では後回しにする
+//              直接の確認に加えて、他のノードに確認を依頼し (SWIM方式の間接的な確認)、いずれかで応答があれば Alive に戻す
+//   Dead: 直接の確認と間接的な確認のいずれにも応答が無かった. このプロセスの全仮想ノードの経路情報から取り除く
+// phi が gval::FAILURE_DETECTOR_PHI_THRESHOLD 以上の Alive のピアも、経路の選択では後回しにする
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerState {
+    Alive,
+    Suspected,
+    Dead
+}
+
+// 監視用に返す、ピアごとの状態
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PeerHealth {
+    pub address_str: String,
+    pub state: PeerState,
+    pub phi: f64,
+    // 記録している応答の間隔の数と、その平均
+    pub sample_count: usize,
+    pub mean_interval_millis: u64,
+    // 最後に応答を受けてからの経過時間. 応答を受けたことが無い場合は None
+    pub millis_since_last_heartbeat: Option<u64>
+}
+
+struct PeerRecord {
+    state: PeerState,
+    intervals: VecDeque<u64>,
+    last_heartbeat: Option<Instant>,
+    // 確認を行っている最中か
+    is_probing: bool
+}
+
+impl PeerRecord {
+    fn new() -> PeerRecord {
+        PeerRecord { state: PeerState::Alive, intervals: VecDeque::new(), last_heartbeat: None, is_probing: false }
+    }
+
+    fn mean_interval_millis(&self) -> u64 {
+        if self.intervals.len() == 0 {
+            return 0;
+        }
+        return self.intervals.iter().sum::<u64>() / self.intervals.len() as u64;
+    }
+
+    fn calc_phi(&self) -> f64 {
+        if self.intervals.len() < gval::FAILURE_DETECTOR_MIN_SAMPLES {
+            return 0.0;
+        }
+        let last_heartbeat = match self.last_heartbeat {
+            None => { return 0.0; }
+            Some(last_heartbeat) => last_heartbeat
+        };
+        let mean = std::cmp::max(self.mean_interval_millis(), gval::FAILURE_DETECTOR_MIN_INTERVAL_MILLIS) as f64;
+        return last_heartbeat.elapsed().as_millis() as f64 / mean * std::f64::consts::LOG10_E;
+    }
+}
+
+lazy_static! {
+    static ref PEER_RECORDS : Mutex<HashMap<String, PeerRecord>> = Mutex::new(HashMap::new());
+}
+
+// address (物理アドレス) のピアからRPCの応答を受けたことを記録する
+pub fn record_heartbeat(address: &str) {
+    if vnode::is_local_address(address) {
+        return;
+    }
+    let mut records_ref = PEER_RECORDS.lock().unwrap();
+    let record = records_ref.entry(address.to_string()).or_insert_with(PeerRecord::new);
+    let now = Instant::now();
+    match record.last_heartbeat {
+        None => { record.last_heartbeat = Some(now); }
+        Some(last_heartbeat) => {
+            // 続けて行われたRPCの応答はまとめて1回とみなす. 平均の間隔が短くなりすぎ、少し間が空いただけで疑わしくなるのを防ぐ
+            let interval_millis = now.duration_since(last_heartbeat).as_millis() as u64;
+            if interval_millis >= gval::FAILURE_DETECTOR_MIN_INTERVAL_MILLIS {
+                record.intervals.push_back(interval_millis);
+                if record.intervals.len() > gval::FAILURE_DETECTOR_WINDOW_SIZE {
+                    record.intervals.pop_front();
+                }
+                record.last_heartbeat = Some(now);
+            }
+        }
+    };
+    if record.state != PeerState::Alive {
+        chord_util::dprint(&("failure_detector_alive,".to_string() + address));
+        record.state = PeerState::Alive;
+    }
+}
+
+// address (物理アドレス) のピアへのRPCが失敗したことを記録し、Suspected として確認を開始する
+pub fn report_failure(address: &str) {
+    if vnode::is_local_address(address) {
+        return;
+    }
+    {
+        let mut records_ref = PEER_RECORDS.lock().unwrap();
+        let record = records_ref.entry(address.to_string()).or_insert_with(PeerRecord::new);
+        if record.state == PeerState::Dead || record.is_probing {
+            return;
+        }
+        if record.state == PeerState::Alive {
+            chord_util::dprint(&("failure_detector_suspected,".to_string() + address));
+            record.state = PeerState::Suspected;
+        }
+        record.is_probing = true;
+    }
+    tokio::spawn(probe_suspected(address.to_string()));
+}
+
+pub fn get_state(address: &str) -> PeerState {
+    let records_ref = PEER_RECORDS.lock().unwrap();
+    return match records_ref.get(&vnode::get_physical_address(address)) {
+        None => PeerState::Alive,
+        Some(record) => record.state
+    };
+}
+
+// 経路の選択での優先度. 小さいほど優先する (Alive, phi が閾値以上の Alive, Suspected, Dead の順)
+pub fn get_route_priority(address: &str) -> u32 {
+    let records_ref = PEER_RECORDS.lock().unwrap();
+    let record = match records_ref.get(&vnode::get_physical_address(address)) {
+        None => { return 0; }
+        Some(record) => record
+    };
+    return match record.state {
+        PeerState::Alive => if record.calc_phi() < gval::FAILURE_DETECTOR_PHI_THRESHOLD { 0 } else { 1 },
+        PeerState::Suspected => 2,
+        PeerState::Dead => 3
+    };
+}
+
+pub fn is_healthy(address: &str) -> bool {
+    return get_route_priority(address) == 0;
+}
+
+// 記録している全ピアの状態. アドレス順
+pub fn get_peer_healths() -> Vec<PeerHealth> {
+    let records_ref = PEER_RECORDS.lock().unwrap();
+    let mut healths: Vec<PeerHealth> = records_ref.iter().map(|(address, record)| PeerHealth {
+        address_str: address.clone(),
+        state: record.state,
+        phi: record.calc_phi(),
+        sample_count: record.intervals.len(),
+        mean_interval_millis: record.mean_interval_millis(),
+        millis_since_last_heartbeat: record.last_heartbeat.map(|last_heartbeat| last_heartbeat.elapsed().as_millis() as u64)
+    }).collect();
+    healths.sort_by(|a, b| a.address_str.cmp(&b.address_str));
+    return healths;
+}
+
+// address のピアに直接RPCを行い、応答があるかを返す. probe_peer で他のノードから依頼された場合にも用いる
+pub async fn probe_directly(address: &String) -> bool {
+    return endpoints::rrpc_call__get_predecessor(address).await.is_ok();
+}
+
+// 間接的な確認を依頼するノード. 自プロセスの仮想ノードの経路情報にあるピアのうち、健全なものを最大 gval::FAILURE_DETECTOR_INDIRECT_PROBES 個
+fn select_probe_helpers(target_address: &String) -> Vec<String> {
+    let mut entry_addresses: Vec<String> = vec![];
+    for vnode_info in vnode::get_all().iter() {
+        let self_node_ref = vnode_info.lock().unwrap();
+        let entries = self_node_ref.successor_info_list.iter()
+            .chain(self_node_ref.predecessor_info.iter())
+            .chain(self_node_ref.finger_table.iter().flatten());
+        for entry in entries {
+            entry_addresses.push(vnode::get_physical_address(&entry.address_str));
+        }
+    }
+
+    // is_local_address は0番の仮想ノードをロックするため、上のロックを解放してから判定する
+    let mut helpers: Vec<String> = vec![];
+    for address in entry_addresses {
+        if address == *target_address || helpers.contains(&address) || vnode::is_local_address(&address) || is_healthy(&address) == false {
+            continue;
+        }
+        helpers.push(address);
+        if helpers.len() >= gval::FAILURE_DETECTOR_INDIRECT_PROBES {
+            break;
+        }
+    }
+    return helpers;
+}
+
+async fn probe_suspected(address: String) {
+    let mut is_alive = probe_directly(&address).await;
+    if is_alive == false {
+        for helper in select_probe_helpers(&address) {
+            match endpoints::rrpc_call__probe_peer(&helper, &address).await {
+                Ok(true) => {
+                    is_alive = true;
+                    break;
+                }
+                _ => {}
+            };
+        }
+    }
+
+    {
+        let mut records_ref = PEER_RECORDS.lock().unwrap();
+        let record = records_ref.entry(address.clone()).or_insert_with(PeerRecord::new);
+        record.is_probing = false;
+        if is_alive {
+            // 直接の確認で応答があった場合は record_heartbeat で既に Alive となっている
+            if record.state != PeerState::Alive {
+                chord_util::dprint(&("failure_detector_alive_indirect,".to_string() + address.as_str()));
+                record.state = PeerState::Alive;
+            }
+            return;
+        }
+        chord_util::dprint(&("failure_detector_dead,".to_string() + address.as_str()));
+        record.state = PeerState::Dead;
+    }
+
+    for vnode_info in vnode::get_all().iter() {
+        let mut self_node_ref = vnode_info.lock().unwrap();
+        node_info::remove_dead_peer(&mut self_node_ref, &address);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // PEER_RECORDS はプロセスで共有されるため、テストごとに異なるアドレスを用いる
+    fn gen_record(state: PeerState, interval_millis: u64, sample_count: usize, elapsed_millis: u64) -> PeerRecord {
+        let mut record = PeerRecord::new();
+        record.state = state;
+        record.intervals = std::iter::repeat_n(interval_millis, sample_count).collect();
+        record.last_heartbeat = Instant::now().checked_sub(Duration::from_millis(elapsed_millis));
+        return record;
+    }
+
+    #[test]
+    fn calc_phi_grows_with_elapsed_time() {
+        // サンプルが足りない間は疑わない
+        assert_eq!(gen_record(PeerState::Alive, 1000, gval::FAILURE_DETECTOR_MIN_SAMPLES - 1, 60000).calc_phi(), 0.0);
+
+        let phi = gen_record(PeerState::Alive, 1000, gval::FAILURE_DETECTOR_MIN_SAMPLES, 2000).calc_phi();
+        assert!((phi - 2.0 * std::f64::consts::LOG10_E).abs() < 0.05, "phi: {}", phi);
+        let phi = gen_record(PeerState::Alive, 1000, gval::FAILURE_DETECTOR_MIN_SAMPLES, 20000).calc_phi();
+        assert!(phi >= gval::FAILURE_DETECTOR_PHI_THRESHOLD, "phi: {}", phi);
+
+        // 平均の間隔は gval::FAILURE_DETECTOR_MIN_INTERVAL_MILLIS 未満にはしない
+        let phi = gen_record(PeerState::Alive, 1, gval::FAILURE_DETECTOR_MIN_SAMPLES, gval::FAILURE_DETECTOR_MIN_INTERVAL_MILLIS).calc_phi();
+        assert!((phi - std::f64::consts::LOG10_E).abs() < 0.05, "phi: {}", phi);
+    }
+
+    #[test]
+    fn get_route_priority_orders_by_state_and_phi() {
+        {
+            let mut records_ref = PEER_RECORDS.lock().unwrap();
+            records_ref.insert("127.0.0.1:19101".to_string(), gen_record(PeerState::Alive, 1000, gval::FAILURE_DETECTOR_MIN_SAMPLES, 0));
+            records_ref.insert("127.0.0.1:19102".to_string(), gen_record(PeerState::Alive, 1000, gval::FAILURE_DETECTOR_MIN_SAMPLES, 60000));
+            records_ref.insert("127.0.0.1:19103".to_string(), gen_record(PeerState::Suspected, 1000, 0, 0));
+            records_ref.insert("127.0.0.1:19104".to_string(), gen_record(PeerState::Dead, 1000, 0, 0));
+        }
+        assert_eq!(get_route_priority("127.0.0.1:19100"), 0);
+        assert_eq!(get_route_priority("127.0.0.1:19101"), 0);
+        assert_eq!(get_route_priority("127.0.0.1:19102"), 1);
+        assert_eq!(get_route_priority("127.0.0.1:19103"), 2);
+        assert_eq!(get_route_priority("127.0.0.1:19104"), 3);
+        assert!(is_healthy("127.0.0.1:19101"));
+        assert!(is_healthy("127.0.0.1:19102") == false);
+    }
+
+    #[tokio::test]
+    async fn report_failure_and_heartbeat_change_state() {
+        let address = "127.0.0.1:19201".to_string();
+        record_heartbeat(&address);
+        // 続けて受けた応答は間隔として記録しない
+        record_heartbeat(&address);
+        assert_eq!(get_state(&address), PeerState::Alive);
+        assert_eq!(PEER_RECORDS.lock().unwrap()[&address].intervals.len(), 0);
+
+        // 確認のタスクはこのテストが await するまで実行されない
+        report_failure(&address);
+        assert_eq!(get_state(&address), PeerState::Suspected);
+        assert!(PEER_RECORDS.lock().unwrap()[&address].is_probing);
+        record_heartbeat(&address);
+        assert_eq!(get_state(&address), PeerState::Alive);
+
+        // Dead となったピアは RPC の失敗では変化せず、応答があれば Alive に戻る
+        let dead_address = "127.0.0.1:19202".to_string();
+        PEER_RECORDS.lock().unwrap().insert(dead_address.clone(), gen_record(PeerState::Dead, 1000, 0, 0));
+        report_failure(&dead_address);
+        assert_eq!(get_state(&dead_address), PeerState::Dead);
+        record_heartbeat(&dead_address);
+        assert_eq!(get_state(&dead_address), PeerState::Alive);
+    }
+}
@@ -17,9 +17,22 @@ pub const ID_SPACE_BITS : u32 = 32; // 160 <- sha1での本来の値
 pub const ID_SPACE_RANGE : u32 = 0xFFFFFFFF; // 0を含めての数である点に注意
 pub const ID_MAX : u32 = 0xFFFFFFFF - 1;
 
-// マスターデータ相当のものは含まない
-pub const REPLICA_NUM : u32 = 6;
-pub const REPLICA_ID_DISTANCE : u32 =  0xFFFFFFFF / 8;
+// レプリケーションファクタ (マスターデータ相当のものを含めたデータのコピーの数) として設定できる値の上限
+// デフォルト値などは設定ファイルで指定する (config::NodeConfig)
+pub const REPLICATION_FACTOR_LIMIT : u32 = 32;
+
+// successor_list モードのレプリケーションで、データの担当ノードがダウンしていた場合に後続のノードを
+// 担当ノードとして問い合わせ直す回数の上限
+pub const REPLICA_NODES_LOOKUP_TRY_MAX : u32 = 3;
+
+// salted_id モードのレプリケーションで、コピーを配置するノードを探してリングをたどるノード数の上限
+pub const REPLICA_PLACEMENT_WALK_MAX : u32 = 64;
+
+// 1プロセスでホストできる仮想ノードの数の上限
+pub const VNODE_MAX_PER_PROCESS : u32 = 64;
+
+// システム用のネームスペース (ネームスペースの定義の一覧など) のデータのレプリケーションファクタ
+pub const SYSTEM_NAMESPACE_REPLICATION_FACTOR : u32 = 7;
 
 // successor_info_listに保持するNodeInfoオブジェクトの要素数
 // 30ノード規模を想定し、ln(32) = 6 から、6としている
@@ -27,3 +40,117 @@ pub const SUCCESSOR_INFO_LIST_LEN : i32 = 6;
 
 // 何回のstabilize_successor呼出しごとにsuccessor_info_list埋めを行うか
 pub const FILL_SUCC_LIST_INTERVAL_TIMES : i32 = 5;
+
+// ノード間RPCでバイナリプロトコル(tcp_rpcモジュール)を利用するか
+// 利用する場合も、相手ノードが対応していなければHTTPでやり取りする
+pub const ENABLE_BINARY_RPC : bool = true;
+
+// バイナリプロトコルのリスナは HTTPのポート番号 + このオフセット のポートで待ち受ける
+pub const BINARY_RPC_PORT_OFFSET : i32 = 1000;
+
+pub const BINARY_RPC_MAX_FRAME_LEN : u32 = 64 * 1024 * 1024;
+
+// ピアごとに保持しておく空きコネクションの最大数
+pub const BINARY_RPC_MAX_IDLE_CONNS_PER_PEER : usize = 8;
+
+// ノード間のHTTPでのRPCについて、ピアごとに同時に発行できるリクエスト数の上限
+// バイナリプロトコルでのRPCも含めて数える
+pub const MAX_CONCURRENT_RPCS_PER_PEER : usize = 32;
+
+// ピアごとのコネクションプールに関する設定
+pub const PEER_CONN_MAX_IDLE_PER_PEER : usize = 8;
+pub const PEER_CONN_IDLE_TIMEOUT_SEC : u64 = 90;
+pub const PEER_CONN_TCP_KEEPALIVE_SEC : u64 = 60;
+
+// リングを一周たどる処理 (ネームスペースの削除など) でたどるノード数の上限
+pub const RING_WALK_MAX_NODES : usize = 4096;
+
+// 各ノードでキャッシュするネームスペースの定義の有効期間
+pub const NAMESPACE_REGISTRY_CACHE_MILLIS : u64 = 5000;
+
+// クォータのチェックに用いるクラスタ全体でのネームスペースの使用量を、バックグラウンドのタスクがリングを一周して集計し直す間隔
+// 集計の間に自ノード経由で行われた書き込みの分は、キャッシュした使用量に加算しておく
+pub const NAMESPACE_USAGE_REFRESH_MILLIS : u64 = 5000;
+
+// ネームスペース名の長さの上限
+pub const NAMESPACE_NAME_MAX_LEN : usize = 64;
+
+// 書き込み時に指定できるTTL(秒)の上限. 10年
+pub const MAX_TTL_SEC : u64 = 10 * 365 * 24 * 60 * 60;
+
+// リバランスでノードIDの移動を試みる、リングを一周する回数の上限
+// 移動先が隣接ノードの現在のIDを越えるノードは、隣接ノードの移動後の周回で移動する
+pub const REBALANCE_PASS_MAX : u32 = 4;
+
+// リバランスで1つのノードのIDを移動してから、次のノードを移動するまでの間隔
+pub const REBALANCE_MOVE_INTERVAL_MILLIS : u64 = 2000;
+
+// 範囲単位のデータの転送で、1回のRPCで送るデータの数
+pub const DATA_TRANSFER_CHUNK_LEN : usize = 256;
+
+// 途中で失敗したデータの転送を再開する間隔と、再開を試みる回数の上限
+pub const DATA_TRANSFER_RESUME_INTERVAL_MILLIS : u64 = 3000;
+pub const DATA_TRANSFER_RESUME_MAX : u32 = 20;
+
+// データの転送の受け手が、受信中の転送の状態を保持する時間. 最後にチャンクを受け取ってからこの時間が経過したものは破棄する
+pub const DATA_TRANSFER_RECEIVE_STATE_TTL_MILLIS : u64 = 600000;
+
+// エクスポートで1回のRPCにより各ノードから読み出すデータの数
+pub const EXPORT_PAGE_LEN : u32 = 1000;
+
+// インポートで担当ノードに1回のRPCで送るデータの数と、並列に送るバッチの数の上限
+pub const IMPORT_BATCH_LEN : usize = 200;
+pub const IMPORT_PARALLELISM : usize = 4;
+
+// インポートで受け付けるリクエストボディのサイズの上限 (MiB)
+pub const IMPORT_MAX_BODY_MIB : u64 = 256;
+
+// インポートの結果に含める失敗の内容の数の上限
+pub const IMPORT_ERROR_REPORT_MAX : usize = 20;
+
+// 再帰モードの名前解決で問い合わせを転送する回数の上限
+// 経路表が正しければ log2(ノード数) 回程度で済むため、これを超える場合は経路がループしているものとみなす
+pub const LOOKUP_RECURSIVE_HOP_MAX : u32 = 64;
+
+// 再帰モードの名前解決で転送する際に、転送先へ渡す残り時間から差し引く分 (ミリ秒)
+// 転送先がタイムアウトした場合にも、転送元が自身のタイムアウトより前にエラーを返せるようにする
+pub const LOOKUP_RECURSIVE_HOP_MARGIN_MILLIS : u64 = 100;
+
+// 再帰モードの名前解決で、転送に失敗した場合に転送先を選び直す回数の上限 (1ノードあたり)
+pub const LOOKUP_RECURSIVE_FORWARD_ATTEMPT_MAX : u32 = 4;
+
+// 近接性を考慮したフィンガーの選択で、範囲の先頭のノードに加えてRTTを計測する successor の数の上限
+pub const PNS_CANDIDATE_MAX : usize = 4;
+
+// RTTの平滑化. 新しい計測値の重みを 1 / RTT_SMOOTHING_DIVISOR とする
+pub const RTT_SMOOTHING_DIVISOR : u64 = 8;
+
+// closest_preceding_finger で、探索対象のIDに近い方からこの数の候補のうち、RTTの小さいものを優先して経由する
+pub const PROXIMITY_ROUTE_CANDIDATES : usize = 2;
+
+// 名前解決の結果のキャッシュに保持する範囲 (担当ノード) の数の上限と、各範囲を用いる期間
+pub const LOCATION_CACHE_CAPACITY : usize = 1024;
+pub const LOCATION_CACHE_TTL_MILLIS : u64 = 10000;
+
+// 担当範囲を他のノードに渡した後も、渡した範囲のデータの読み出しに応じる期間
+pub const OWNERSHIP_GRACE_MILLIS : u64 = 5000;
+
+// 担当ノードでないことを示すエラーが返された場合に、名前解決をやり直して読み書きを再試行する回数の上限と間隔
+pub const NOT_TANTOU_RETRY_MAX : u32 = 3;
+pub const NOT_TANTOU_RETRY_INTERVAL_MILLIS : u64 = 200;
+
+// stabilize処理で経路表の変化やRPCの失敗が無い回がこの回数続くごとに、実行間隔を2倍に広げる (上限は config による)
+pub const STABILIZE_BACKOFF_STABLE_ROUNDS : u32 = 10;
+
+// predecessor の生死を確認する間隔
+pub const PREDECESSOR_LIVENESS_CHECK_INTERVAL_MILLIS : u64 = 1000;
+
+// 故障検知 (failure_detector) で記録するピアごとの応答の間隔の数と、phi を求めるのに必要な数
+pub const FAILURE_DETECTOR_WINDOW_SIZE : usize = 100;
+pub const FAILURE_DETECTOR_MIN_SAMPLES : usize = 5;
+// これより短い間隔で受けた応答はまとめて1回とみなす. 平均の間隔の下限も兼ねる
+pub const FAILURE_DETECTOR_MIN_INTERVAL_MILLIS : u64 = 100;
+// phi がこの値以上のピアは、応答が途絶えている疑いがあるものとして経路の選択で後回しにする
+pub const FAILURE_DETECTOR_PHI_THRESHOLD : f64 = 8.0;
+// RPCが失敗したピアについて、間接的な確認を依頼するノードの数
+pub const FAILURE_DETECTOR_INDIRECT_PROBES : usize = 3;
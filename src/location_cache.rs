@@ -0,0 +1,219 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::gval;
+use crate::node_info;
+use crate::chord_util;
+use crate::vnode;
+
+// 名前解決の結果のキャッシュ (ノードごと. 仮想ノードは同じリングを参照するためプロセスで共有する)
+//
+// find_successor(id) の結果が owner であった場合、[id, owner の ID] の範囲のIDは全て owner の担当であることが分かる.
+// そこで担当ノードごとに、担当していることが分かっている範囲を保持し、範囲内のIDについては名前解決を省略する
+// 範囲は同じ担当ノードについての結果が得られるごとに広げていく. 担当ノードの変化 (ノードの参加など) には
+// 以下で追従する
+//   - 担当でないことを示すエラー (ERR_CODE_NOT_TANTOU) が返された場合は、そのIDを含む範囲を無効化する
+//   - handle_downed_node_info でダウンが判明したノードの範囲は無効化する
+//   - gval::LOCATION_CACHE_TTL_MILLIS を過ぎた範囲は用いない
+// 保持する範囲の数は gval::LOCATION_CACHE_CAPACITY までとし、超える場合は最も長く使われていないものを捨てる
+
+struct CacheEntry {
+    // 範囲の先頭. 範囲の末尾は担当ノードのID
+    from_id: u32,
+    owner: node_info::NodeInfoSummary,
+    stored_at: Instant,
+    last_used: u64
+}
+
+struct LocationCache {
+    // キーは担当ノードのID
+    entries: BTreeMap<u32, CacheEntry>,
+    // LRUの判定に用いる、参照ごとに増える値
+    tick: u64
+}
+
+lazy_static! {
+    static ref LOCATION_CACHE : Mutex<LocationCache> = Mutex::new(LocationCache::new());
+}
+
+fn is_in_entry_range(entry: &CacheEntry, id: u32) -> bool {
+    return id == entry.from_id || id == entry.owner.node_id || chord_util::exist_between_two_nodes_right_mawari(entry.from_id, entry.owner.node_id, id);
+}
+
+impl LocationCache {
+    fn new() -> LocationCache {
+        LocationCache { entries: BTreeMap::new(), tick: 0 }
+    }
+
+    // id を含む範囲の担当ノードのID. id 以上で最小のものか、無ければ (0をまたぐ場合) 最小のもの
+    fn find_entry_key(&self, id: u32) -> Option<u32> {
+        let owner_id = match self.entries.range(id..).next() {
+            Some((owner_id, _)) => *owner_id,
+            None => match self.entries.keys().next() {
+                None => { return None; }
+                Some(owner_id) => *owner_id
+            }
+        };
+        if is_in_entry_range(&self.entries[&owner_id], id) == false {
+            return None;
+        }
+        return Some(owner_id);
+    }
+
+    fn lookup(&mut self, id: u32) -> Option<node_info::NodeInfoSummary> {
+        let owner_id = match self.find_entry_key(id) {
+            None => { return None; }
+            Some(owner_id) => owner_id
+        };
+        if self.entries[&owner_id].stored_at.elapsed() > Duration::from_millis(gval::LOCATION_CACHE_TTL_MILLIS) {
+            self.entries.remove(&owner_id);
+            return None;
+        }
+        self.tick += 1;
+        let tick = self.tick;
+        let entry = self.entries.get_mut(&owner_id).unwrap();
+        entry.last_used = tick;
+        return Some(entry.owner.clone());
+    }
+
+    fn store(&mut self, id: u32, owner: &node_info::NodeInfoSummary) {
+        self.tick += 1;
+        let tick = self.tick;
+
+        let mut from_id = id;
+        // 同じ担当ノードについての範囲であれば、広い方の先頭を用いる
+        // (どちらの範囲にも担当ノード以外のノードは存在しないため、和は広い方の範囲と一致する)
+        match self.entries.get(&owner.node_id) {
+            Some(entry) if entry.owner.address_str == owner.address_str
+                && entry.stored_at.elapsed() <= Duration::from_millis(gval::LOCATION_CACHE_TTL_MILLIS)
+                && chord_util::calc_distance_between_nodes_right_mawari(entry.from_id, owner.node_id) > chord_util::calc_distance_between_nodes_right_mawari(id, owner.node_id) => {
+                from_id = entry.from_id;
+            }
+            _ => {}
+        };
+
+        if self.entries.contains_key(&owner.node_id) == false && self.entries.len() >= gval::LOCATION_CACHE_CAPACITY {
+            let lru_key = self.entries.iter().min_by_key(|(_, entry)| entry.last_used).map(|(owner_id, _)| *owner_id);
+            match lru_key {
+                None => {}
+                Some(lru_key) => { self.entries.remove(&lru_key); }
+            };
+        }
+        self.entries.insert(owner.node_id, CacheEntry { from_id: from_id, owner: owner.clone(), stored_at: Instant::now(), last_used: tick });
+    }
+
+    fn invalidate_id(&mut self, id: u32) -> bool {
+        return match self.find_entry_key(id) {
+            None => false,
+            Some(owner_id) => {
+                self.entries.remove(&owner_id);
+                true
+            }
+        };
+    }
+}
+
+// id の担当ノードがキャッシュにあれば返す
+pub fn lookup(id: u32) -> Option<node_info::NodeInfoSummary> {
+    let mut cache = LOCATION_CACHE.lock().unwrap();
+    return cache.lookup(id);
+}
+
+// find_successor(id) の結果が owner であったことを記録する
+pub fn store(id: u32, owner: &node_info::NodeInfoSummary) {
+    let mut cache = LOCATION_CACHE.lock().unwrap();
+    cache.store(id, owner);
+}
+
+// id を含む範囲を無効化する
+pub fn invalidate_id(id: u32) {
+    let mut cache = LOCATION_CACHE.lock().unwrap();
+    if cache.invalidate_id(id) {
+        chord_util::dprint(&("location_cache_invalidate_id,".to_string() + chord_util::gen_debug_str_of_data(id).as_str()));
+    }
+}
+
+// address のノードと、同じプロセスの仮想ノードが担当ノードである範囲を無効化する
+pub fn invalidate_node(address: &str) {
+    let physical_address = vnode::get_physical_address(address);
+    let mut cache = LOCATION_CACHE.lock().unwrap();
+    cache.entries.retain(|_, entry| vnode::get_physical_address(&entry.owner.address_str) != physical_address);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gen_owner(node_id: u32) -> node_info::NodeInfoSummary {
+        return node_info::NodeInfoSummary { node_id: node_id, succ0_id: 0, address_str: format!("127.0.0.1:{}", node_id), zone: "".to_string() };
+    }
+
+    #[test]
+    fn store_merges_ranges_of_same_owner() {
+        let mut cache = LocationCache::new();
+        cache.store(500, &gen_owner(1000));
+        cache.store(100, &gen_owner(1000));
+        assert_eq!(cache.entries[&1000].from_id, 100);
+        // 狭い範囲の結果では縮めない
+        cache.store(800, &gen_owner(1000));
+        assert_eq!(cache.entries[&1000].from_id, 100);
+
+        assert_eq!(cache.lookup(100).unwrap().node_id, 1000);
+        assert_eq!(cache.lookup(1000).unwrap().node_id, 1000);
+        assert!(cache.lookup(99).is_none());
+        assert!(cache.lookup(1001).is_none());
+    }
+
+    #[test]
+    fn find_entry_key_handles_range_wrapping_zero() {
+        let mut cache = LocationCache::new();
+        cache.store(gval::ID_MAX - 100, &gen_owner(50));
+        cache.store(200, &gen_owner(300));
+
+        assert_eq!(cache.find_entry_key(gval::ID_MAX), Some(50));
+        assert_eq!(cache.find_entry_key(0), Some(50));
+        assert_eq!(cache.find_entry_key(gval::ID_MAX - 100), Some(50));
+        assert_eq!(cache.find_entry_key(250), Some(300));
+        assert_eq!(cache.find_entry_key(100), None);
+        assert_eq!(cache.find_entry_key(gval::ID_MAX - 101), None);
+
+        assert!(cache.invalidate_id(10));
+        assert_eq!(cache.find_entry_key(gval::ID_MAX), None);
+        assert_eq!(cache.find_entry_key(250), Some(300));
+    }
+
+    #[test]
+    fn store_evicts_least_recently_used_entry() {
+        let mut cache = LocationCache::new();
+        for idx in 0..gval::LOCATION_CACHE_CAPACITY as u32 {
+            cache.store(idx * 10 + 1, &gen_owner(idx * 10 + 5));
+        }
+        // 最初に記録したものを参照しておき、2番目に記録したものが最も長く使われていないものとなるようにする
+        assert!(cache.lookup(1).is_some());
+
+        cache.store(gval::ID_MAX - 10, &gen_owner(gval::ID_MAX - 5));
+        assert_eq!(cache.entries.len(), gval::LOCATION_CACHE_CAPACITY);
+        assert!(cache.lookup(1).is_some());
+        assert!(cache.lookup(11).is_none());
+        assert!(cache.lookup(gval::ID_MAX - 10).is_some());
+    }
+
+    #[test]
+    fn lookup_ignores_and_removes_expired_entry() {
+        let mut cache = LocationCache::new();
+        cache.store(100, &gen_owner(200));
+        cache.store(300, &gen_owner(400));
+        let expired_at = Instant::now().checked_sub(Duration::from_millis(gval::LOCATION_CACHE_TTL_MILLIS + 1)).unwrap();
+        cache.entries.get_mut(&200).unwrap().stored_at = expired_at;
+
+        assert!(cache.lookup(150).is_none());
+        assert!(cache.entries.contains_key(&200) == false);
+        assert_eq!(cache.lookup(350).unwrap().node_id, 400);
+
+        // 期限切れの範囲とは併合しない
+        cache.entries.get_mut(&400).unwrap().stored_at = expired_at;
+        cache.store(380, &gen_owner(400));
+        assert_eq!(cache.entries[&400].from_id, 380);
+    }
+}
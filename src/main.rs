@@ -8,20 +8,12 @@
 #![allow(non_snake_case)]
 #![allow(unused_must_use)]
 
-#![feature(proc_macro_hygiene)]
-#![feature(decl_macro)]
-
 #[macro_use]
 extern crate rocket;
 
 #[macro_use]
 extern crate lazy_static;
 
-//HTTPヘッダを生成する構造体を自動生成するためのマクロを使用可能とする
-//認証などを行わないのであれば必要ないかも
-#[macro_use]
-extern crate hyper;
-
 // utility macros
 
 macro_rules! ArMu_new {
@@ -38,6 +30,21 @@ pub mod stabilizer;
 pub mod router;
 pub mod data_store;
 pub mod endpoints;
+pub mod tcp_rpc;
+pub mod conn_pool;
+pub mod config;
+pub mod circuit_breaker;
+pub mod auth;
+pub mod namespace;
+pub mod placement;
+pub mod vnode;
+pub mod rebalance;
+pub mod transfer;
+pub mod bulk;
+pub mod location_cache;
+pub mod stabilize_rate;
+pub mod stabilizer_service;
+pub mod failure_detector;
 
 type ArMu<T> = Arc<Mutex<T>>;
 
@@ -54,46 +61,47 @@ use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
-fn req_rest_api_test_inner_get() {
-    let resp = reqwest::blocking::get("http://127.0.0.1:8000/").unwrap()
-    .text();
+async fn req_rest_api_test_inner_get() {
+    let resp = reqwest::get("http://127.0.0.1:8000/").await.unwrap()
+    .text().await;
     //.json::<HashMap<String, String>>().unwrap();
     println!("{:#?}", resp);
 }
 
-fn req_rest_api_test_inner_get_param_test() {
-    let resp = reqwest::blocking::get("http://localhost:8000/get-param-test?param1=aaaaaa&param2=bbbbbb").unwrap()
-    .text();
+async fn req_rest_api_test_inner_get_param_test() {
+    let resp = reqwest::get("http://localhost:8000/get-param-test?param1=aaaaaa&param2=bbbbbb").await.unwrap()
+    .text().await;
     //.json::<HashMap<String, String>>().unwrap();
     println!("{:#?}", resp);
 }
 
-fn req_rest_api_test_inner_post() {
+async fn req_rest_api_test_inner_post() {
     let text = r#"{"node_id":100,"address_str":"kanbayashi","born_id":77,"successor_info_list":[{"node_id":100,"address_str":"kanbayashi","born_id":77,"successor_info_list":[],"predecessor_info":[],"finger_table":[null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null]}],"predecessor_info":[{"node_id":100,"address_str":"kanbayashi","born_id":77,"successor_info_list":[{"node_id":100,"address_str":"kanbayashi","born_id":77,"successor_info_list":[],"predecessor_info":[],"finger_table":[null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null]}],"predecessor_info":[],"finger_table":[null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null]}],"finger_table":[null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null]}"#;
     let arg_node_info = serde_json::from_str::<node_info::NodeInfo>(text).unwrap();
 
-    let client = reqwest::blocking::Client::new();
+    let client = reqwest::Client::new();
     let res = client.post("http://localhost:8000/deserialize")
     .body(serde_json::to_string(&arg_node_info).unwrap())
-    .send().unwrap();
+    .send().await.unwrap();
 
-    println!("{:#?}", res.text());
+    println!("{:#?}", res.text().await);
 }
 
-fn req_rest_api_test() {    
+async fn req_rest_api_test() {    
     println!("client mode!\n");
-    //req_rest_api_test_inner_post();
-    //req_rest_api_test_inner_get_param_test();
-    req_rest_api_test_inner_get();
+    //req_rest_api_test_inner_post().await;
+    //req_rest_api_test_inner_get_param_test().await;
+    req_rest_api_test_inner_get().await;
 }
 
-fn main() {
+#[tokio::main]
+async fn main() {
     //引数処理
     let args: Vec<String> = env::args().collect();
     if args.len() == 2 {
         let num: i32 = args[1].parse().unwrap();
         if num == 2 { // REST client
-            req_rest_api_test();
+            req_rest_api_test().await;
         }
     }else if args.len() > 2 {
         let born_id: i32 = args[1].parse().unwrap();
@@ -102,6 +110,17 @@ fn main() {
         let tyukai_addr: String = args[4].parse().unwrap();
         let tyukai_port_num: i32 = args[5].parse().unwrap();
         let log_out_path: String = args[6].parse().unwrap();
+        // 設定ファイル(JSON)のパスの指定は省略可能
+        if args.len() > 7 {
+            let config_path: String = args[7].parse().unwrap();
+            match config::load_config(&config_path) {
+                Err(err) => {
+                    println!("failed to load config file: {:?} {:?}", &config_path, err);
+                    return;
+                }
+                Ok(_) => {}
+            };
+        }
         //TODO: (rustr) ログの出力先ディレクトリのパスも受けられるようにする
         //              ディレクトリがまだ存在しなければここの引数処理の中で作成してしまう
 
@@ -109,73 +128,84 @@ fn main() {
         //              （つまり、ロガーライブラリの初期化もグローバルに一度やればOK、みたいなものであればここでやる）
         println!("born_id={:?}, bind_addr={:?}, bind_port_num={:?}, tyukai_addr={:?}, tyukai_port_num={:?}, log_out_path={:?}", &born_id, &bind_addr, &bind_port_num, &tyukai_addr, &tyukai_port_num, &log_out_path);
 
-        let node_info = ArMu_new!(node_info::NodeInfo::new());
         let data_store = ArMu_new!(data_store::DataStore::new());
 
+        // 1プロセスでホストする仮想ノード. 0番の仮想ノードは仮想ノードを用いない場合のノードと同じ扱い
+        let vnode_count = vnode::calc_vnode_count();
+        let mut vnode_infos: Vec<ArMu<node_info::NodeInfo>> = vec![];
+        for _ in 0..vnode_count {
+            let vnode_info = ArMu_new!(node_info::NodeInfo::new());
+            vnode::register(Arc::clone(&vnode_info));
+            vnode_infos.push(vnode_info);
+        }
+        let node_info = Arc::clone(&vnode_infos[0]);
+
         let node_info_api_serv = Arc::clone(&node_info);
         let data_store_api_serv = Arc::clone(&data_store);
         let bind_addr_api_serv = bind_addr.clone();
 
-        let node_info_arc_succ_th = Arc::clone(&node_info);
-        let data_store_arc_succ_th = Arc::clone(&data_store);
-    
-        let node_info_arc_ftable_th = Arc::clone(&node_info);
-        let data_store_arc_ftable_th = Arc::clone(&data_store);
+        let data_store_bin_rpc_serv = Arc::clone(&data_store);
+        let bind_addr_bin_rpc_serv = bind_addr.clone();
 
-        std::thread::spawn(move|| {
-            endpoints::rest_api_server_start(Arc::clone(&node_info_api_serv), Arc::clone(&data_store_api_serv), bind_addr_api_serv, bind_port_num);
+        let api_serv_th_handle = tokio::spawn(async move {
+            endpoints::rest_api_server_start(Arc::clone(&node_info_api_serv), Arc::clone(&data_store_api_serv), bind_addr_api_serv, bind_port_num).await;
         });
 
-        std::thread::sleep(std::time::Duration::from_millis(1500 as u64));
+        // ノード間RPC用のバイナリプロトコルのリスナ
+        if tcp_rpc::is_binary_rpc_enabled() {
+            tokio::spawn(async move {
+                tcp_rpc::binary_rpc_server_start(data_store_bin_rpc_serv, bind_addr_bin_rpc_serv, bind_port_num + gval::BINARY_RPC_PORT_OFFSET).await;
+            });
+        }
+
+        tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
 
         // 仲介ノードを介してChordネットワークに参加する
+        let self_address = bind_addr.clone() + ":" + &bind_port_num.to_string();
         stabilizer::join(
             Arc::clone(&node_info),
-            &(bind_addr.clone() + ":" + &bind_port_num.to_string()),
+            &self_address,
             &(tyukai_addr + ":" + &tyukai_port_num.to_string()),
             born_id
-        );
-
-        std::thread::sleep(std::time::Duration::from_millis(500 as u64));
-
-
-        let mut counter = 0;
-        let stabilize_succ_th_handle = std::thread::spawn(move|| loop{
-            stabilizer::stabilize_successor(Arc::clone(&node_info_arc_succ_th));
-            counter += 1;
-            if counter % gval::FILL_SUCC_LIST_INTERVAL_TIMES == 0 {
-                // successor_info_listの0番要素以降を規定数まで埋める（埋まらない場合もある）
-                stabilizer::fill_succ_info_list(Arc::clone(&node_info_arc_succ_th));
-            }
-            //std::thread::sleep(std::time::Duration::from_millis(100 as u64));
-            std::thread::sleep(std::time::Duration::from_millis(500 as u64));
-        });
-    
-        let stabilize_ftable_th_handle = std::thread::spawn(move|| loop{
-            for idx in 1..(gval::ID_SPACE_BITS + 1){
-                    stabilizer::stabilize_finger_table(Arc::clone(&node_info_arc_ftable_th), idx as i32);
-                    //std::thread::sleep(std::time::Duration::from_millis(50 as u64));
-                    std::thread::sleep(std::time::Duration::from_millis(100 as u64));
-            }
-        });    
-
-        // std::thread::spawn(|| loop{
-        //     std::thread::sleep(std::time::Duration::from_millis((10000) as u64));
-        //     println!("req_rest_api_test!");
-        //     req_rest_api_test();
-        // });
-
-
-        let mut thread_handles = vec![];    
-        thread_handles.push(stabilize_succ_th_handle);
-        thread_handles.push(stabilize_ftable_th_handle);
-        
-    
-        // スレッド終了の待ち合わせ（終了してくるスレッドは基本的に無い）
-        for handle in thread_handles {
-            handle.join().unwrap();
+        ).await;
+
+        // 1番以降の仮想ノードは参加済みの0番の仮想ノードを仲介ノードとして参加する
+        for vnode_idx in 1..vnode_count {
+            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+            stabilizer::join(
+                Arc::clone(&vnode_infos[vnode_idx as usize]),
+                &vnode::gen_vnode_address(&self_address, vnode_idx),
+                &self_address,
+                vnode::gen_vnode_born_id(born_id, vnode_idx)
+            ).await;
         }
 
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+
+        // 途中で失敗したデータの転送の再開. DataStore はプロセスで共有しているため、仮想ノードの数によらず1つ
+        let data_store_transfer_th = Arc::clone(&data_store);
+        let transfer_resume_th_handle = tokio::spawn(async move { loop{
+            tokio::time::sleep(std::time::Duration::from_millis(gval::DATA_TRANSFER_RESUME_INTERVAL_MILLIS)).await;
+            transfer::resume_pending_transfers(Arc::clone(&data_store_transfer_th)).await;
+        }});
+
+        // クォータの判定に用いるクラスタ全体の使用量の集計. 書き込みの処理ではキャッシュした値のみを参照する
+        let node_info_usage_th = Arc::clone(&node_info);
+        let data_store_usage_th = Arc::clone(&data_store);
+        let usage_refresh_th_handle = tokio::spawn(async move { loop{
+            namespace::refresh_cluster_usages(Arc::clone(&node_info_usage_th), Arc::clone(&data_store_usage_th)).await;
+            tokio::time::sleep(std::time::Duration::from_millis(gval::NAMESPACE_USAGE_REFRESH_MILLIS)).await;
+        }});
+
+        // stabilize処理のタスクは管理用APIから停止や再開ができるよう stabilizer_service で管理する
+        stabilizer_service::init(&vnode_infos, Arc::clone(&data_store));
+        stabilizer_service::start();
+
+        // リスナが終了 (シャットダウン) した場合は、stabilize処理の実行中のものを終えてから終了する
+        api_serv_th_handle.await;
+        stabilizer_service::stop().await;
+        transfer_resume_th_handle.abort();
+        usage_refresh_th_handle.abort();
     }
 
 }
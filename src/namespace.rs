@@ -0,0 +1,457 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
+
+use crate::gval;
+use crate::node_info;
+use crate::chord_util;
+use crate::chord_node;
+use crate::data_store;
+use crate::router;
+use crate::endpoints;
+use crate::config;
+
+type ArMu<T> = Arc<Mutex<T>>;
+
+// ネームスペース (キー空間) の管理
+// キーはネームスペースごとにスコープされたハッシュ値 (chord_util::hash_key_in_namespace) でIDに変換される
+// ネームスペースの定義の一覧は、予約されたシステム用のネームスペースの1エントリとして
+// 通常のデータと同じくDHT上に保持し、各ノードは一定時間キャッシュして用いる
+//
+// クォータは厳密なものではない. クラスタ全体の使用量はバックグラウンドのタスク (refresh_cluster_usages) が
+// 一定間隔でリングを一周して集計し、書き込み時にはその値に自ノード経由で行われた書き込みの分のみを加算して判定するため、
+// 複数のノード経由で同時に書き込まれた場合は上限を多少超えることがある
+// 起動直後などでまだ集計が行われていない間は、使用量を0とみなす
+
+// クライアントがネームスペースを指定しなかった場合に用いられる. 内部的には空文字列で表す
+pub const DEFAULT_NAMESPACE : &str = "";
+pub const DEFAULT_NAMESPACE_DISPLAY_NAME : &str = "default";
+// ネームスペースの定義の一覧などを保持するためのもの. クライアントからは利用できない
+pub const SYSTEM_NAMESPACE : &str = "__system";
+const REGISTRY_KEY : &str = "namespaces";
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+#[serde(default)]
+pub struct NamespaceConfig {
+    pub name: String,
+    // マスターデータ相当のものを含めたデータのコピーの数
+    pub replication_factor: u32,
+    // 書き込み時にTTLが指定されなかった場合に用いるTTL (秒). 0 の場合は期限無し
+    pub default_ttl_sec: u64,
+    // キーの数の上限. 0 の場合は無制限
+    pub max_keys: u64,
+    // 値のバイト数の合計の上限. 0 の場合は無制限
+    pub max_bytes: u64
+}
+
+impl Default for NamespaceConfig {
+    fn default() -> Self {
+        NamespaceConfig {
+            name: "".to_string(),
+            replication_factor: config::get_default_replication_factor(),
+            default_ttl_sec: 0,
+            max_keys: 0,
+            max_bytes: 0
+        }
+    }
+}
+
+impl NamespaceConfig {
+    pub fn has_quota(&self) -> bool {
+        return self.max_keys != 0 || self.max_bytes != 0;
+    }
+
+    // ttl_sec が None の場合はネームスペースのデフォルトのTTLを用いる
+    pub fn calc_expire_at(&self, ttl_sec: Option<u64>) -> Result<i64, chord_util::GeneralError> {
+        let ttl = match ttl_sec {
+            None => self.default_ttl_sec,
+            Some(ttl) => ttl
+        };
+        if ttl == 0 {
+            return Ok(0);
+        }
+        match validate_ttl_sec(ttl) {
+            Err(err) => { return Err(err); }
+            Ok(_) => {}
+        };
+        // 上限の検証済みのため i64 に収まるが、念のため桁あふれを検査する
+        return match (ttl as i64).checked_mul(1000).and_then(|ttl_millis| chord_util::get_unixtime_in_millis().checked_add(ttl_millis)) {
+            None => Err(chord_util::GeneralError::new("ttl_sec is too large: ".to_string() + ttl.to_string().as_str(), chord_util::ERR_CODE_INVALID_ARGUMENT)),
+            Some(expire_at) => Ok(expire_at)
+        };
+    }
+}
+
+// ネームスペースの削除の結果
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct DropNamespaceResult {
+    pub removed_count: u64,
+    // データの削除の依頼に失敗したノード. 空でない場合、それらのノードにはデータが残っている可能性がある
+    pub failed_nodes: Vec<String>
+}
+
+lazy_static! {
+    static ref REGISTRY_CACHE : Mutex<Option<(Vec<NamespaceConfig>, Instant)>> = Mutex::new(None);
+    // キーはネームスペース名
+    static ref USAGE_CACHE : Mutex<Option<(HashMap<String, data_store::NamespaceUsage>, Instant)>> = Mutex::new(None);
+}
+
+// クライアントから指定されたネームスペース名を内部での表現に変換する
+pub fn resolve_request_namespace(ns: Option<String>) -> String {
+    return match ns {
+        None => DEFAULT_NAMESPACE.to_string(),
+        Some(name) => {
+            if name.len() == 0 || name == DEFAULT_NAMESPACE_DISPLAY_NAME {
+                DEFAULT_NAMESPACE.to_string()
+            } else {
+                name
+            }
+        }
+    };
+}
+
+// ノードごとに設定が異なっていても同じ位置のコピーを参照できるよう、レプリケーションファクタは固定の値とする
+fn system_namespace_config() -> NamespaceConfig {
+    return NamespaceConfig { name: SYSTEM_NAMESPACE.to_string(), replication_factor: gval::SYSTEM_NAMESPACE_REPLICATION_FACTOR, ..NamespaceConfig::default() };
+}
+
+fn validate_ttl_sec(ttl_sec: u64) -> Result<bool, chord_util::GeneralError> {
+    if ttl_sec > gval::MAX_TTL_SEC {
+        return Err(chord_util::GeneralError::new("ttl_sec must be equal or less than ".to_string() + gval::MAX_TTL_SEC.to_string().as_str(), chord_util::ERR_CODE_INVALID_ARGUMENT));
+    }
+    return Ok(true);
+}
+
+fn validate_namespace_config(conf: &NamespaceConfig) -> Result<bool, chord_util::GeneralError> {
+    if conf.name.len() == 0 || conf.name.len() > gval::NAMESPACE_NAME_MAX_LEN
+        || conf.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') == false {
+        return Err(chord_util::GeneralError::new("namespace name must be 1 to ".to_string() + gval::NAMESPACE_NAME_MAX_LEN.to_string().as_str() + " characters of [A-Za-z0-9_-]", chord_util::ERR_CODE_INVALID_ARGUMENT));
+    }
+    if conf.name == DEFAULT_NAMESPACE_DISPLAY_NAME || conf.name.starts_with("__") {
+        return Err(chord_util::GeneralError::new("namespace name is reserved: ".to_string() + conf.name.as_str(), chord_util::ERR_CODE_INVALID_ARGUMENT));
+    }
+    match validate_ttl_sec(conf.default_ttl_sec) {
+        Err(err) => { return Err(err); }
+        Ok(_) => {}
+    };
+    return config::validate_replication_factor(conf.replication_factor);
+}
+
+fn registry_data_id() -> u32 {
+    return chord_util::hash_key_in_namespace(SYSTEM_NAMESPACE, &REGISTRY_KEY.to_string());
+}
+
+async fn load_registry(self_node: ArMu<node_info::NodeInfo>) -> Result<Vec<NamespaceConfig>, chord_util::GeneralError> {
+    let sys_conf = system_namespace_config();
+    let registry_iv = match chord_node::get_replicas(Arc::clone(&self_node), registry_data_id(), sys_conf.replication_factor).await {
+        Err(err) => {
+            if err.err_code == chord_util::ERR_CODE_QUERIED_DATA_NOT_FOUND {
+                // まだネームスペースが一度も作成されていない
+                return Ok(vec![]);
+            }
+            return Err(err);
+        }
+        Ok(iv) => iv
+    };
+
+    let registry = match serde_json::from_str::<Vec<NamespaceConfig>>(&registry_iv.val_str) {
+        Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_INTERNAL_CONTROL_FLOW_PROBLEM)); }
+        Ok(registry) => registry
+    };
+
+    {
+        let mut cache_ref = REGISTRY_CACHE.lock().unwrap();
+        *cache_ref = Some((registry.clone(), Instant::now()));
+    }
+
+    return Ok(registry);
+}
+
+async fn store_registry(self_node: ArMu<node_info::NodeInfo>, registry: &Vec<NamespaceConfig>) -> Result<bool, chord_util::GeneralError> {
+    let registry_json = match serde_json::to_string(registry) {
+        Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_INTERNAL_CONTROL_FLOW_PROBLEM)); }
+        Ok(json) => json
+    };
+
+    let sys_conf = system_namespace_config();
+    match chord_node::put_replicas(Arc::clone(&self_node), SYSTEM_NAMESPACE, registry_data_id(), registry_json, 0, sys_conf.replication_factor).await {
+        Err(err) => { return Err(err); }
+        Ok((_, 0)) => { return Err(chord_util::GeneralError::new("failed to write namespace registry".to_string(), chord_util::ERR_CODE_NO_REPLICA_WRITTEN)); }
+        Ok(_) => {}
+    };
+
+    invalidate_registry_cache();
+    return Ok(true);
+}
+
+fn invalidate_registry_cache() {
+    let mut cache_ref = REGISTRY_CACHE.lock().unwrap();
+    *cache_ref = None;
+}
+
+// キャッシュが有効であればキャッシュから返す
+async fn get_registry(self_node: ArMu<node_info::NodeInfo>) -> Result<Vec<NamespaceConfig>, chord_util::GeneralError> {
+    {
+        let cache_ref = REGISTRY_CACHE.lock().unwrap();
+        match &*cache_ref {
+            Some((registry, cached_at)) if cached_at.elapsed() < Duration::from_millis(gval::NAMESPACE_REGISTRY_CACHE_MILLIS) => {
+                return Ok(registry.clone());
+            }
+            _ => {}
+        };
+    }
+    return load_registry(self_node).await;
+}
+
+// クライアントからの読み書きの対象のネームスペースの設定を返す
+pub async fn get_namespace_config(self_node: ArMu<node_info::NodeInfo>, namespace: &str) -> Result<NamespaceConfig, chord_util::GeneralError> {
+    if namespace == DEFAULT_NAMESPACE {
+        return Ok(NamespaceConfig::default());
+    }
+
+    if namespace.starts_with("__") == false {
+        let registry = match get_registry(self_node).await {
+            Err(err) => { return Err(err); }
+            Ok(registry) => registry
+        };
+        for conf in registry {
+            if conf.name == namespace {
+                return Ok(conf);
+            }
+        }
+    }
+
+    return Err(chord_util::GeneralError::new("namespace not found: ".to_string() + namespace, chord_util::ERR_CODE_NAMESPACE_NOT_FOUND));
+}
+
+// 一覧の読み出しと書き込みの間に他のノード経由で一覧が更新された場合、一方の更新は失われる
+// (ネームスペースの作成・削除は頻繁に行うものではないため許容する)
+pub async fn create_namespace(self_node: ArMu<node_info::NodeInfo>, conf: NamespaceConfig) -> Result<bool, chord_util::GeneralError> {
+    match validate_namespace_config(&conf) {
+        Err(err) => { return Err(err); }
+        Ok(_) => {}
+    };
+
+    let mut registry = match load_registry(Arc::clone(&self_node)).await {
+        Err(err) => { return Err(err); }
+        Ok(registry) => registry
+    };
+    if registry.iter().any(|existing| existing.name == conf.name) {
+        return Err(chord_util::GeneralError::new("namespace already exists: ".to_string() + conf.name.as_str(), chord_util::ERR_CODE_NAMESPACE_ALREADY_EXISTS));
+    }
+
+    chord_util::dprint(&("create_namespace,".to_string() + conf.name.as_str()));
+    registry.push(conf);
+    return store_registry(self_node, &registry).await;
+}
+
+pub async fn list_namespaces(self_node: ArMu<node_info::NodeInfo>) -> Result<Vec<NamespaceConfig>, chord_util::GeneralError> {
+    return load_registry(self_node).await;
+}
+
+// ネームスペースを一覧から削除した上で、リング上の全ノードにそのネームスペースのデータの削除を依頼する
+// 全ノードを把握できなかった場合は何も変更せずにエラーを返す
+pub async fn drop_namespace(self_node: ArMu<node_info::NodeInfo>, namespace: String) -> Result<DropNamespaceResult, chord_util::GeneralError> {
+    let mut registry = match load_registry(Arc::clone(&self_node)).await {
+        Err(err) => { return Err(err); }
+        Ok(registry) => registry
+    };
+    if registry.iter().any(|existing| existing.name == namespace) == false {
+        return Err(chord_util::GeneralError::new("namespace not found: ".to_string() + namespace.as_str(), chord_util::ERR_CODE_NAMESPACE_NOT_FOUND));
+    }
+
+    let addresses = match router::collect_ring_node_addresses(Arc::clone(&self_node)).await {
+        Err(err) => { return Err(err); }
+        Ok(addresses) => addresses
+    };
+
+    // 先に一覧から削除し、以降の書き込みを受け付けないようにする
+    registry.retain(|existing| existing.name != namespace);
+    match store_registry(Arc::clone(&self_node), &registry).await {
+        Err(err) => { return Err(err); }
+        Ok(_) => {}
+    };
+
+    chord_util::dprint(&("drop_namespace,".to_string() + namespace.as_str() + "," + addresses.len().to_string().as_str()));
+
+    let mut result = DropNamespaceResult { removed_count: 0, failed_nodes: vec![] };
+    for address in addresses {
+        match endpoints::rrpc_call__drop_namespace_datas(&address, namespace.clone()).await {
+            Err(err) => {
+                chord_util::dprint(&("ERROR at drop_namespace,".to_string() + address.as_str() + "," + err.to_string().as_str()));
+                result.failed_nodes.push(address);
+            }
+            Ok(removed_count) => {
+                result.removed_count += removed_count;
+            }
+        };
+    }
+
+    return Ok(result);
+}
+
+// drop_namespace から各ノードに対して呼び出される
+pub fn drop_local_namespace_datas(data_store: ArMu<data_store::DataStore>, namespace: String) -> Result<u64, chord_util::GeneralError> {
+    if namespace == DEFAULT_NAMESPACE || namespace == SYSTEM_NAMESPACE {
+        return Err(chord_util::GeneralError::new("namespace can not be dropped: ".to_string() + namespace.as_str(), chord_util::ERR_CODE_INVALID_ARGUMENT));
+    }
+
+    // 削除済みのネームスペースへの書き込みを受け付けないよう、キャッシュしている定義を破棄する
+    invalidate_registry_cache();
+
+    let removed_count;
+    {
+        let mut data_store_ref = data_store.lock().unwrap();
+        removed_count = data_store_ref.remove_namespace_datas(namespace.as_str());
+    }
+    {
+        let mut cache_ref = USAGE_CACHE.lock().unwrap();
+        match &mut *cache_ref {
+            None => {}
+            Some((usages, _)) => { usages.remove(&namespace); }
+        };
+    }
+
+    chord_util::dprint(&("drop_local_namespace_datas,".to_string() + namespace.as_str() + "," + removed_count.to_string().as_str()));
+    return Ok(removed_count as u64);
+}
+
+// 自ノードが保持するデータについてのネームスペースごとの使用量を返す
+pub fn get_local_namespace_usages(data_store: ArMu<data_store::DataStore>) -> HashMap<String, data_store::NamespaceUsage> {
+    let mut data_store_ref = data_store.lock().unwrap();
+    data_store_ref.purge_expired_datas();
+    return data_store_ref.get_namespace_usages();
+}
+
+// リング上の全ノードの使用量を集計する. 応答の無かったノードの分は含まれない
+async fn collect_cluster_usages(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::DataStore>) -> HashMap<String, data_store::NamespaceUsage> {
+    let mut total_usages = get_local_namespace_usages(data_store);
+
+    let addresses = match router::collect_ring_node_addresses(Arc::clone(&self_node)).await {
+        Err(err) => {
+            chord_util::dprint(&("ERROR at collect_cluster_usages,".to_string() + err.to_string().as_str()));
+            return total_usages;
+        }
+        Ok(addresses) => addresses
+    };
+
+    // 先頭は自ノード
+    for address in addresses.iter().skip(1) {
+        match endpoints::rrpc_call__get_namespace_usages(address).await {
+            Err(err) => {
+                chord_util::dprint(&("ERROR at collect_cluster_usages,".to_string() + address.as_str() + "," + err.to_string().as_str()));
+            }
+            Ok(usages) => {
+                for (namespace, usage) in usages {
+                    let total = total_usages.entry(namespace).or_default();
+                    total.key_count += usage.key_count;
+                    total.bytes += usage.bytes;
+                }
+            }
+        };
+    }
+
+    return total_usages;
+}
+
+// クォータが設定されたネームスペースがある場合に、クラスタ全体の使用量を集計してキャッシュを更新する
+// gval::NAMESPACE_USAGE_REFRESH_MILLIS 間隔でバックグラウンドのタスクから呼び出される
+pub async fn refresh_cluster_usages(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::DataStore>) {
+    let registry = match get_registry(Arc::clone(&self_node)).await {
+        Err(err) => {
+            chord_util::dprint(&("ERROR at refresh_cluster_usages,".to_string() + err.to_string().as_str()));
+            return;
+        }
+        Ok(registry) => registry
+    };
+    if registry.iter().any(|conf| conf.has_quota()) == false {
+        return;
+    }
+
+    let usages = collect_cluster_usages(self_node, data_store).await;
+    let mut cache_ref = USAGE_CACHE.lock().unwrap();
+    *cache_ref = Some((usages, Instant::now()));
+}
+
+// キャッシュしているクラスタ全体の使用量を返す. 書き込みの度にリングを一周しないよう、集計は行わない
+fn get_cached_cluster_usage(namespace: &str) -> data_store::NamespaceUsage {
+    let cache_ref = USAGE_CACHE.lock().unwrap();
+    return match &*cache_ref {
+        None => data_store::NamespaceUsage::new(),
+        Some((usages, _)) => match usages.get(namespace) {
+            None => data_store::NamespaceUsage::new(),
+            Some(usage) => usage.clone()
+        }
+    };
+}
+
+// 自ノード経由で行われた書き込みの分を、キャッシュしている使用量に反映する
+pub fn add_usage_delta(namespace: &str, key_count_delta: i64, bytes_delta: i64) {
+    let mut cache_ref = USAGE_CACHE.lock().unwrap();
+    match &mut *cache_ref {
+        None => {}
+        Some((usages, _)) => {
+            let usage = usages.entry(namespace.to_string()).or_insert(data_store::NamespaceUsage::new());
+            usage.key_count = (usage.key_count as i64 + key_count_delta).max(0) as u64;
+            usage.bytes = (usage.bytes as i64 + bytes_delta).max(0) as u64;
+        }
+    };
+}
+
+fn is_within_quota(conf: &NamespaceConfig, key_count: u64, bytes: u64) -> bool {
+    return (conf.max_keys == 0 || key_count <= conf.max_keys) && (conf.max_bytes == 0 || bytes <= conf.max_bytes);
+}
+
+// data_id のデータに val_len バイトの値を書き込んだ場合にクォータを超えないかを確認する
+pub async fn check_quota(self_node: ArMu<node_info::NodeInfo>, conf: &NamespaceConfig, data_id: u32, val_len: u64) -> Result<bool, chord_util::GeneralError> {
+    let usage = get_cached_cluster_usage(conf.name.as_str());
+    if is_within_quota(conf, usage.key_count + 1, usage.bytes + val_len) {
+        return Ok(true);
+    }
+
+    // 上限に達している場合でも、既存のキーの上書きでありサイズが収まるのであれば許容する
+    match chord_node::get_replicas(self_node, data_id, conf.replication_factor).await {
+        Err(_) => {}
+        Ok(old_iv) => {
+            if old_iv.val_str != data_store::DELETED_ENTRY_MARKING_STR
+                && is_within_quota(conf, usage.key_count, (usage.bytes + val_len).saturating_sub(old_iv.val_str.len() as u64)) {
+                return Ok(true);
+            }
+        }
+    };
+
+    return Err(chord_util::GeneralError::new("quota of namespace is exceeded: ".to_string() + conf.name.as_str(), chord_util::ERR_CODE_QUOTA_EXCEEDED));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gen_conf(default_ttl_sec: u64) -> NamespaceConfig {
+        return NamespaceConfig { name: "team_a".to_string(), default_ttl_sec: default_ttl_sec, ..NamespaceConfig::default() };
+    }
+
+    #[test]
+    fn calc_expire_at_rejects_too_large_ttl() {
+        let conf = gen_conf(0);
+        assert_eq!(conf.calc_expire_at(None).unwrap(), 0);
+        assert_eq!(conf.calc_expire_at(Some(0)).unwrap(), 0);
+        assert!(conf.calc_expire_at(Some(gval::MAX_TTL_SEC)).unwrap() > chord_util::get_unixtime_in_millis());
+
+        // i64 に変換すると負になる値や、ミリ秒への変換で桁あふれする値も拒否される
+        for ttl in [gval::MAX_TTL_SEC + 1, 10u64.pow(16), 1u64 << 63, u64::MAX] {
+            let err = conf.calc_expire_at(Some(ttl)).unwrap_err();
+            assert_eq!(err.err_code, chord_util::ERR_CODE_INVALID_ARGUMENT);
+        }
+    }
+
+    #[test]
+    fn validate_namespace_config_checks_default_ttl() {
+        assert!(validate_namespace_config(&gen_conf(gval::MAX_TTL_SEC)).is_ok());
+        let err = validate_namespace_config(&gen_conf(u64::MAX)).unwrap_err();
+        assert_eq!(err.err_code, chord_util::ERR_CODE_INVALID_ARGUMENT);
+    }
+}
@@ -9,6 +9,11 @@ use crate::stabilizer;
 use crate::endpoints;
 use crate::data_store;
 use crate::router;
+use crate::conn_pool;
+use crate::vnode;
+use crate::location_cache;
+use crate::stabilize_rate;
+use crate::failure_detector;
 
 type ArMu<T> = Arc<Mutex<T>>;
 
@@ -20,22 +25,42 @@ pub struct NodeInfo {
     // デバッグ用のID
     // 何ノード目として生成されたかの値
     pub born_id : i32,
-    // 以下の2つはNodeInfoオブジェクトを保持.
+    // ノードが属する障害ドメイン (ゾーンやラック) のラベル. レプリカの配置に用いる
+    #[serde(default)]
+    pub zone : String,
+    // 自ノードからこのノードへのRPCの往復時間 (マイクロ秒) の平滑化した値. 未計測の場合は 0
+    // 経路表のエントリとして保持しているものにのみ設定され、フィンガーの選択と closest_preceding_finger での経由先の選択に用いる
+    #[serde(default)]
+    pub rtt_micros : u64,
+    // 以下の3つは NodeInfoEntry オブジェクトを保持.
     // ある時点で取得したものが保持されており、変化する場合のあるフィールド
     // の内容は最新の内容となっているとは限らないため注意が必要.
-    // そのような情報が必要な場合はChordNodeオブジェクトから参照し、
-    // 必要であれば、その際に下のフィールドにdeepcopyを設定しなおさ
-    // なければならない.
+    // そのような情報が必要な場合は当該ノードにRPCで問い合わせなければならない.
 
     // 状況に応じて伸縮するが、インデックス0には必ず 非None な要素が入っている
     // ように制御する
-    pub successor_info_list: Vec<NodeInfo>,
+    pub successor_info_list: Vec<NodeInfoEntry>,
     // 要素数が0もしくは1のVecとして定義する。Noneに対応する状態はlen()の結果が0の時
     // 格納されている要素自体はimmutableとして扱わなければならないので注意
-    pub predecessor_info: Vec<NodeInfo>,
-    // NodeInfoオブジェクトを要素として持つリスト
+    pub predecessor_info: Vec<NodeInfoEntry>,
+    // NodeInfoEntryオブジェクトを要素として持つリスト
     // インデックスの小さい方から狭い範囲が格納される形で保持する
-    pub finger_table: Vec<Option<NodeInfo>>,
+    pub finger_table: Vec<Option<NodeInfoEntry>>,
+}
+
+// 経路表 (successor_info_list, predecessor_info, finger_table) のエントリ
+// NodeInfo と異なり経路表を持たないため、RPCでやりとりする際にも入れ子にならない
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct NodeInfoEntry {
+    pub node_id : u32,
+    pub address_str: String,
+    pub born_id : i32,
+    #[serde(default)]
+    pub zone : String,
+    // NodeInfo の rtt_micros と同様
+    #[serde(default)]
+    pub rtt_micros : u64
 }
 
 // routerモジュールの中で利用する、通信量を減らすための必要な情報だけのNodeInfo
@@ -44,7 +69,15 @@ pub struct NodeInfo {
 pub struct NodeInfoSummary {
     pub node_id: u32,
     pub succ0_id: u32,
-    pub address_str: String
+    pub address_str: String,
+    #[serde(default)]
+    pub zone: String
+}
+
+impl Default for NodeInfo {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl NodeInfo {
@@ -53,6 +86,8 @@ impl NodeInfo {
             node_id : 0, //TODO: node_idの初期値を-1から0に変更したので注意
             address_str: "".to_string(),
             born_id : -1,
+            zone: "".to_string(),
+            rtt_micros: 0,
             successor_info_list : Vec::new(),
             predecessor_info : Vec::new(),
             finger_table : vec![None; gval::ID_SPACE_BITS as usize]
@@ -61,16 +96,9 @@ impl NodeInfo {
 }
 
 
-// 単純にdeepcopyするとチェーン構造になっているものが全てコピーされてしまう
-// ため、そこの考慮を行ったデータを返す
-// 上述の考慮により、コピーした NodeInfoオブジェクト の successor_infoと
-// predecessor_info および finger_table は deepcopy の対象ではあるが、
-// それらには空のVecが設定される. これにより、あるノードがコピーされた NodeInfo を保持
-// していても、自身のpredecessor や successor は自身が保持しているそれらのNodeInfo
-// オブジェクトの情報から辿ることができるが、その先は辿ることは直接的にはできないことになる
-//（predecessor や successor の NodeInfoオブジェクトをRPCで当該ノードから取得すれば可能）
-// 用途としては、あるノードの NodeInfo を他のノードが取得し保持する際に利用される
-// ことを想定して実装されている.
+// 経路表はコピーの対象とせず、successor_info_list と predecessor_info には空のVecが、
+// finger_table には全て None のVecが設定される
+// 経路表も含めたコピーが必要な場合は partial_clone_from_ref_strong を用いる
 impl Clone for NodeInfo {
     fn clone(&self) -> Self {
         // let mut ret_node_info = NodeInfo::new();
@@ -84,6 +112,8 @@ impl Clone for NodeInfo {
             node_id: self.node_id,
             address_str: self.address_str.clone(),
             born_id: self.born_id,
+            zone: self.zone.clone(),
+            rtt_micros: self.rtt_micros,
             successor_info_list: vec![],
             predecessor_info: vec![],
             finger_table: vec![None; gval::ID_SPACE_BITS as usize]
@@ -93,101 +123,87 @@ impl Clone for NodeInfo {
 
 impl Clone for NodeInfoSummary {
     fn clone(&self) -> Self {
-        return NodeInfoSummary{ node_id: self.node_id, succ0_id: self.succ0_id, address_str: self.address_str.clone() };
+        return NodeInfoSummary{ node_id: self.node_id, succ0_id: self.succ0_id, address_str: self.address_str.clone(), zone: self.zone.clone() };
     }  
 }
 
 // 実体の参照からコピーを作成する
 // cloneした場合と異なり、predecessor_info, successor_info_list, finger_table
-// も一段階だけは値を埋めて返す（各NodeInfoオブジェクトはcloneされたもの）
+// もコピーして返す
 pub fn partial_clone_from_ref_strong(node_info_ref: &NodeInfo) -> NodeInfo {
-    //let mut ret_node_info = NodeInfo::new();
-    // ret_node_info.node_id = node_info_ref.node_id;
-    // ret_node_info.address_str = node_info_ref.address_str.clone();
-    // ret_node_info.born_id = node_info_ref.born_id;
-    let mut successor_info_list = vec![];
-    for each_ninfo in &node_info_ref.successor_info_list {
-        successor_info_list.push((*each_ninfo).clone());
-    }
-    let mut finger_table = vec![];
-    for each_ninfo in &node_info_ref.finger_table {
-        let tmp_val = match each_ninfo {
-            None => None,
-            Some(val) => {
-                let ret_val = Some((*val).clone());
-                ret_val
-            }
-        };
-        finger_table.push(tmp_val);
-    }    
-    let mut predecessor_info = vec![];
-    for each_ninfo in &node_info_ref.predecessor_info {
-        predecessor_info.push((*each_ninfo).clone());
-    }
-
-    //return ret_node_info;
     return NodeInfo{
         node_id: node_info_ref.node_id,
         address_str: node_info_ref.address_str.clone(),
         born_id: node_info_ref.born_id,
-        successor_info_list: successor_info_list,
-        predecessor_info: predecessor_info,
-        finger_table: finger_table
+        zone: node_info_ref.zone.clone(),
+        rtt_micros: node_info_ref.rtt_micros,
+        successor_info_list: node_info_ref.successor_info_list.clone(),
+        predecessor_info: node_info_ref.predecessor_info.clone(),
+        finger_table: node_info_ref.finger_table.clone()
     };
 }
 
 // 実体の参照からコピーを作成する
 // cloneした場合と異なり、predecessor_info, successor_info_list
-// も一段階だけは値を埋めて返す（各NodeInfoオブジェクトはcloneされたもの）
+// もコピーして返す
 pub fn partial_clone_from_ref_strong_without_ftable(node_info_ref: &NodeInfo) -> NodeInfo {
-    //let mut ret_node_info = NodeInfo::new();
-    // ret_node_info.node_id = node_info_ref.node_id;
-    // ret_node_info.address_str = node_info_ref.address_str.clone();
-    // ret_node_info.born_id = node_info_ref.born_id;
-    let mut successor_info_list = vec![];
-    for each_ninfo in &node_info_ref.successor_info_list {
-        successor_info_list.push((*each_ninfo).clone());
-    }
-    // let mut finger_table = vec![];
-    // for each_ninfo in &node_info_ref.finger_table {
-    //     let tmp_val = match each_ninfo {
-    //         None => None,
-    //         Some(val) => {
-    //             let ret_val = Some((*val).clone());
-    //             ret_val
-    //         }
-    //     };
-    //     finger_table.push(tmp_val);
-    // }    
-    let mut predecessor_info = vec![];
-    for each_ninfo in &node_info_ref.predecessor_info {
-        predecessor_info.push((*each_ninfo).clone());
-    }
-
-    //return ret_node_info;
     return NodeInfo{
         node_id: node_info_ref.node_id,
         address_str: node_info_ref.address_str.clone(),
         born_id: node_info_ref.born_id,
-        successor_info_list: successor_info_list,
-        predecessor_info: predecessor_info,
+        zone: node_info_ref.zone.clone(),
+        rtt_micros: node_info_ref.rtt_micros,
+        successor_info_list: node_info_ref.successor_info_list.clone(),
+        predecessor_info: node_info_ref.predecessor_info.clone(),
         finger_table: vec![]
     };
 }
 
 pub fn gen_summary_node_info(node_info_ref: &NodeInfo) -> NodeInfoSummary {
-    return NodeInfoSummary { node_id: node_info_ref.node_id, succ0_id: node_info_ref.successor_info_list[0].node_id, address_str: node_info_ref.address_str.clone() }
+    return NodeInfoSummary { node_id: node_info_ref.node_id, succ0_id: node_info_ref.successor_info_list[0].node_id, address_str: node_info_ref.address_str.clone(), zone: node_info_ref.zone.clone() }
 }
 
 pub fn gen_node_info_from_summary(summary_ref: &NodeInfoSummary) -> NodeInfo {
     let mut ret_ninfo = NodeInfo::new();
     ret_ninfo.node_id = summary_ref.node_id;
     ret_ninfo.address_str = summary_ref.address_str.clone();
+    ret_ninfo.zone = summary_ref.zone.clone();
+    return ret_ninfo;
+}
+
+// 経路表に保持するためのエントリを作成する
+pub fn gen_entry(node_info_ref: &NodeInfo) -> NodeInfoEntry {
+    return NodeInfoEntry { node_id: node_info_ref.node_id, address_str: node_info_ref.address_str.clone(), born_id: node_info_ref.born_id, zone: node_info_ref.zone.clone(), rtt_micros: node_info_ref.rtt_micros };
+}
+
+// born_id は分からないため -1 とする
+pub fn gen_entry_from_summary(summary_ref: &NodeInfoSummary) -> NodeInfoEntry {
+    return NodeInfoEntry { node_id: summary_ref.node_id, address_str: summary_ref.address_str.clone(), born_id: -1, zone: summary_ref.zone.clone(), rtt_micros: 0 };
+}
+
+// エントリの指すノードへのRPCの呼び出しなど、NodeInfo が必要な場合に用いる. 経路表は空となる
+pub fn gen_node_info_from_entry(entry_ref: &NodeInfoEntry) -> NodeInfo {
+    let mut ret_ninfo = NodeInfo::new();
+    ret_ninfo.node_id = entry_ref.node_id;
+    ret_ninfo.address_str = entry_ref.address_str.clone();
+    ret_ninfo.born_id = entry_ref.born_id;
+    ret_ninfo.zone = entry_ref.zone.clone();
+    ret_ninfo.rtt_micros = entry_ref.rtt_micros;
     return ret_ninfo;
 }
 
-pub fn set_pred_info(self_node: ArMu<NodeInfo>, node_info: NodeInfo){
+// エントリの指すノードの、successor を含む要約を作成する (successor は get_successor などで別途得たもの)
+pub fn gen_summary_from_entry(entry_ref: &NodeInfoEntry, succ0_id: u32) -> NodeInfoSummary {
+    return NodeInfoSummary { node_id: entry_ref.node_id, succ0_id: succ0_id, address_str: entry_ref.address_str.clone(), zone: entry_ref.zone.clone() };
+}
+
+pub fn set_pred_info(self_node: ArMu<NodeInfo>, node_info: NodeInfoEntry){
     let mut self_node_ref = self_node.lock().unwrap();
+    // predecessor が変わった場合はノードの参加や離脱があったものとして stabilize の間隔を縮める
+    if self_node_ref.predecessor_info.len() == 0 || self_node_ref.predecessor_info[0].node_id != node_info.node_id
+        || self_node_ref.predecessor_info[0].address_str != node_info.address_str {
+        stabilize_rate::notify_churn(&self_node_ref.address_str);
+    }
     if self_node_ref.predecessor_info.len() == 0 {
         self_node_ref.predecessor_info.push(node_info);
     }else{
@@ -195,18 +211,66 @@ pub fn set_pred_info(self_node: ArMu<NodeInfo>, node_info: NodeInfo){
     }
 }
 
-// RPC呼出しが接続失敗やタイムアウトで終了した場合、保持しているルーティングに関する情報の各々について
+// RPC呼出しが接続失敗やタイムアウト、サーキットブレーカーにより終了した場合、保持しているルーティングに関する情報の各々について
 // 反映する
+// 1回の失敗ではノードを経路情報から取り除かず、failure_detector でダウンしていると判定された (Dead) 場合に取り除く
+// (判定は RPC の失敗を契機に failure_detector が行い、Dead と判定した時点で全仮想ノードの経路情報からも取り除く)
 pub fn handle_downed_node_info(self_node: &mut NodeInfo, target_node: &NodeInfo, err: &chord_util::GeneralError){
     chord_util::dprint(&("handle_downed_node_info called!".to_string()));
 
+    if chord_util::is_unreachable_err(err) == false {
+        return;
+    }
+
+    // ダウンした可能性のあるノードとのコネクションは使いまわせないので破棄する
+    // ダウンした可能性のあるノードが担当ノードとしてキャッシュされている範囲も用いない
+    if target_node.node_id != self_node.node_id {
+        conn_pool::evict_peer(&vnode::get_physical_address(&target_node.address_str));
+        location_cache::invalidate_node(&target_node.address_str);
+    }
+    stabilize_rate::notify_churn(&self_node.address_str);
+
+    if target_node.address_str != self_node.address_str
+        && failure_detector::get_state(&target_node.address_str) != failure_detector::PeerState::Dead {
+        chord_util::dprint(&("handle_downed_node_info_not_dead,".to_string() + chord_util::gen_debug_str_of_node(target_node).as_str()));
+        return;
+    }
+    remove_downed_node(self_node, target_node);
+}
+
+// failure_detector がダウンしていると判定したプロセス (物理アドレス) の全仮想ノードを経路情報から取り除く
+pub fn remove_dead_peer(self_node: &mut NodeInfo, physical_address: &String){
+    let mut downed_nodes: Vec<NodeInfoEntry> = vec![];
+    let entries = self_node.successor_info_list.iter()
+        .chain(self_node.predecessor_info.iter())
+        .chain(self_node.finger_table.iter().flatten());
+    for entry in entries {
+        if vnode::get_physical_address(&entry.address_str) == *physical_address
+            && downed_nodes.iter().any(|downed| downed.node_id == entry.node_id) == false {
+            downed_nodes.push(entry.clone());
+        }
+    }
+    if downed_nodes.len() == 0 {
+        return;
+    }
+    stabilize_rate::notify_churn(&self_node.address_str);
+    for downed_node in downed_nodes {
+        chord_util::dprint(&("remove_dead_peer,".to_string() + chord_util::gen_debug_str_of_node(self_node).as_str() + ","
+            + chord_util::gen_debug_str_of_entry(&downed_node).as_str()));
+        location_cache::invalidate_node(&downed_node.address_str);
+        remove_downed_node(self_node, &gen_node_info_from_entry(&downed_node));
+    }
+}
+
+// ダウンしたノードを経路情報から取り除く
+fn remove_downed_node(self_node: &mut NodeInfo, target_node: &NodeInfo){
     //successorについて
-    if err.err_code == chord_util::ERR_CODE_HTTP_REQUEST_ERR {
+    {
         // successor_info_listを先頭から確認しダウンが判明したノード以外を残す
-        let mut new_succ_info_list: Vec<NodeInfo> = vec![];
+        let mut new_succ_info_list: Vec<NodeInfoEntry> = vec![];
         for ninfo in &self_node.successor_info_list {
             if ninfo.node_id != self_node.node_id && ninfo.node_id != target_node.node_id {
-                chord_util::dprint(&("insert new successor!,".to_string() + chord_util::gen_debug_str_of_node(ninfo).as_str()));
+                chord_util::dprint(&("insert new successor!,".to_string() + chord_util::gen_debug_str_of_entry(ninfo).as_str()));
                 new_succ_info_list.push((*ninfo).clone());
             }
         }
@@ -214,17 +278,14 @@ pub fn handle_downed_node_info(self_node: &mut NodeInfo, target_node: &NodeInfo,
     }
 
     // predecessorについて
-    if self_node.predecessor_info.len() != 0 {
-        if err.err_code == chord_util::ERR_CODE_HTTP_REQUEST_ERR 
-            && target_node.node_id == self_node.predecessor_info[0].node_id {
-            // predecessorであった場合は predecessor のまま設定しておくと都合が悪いので
-            // お役御免とする
-            self_node.predecessor_info.clear();
-        }    
+    if self_node.predecessor_info.len() != 0 && target_node.node_id == self_node.predecessor_info[0].node_id {
+        // predecessorであった場合は predecessor のまま設定しておくと都合が悪いので
+        // お役御免とする
+        self_node.predecessor_info.clear();
     }
 
     // finger tableの情報について
-    if err.err_code == chord_util::ERR_CODE_HTTP_REQUEST_ERR {
+    {
         // finger_tableを先頭から辿ってダウンが判明したノードがいたらNoneに設定する
         for idx in 0..(gval::ID_SPACE_BITS as usize) {
             match &self_node.finger_table[idx] {
@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use serde::{Serialize, Deserialize};
+
+use crate::gval;
+use crate::node_info;
+use crate::chord_util;
+use crate::data_store;
+use crate::router;
+use crate::endpoints;
+use crate::config;
+use crate::namespace;
+use crate::vnode;
+
+type ArMu<T> = Arc<Mutex<T>>;
+
+// レプリカの配置ポリシー (ゾーンをまたいだ配置) と、ポリシーを満たしていないデータの集計
+//
+// 各データのコピーは、互いに異なる物理ノード (仮想ノードを用いる場合はそれらをホストするプロセス) で、かつ min(config の min_replica_zones, レプリケーションファクタ) 個以上の
+// ゾーンにまたがるように配置する. コピーを配置するノードはマスターデータ相当のものから順に決めていき、
+// 残りのコピーの数で必要なゾーン数を満たせなくなる場合は、まだ使われていないゾーンのノードのみを選ぶ
+
+// 1つのデータについて、コピーを配置するノードを決める途中の状態
+pub struct ReplicaPlacement {
+    min_zones: u32,
+    replication_factor: u32,
+    // コピーを配置した物理ノードのアドレス
+    node_addresses: Vec<String>,
+    zones: Vec<String>
+}
+
+impl ReplicaPlacement {
+    pub fn new(replication_factor: u32) -> ReplicaPlacement {
+        let min_zones = std::cmp::min(config::get_min_replica_zones(), replication_factor);
+        ReplicaPlacement { min_zones: min_zones, replication_factor: replication_factor, node_addresses: vec![], zones: vec![] }
+    }
+
+    // address は仮想ノードのアドレスでもよい (同じ物理ノードの仮想ノードは同じノードとみなす)
+    pub fn contains_node(&self, address: &str) -> bool {
+        return self.node_addresses.contains(&vnode::get_physical_address(address));
+    }
+
+    // 次のコピーを address のノードに配置してポリシーを満たせるか
+    pub fn is_acceptable(&self, address: &str, zone: &str) -> bool {
+        if self.contains_node(address) {
+            return false;
+        }
+        if self.zones.iter().any(|z| z == zone) == false {
+            return true;
+        }
+        // 既に使われているゾーンのノードは、残りのコピーでまだ必要な数のゾーンを満たせる場合のみ選ぶ
+        let remaining_slots = self.replication_factor.saturating_sub(self.node_addresses.len() as u32);
+        let needed_zones = self.min_zones.saturating_sub(self.zones.len() as u32);
+        return needed_zones < remaining_slots;
+    }
+
+    pub fn add(&mut self, address: &str, zone: &str) {
+        self.node_addresses.push(vnode::get_physical_address(address));
+        if self.zones.iter().any(|z| z == zone) == false {
+            self.zones.push(zone.to_string());
+        }
+    }
+}
+
+// あるノードが保持しているコピー1つ分の情報
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct ReplicaLocation {
+    pub namespace: String,
+    // マスターデータ相当のもののID
+    pub data_id: u32,
+    pub replica_idx: u32,
+    pub replication_factor: u32
+}
+
+// get_replica_locations の結果
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct NodeReplicaLocations {
+    pub zone: String,
+    pub locations: Vec<ReplicaLocation>
+}
+
+// 配置ポリシーを満たしていないデータ
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct PlacementViolation {
+    pub namespace: String,
+    pub data_id: u32,
+    pub replication_factor: u32,
+    // 見つかったコピーの数
+    pub replica_count: u32,
+    // コピーを保持しているノードのゾーン (重複無し)
+    pub zones: Vec<String>
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct PlacementReport {
+    pub min_replica_zones: u32,
+    // 集計対象となったデータ (キー) の数
+    pub checked_count: u64,
+    pub violations: Vec<PlacementViolation>,
+    // 問い合わせに失敗したノード. 空でない場合、集計結果は不完全
+    pub failed_nodes: Vec<String>
+}
+
+// 自ノードが保持しているコピーの一覧を返す. 削除済みのデータとレプリケーションファクタが不明なデータは含めない
+pub fn get_local_replica_locations(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::DataStore>) -> NodeReplicaLocations {
+    let zone;
+    {
+        let self_node_ref = self_node.lock().unwrap();
+        zone = self_node_ref.zone.clone();
+    }
+    let is_salted_id = config::get_replication_mode() == config::ReplicationMode::SaltedId;
+
+    let data_store_ref = data_store.lock().unwrap();
+    let mut locations: Vec<ReplicaLocation> = vec![];
+    for iv in data_store_ref.get_all_datas() {
+        if iv.replication_factor == 0 || iv.val_str == data_store::DELETED_ENTRY_MARKING_STR {
+            continue;
+        }
+        let master_data_id = if is_salted_id {
+            chord_util::calc_master_data_id(iv.data_id, iv.replica_idx, iv.replication_factor)
+        } else {
+            iv.data_id
+        };
+        locations.push(ReplicaLocation { namespace: iv.namespace.clone(), data_id: master_data_id, replica_idx: iv.replica_idx, replication_factor: iv.replication_factor });
+    }
+    return NodeReplicaLocations { zone: zone, locations: locations };
+}
+
+// リング上の全ノードに保持しているコピーを問い合わせ、配置ポリシーを満たしていないデータを集計する
+pub async fn build_placement_report(self_node: ArMu<node_info::NodeInfo>) -> Result<PlacementReport, chord_util::GeneralError> {
+    let addresses = match router::collect_ring_node_addresses(Arc::clone(&self_node)).await {
+        Err(err) => { return Err(err); }
+        Ok(addresses) => addresses
+    };
+
+    // キーは (ネームスペース, マスターデータ相当のもののID). 値は (レプリケーションファクタ, コピーの数, ゾーン)
+    let mut copies: HashMap<(String, u32), (u32, u32, Vec<String>)> = HashMap::new();
+    let mut failed_nodes: Vec<String> = vec![];
+    for address in addresses {
+        let node_locations = match endpoints::rrpc_call__get_replica_locations(&address).await {
+            Err(err) => {
+                chord_util::dprint(&("ERROR at build_placement_report,".to_string() + address.as_str() + "," + err.message.as_str()));
+                failed_nodes.push(address);
+                continue;
+            }
+            Ok(node_locations) => node_locations
+        };
+        for location in node_locations.locations {
+            let entry = copies.entry((location.namespace, location.data_id)).or_insert((location.replication_factor, 0, vec![]));
+            entry.1 += 1;
+            if entry.2.contains(&node_locations.zone) == false {
+                entry.2.push(node_locations.zone.clone());
+            }
+        }
+    }
+
+    let min_replica_zones = config::get_min_replica_zones();
+    let mut violations: Vec<PlacementViolation> = vec![];
+    for ((ns, data_id), (replication_factor, replica_count, zones)) in copies.iter() {
+        if (zones.len() as u32) < std::cmp::min(min_replica_zones, *replication_factor) {
+            let display_ns = if ns.len() == 0 { namespace::DEFAULT_NAMESPACE_DISPLAY_NAME.to_string() } else { ns.clone() };
+            violations.push(PlacementViolation { namespace: display_ns, data_id: *data_id, replication_factor: *replication_factor, replica_count: *replica_count, zones: zones.clone() });
+        }
+    }
+    violations.sort_by(|a, b| (&a.namespace, a.data_id).cmp(&(&b.namespace, b.data_id)));
+
+    return Ok(PlacementReport { min_replica_zones: min_replica_zones, checked_count: copies.len() as u64, violations: violations, failed_nodes: failed_nodes });
+}
@@ -0,0 +1,334 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+
+use crate::gval;
+use crate::node_info;
+use crate::chord_util;
+use crate::data_store;
+use crate::router;
+use crate::endpoints;
+use crate::transfer;
+
+type ArMu<T> = Arc<Mutex<T>>;
+
+// リングのリバランス (ノードIDの移動による担当範囲の均等化)
+//
+// ノードIDはランダムに決まるため、ノードごとの担当範囲とデータ量には偏りが生じる. リバランスでは
+// リングを一周して各ノードの担当範囲とキーの数を集め、各担当範囲内ではキーが均等に分布しているとみなして
+// キーの数が均等になるようなIDを求め、ノードを1つずつ新しいIDに移動させる
+// 起点のノード (リバランスを要求されたノード) のIDは動かさない. また、リング上のノードの順序が
+// 変わらないよう、移動先は移動時点の predecessor と successor の間に限る. 移動先がその範囲外と
+// なるノードは他のノードの移動後に再度試みる
+//
+// IDの移動は移動するノード自身が行う. 担当範囲が狭まる場合は外れた範囲のデータを successor に転送し
+// (transfer::transfer_range)、広がる場合は successor に check_predecessor を要請してデータを委譲してもらう.
+// いずれの場合もデータは受け手が格納を確認してから手放されるため、移動中も自身か successor のどちらかが保持している
+
+// 同じノードを起点としたリバランスが同時に実行されないようにする
+static REBALANCE_IN_PROGRESS : AtomicBool = AtomicBool::new(false);
+
+// ノード (仮想ノード) 1つ分の負荷の情報
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct NodeLoad {
+    pub node_id: u32,
+    pub address: String,
+    // 担当範囲のマスターデータ相当のものの数 (削除済みのものは含まない)
+    pub key_count: u64
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct RebalanceNode {
+    pub address: String,
+    // リバランス前のID
+    pub node_id: u32,
+    pub key_count: u64,
+    pub target_id: u32,
+    pub moved: bool
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct RebalanceResult {
+    pub dry_run: bool,
+    // リング上の順. 先頭は起点のノード
+    pub nodes: Vec<RebalanceNode>,
+    // 負荷の情報の取得またはIDの移動に失敗したノード
+    pub failed_nodes: Vec<String>
+}
+
+// 自ノードの担当範囲 (predecessor のIDより大きく、自身のID以下) のキーの数を返す
+pub fn get_local_node_load(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::DataStore>) -> NodeLoad {
+    let self_id;
+    let address;
+    let pred_id;
+    {
+        let self_node_ref = self_node.lock().unwrap();
+        self_id = self_node_ref.node_id;
+        address = self_node_ref.address_str.clone();
+        pred_id = if self_node_ref.predecessor_info.len() == 0 { None } else { Some(self_node_ref.predecessor_info[0].node_id) };
+    }
+
+    let data_store_ref = data_store.lock().unwrap();
+    let mut key_count: u64 = 0;
+    for iv in data_store_ref.get_all_datas() {
+        if iv.replica_idx != 0 || iv.val_str == data_store::DELETED_ENTRY_MARKING_STR {
+            continue;
+        }
+        let is_in_range = match pred_id {
+            None => true,
+            Some(pred_id) => pred_id == self_id || chord_util::exist_between_two_nodes_right_mawari(chord_util::overflow_check_and_conv(pred_id as u64 + 1), chord_util::overflow_check_and_conv(self_id as u64 + 1), iv.data_id)
+        };
+        if is_in_range {
+            key_count += 1;
+        }
+    }
+    return NodeLoad { node_id: self_id, address: address, key_count: key_count };
+}
+
+// リング上の順に並んだ各ノードの負荷から、キーの数が均等になるような各ノードのIDを求める
+// 先頭のノードのIDは変えない. 全ノードのキーの数が 0 の場合は担当範囲の広さを均等にする
+fn calc_target_ids(loads: &[NodeLoad]) -> Vec<u32> {
+    let node_num = loads.len();
+    let mut target_ids: Vec<u32> = loads.iter().map(|load| load.node_id).collect();
+    if node_num < 2 {
+        return target_ids;
+    }
+
+    // 先頭のノードのIDから右回りにたどった場合の、各ノードの担当範囲の広さと重み (キーの数)
+    // i 番目の要素は (i + 1) % node_num 番目のノードの担当範囲
+    let total_keys: u64 = loads.iter().map(|load| load.key_count).sum();
+    let mut range_lens: Vec<f64> = vec![];
+    let mut weights: Vec<f64> = vec![];
+    for idx in 1..(node_num + 1) {
+        let range_len = chord_util::calc_distance_between_nodes_right_mawari(loads[idx - 1].node_id, loads[idx % node_num].node_id) as f64;
+        range_lens.push(range_len);
+        weights.push(if total_keys == 0 { range_len } else { loads[idx % node_num].key_count as f64 });
+    }
+    let total_weight: f64 = weights.iter().sum();
+
+    let base_id = loads[0].node_id as u64;
+    let mut seg_idx = 0;
+    let mut passed_weight = 0.0;
+    let mut passed_len = 0.0;
+    for (node_idx, target_id) in target_ids.iter_mut().enumerate().skip(1) {
+        let goal = total_weight * node_idx as f64 / node_num as f64;
+        while seg_idx < node_num && passed_weight + weights[seg_idx] < goal {
+            passed_weight += weights[seg_idx];
+            passed_len += range_lens[seg_idx];
+            seg_idx += 1;
+        }
+        let mut offset = passed_len;
+        if seg_idx < node_num && weights[seg_idx] > 0.0 {
+            offset += range_lens[seg_idx] * (goal - passed_weight) / weights[seg_idx];
+        }
+        *target_id = chord_util::overflow_check_and_conv(base_id + offset.round() as u64);
+    }
+    return target_ids;
+}
+
+// self_node を起点にリングのリバランスを行う. dry_run の場合は移動先のIDを求めるのみで移動は行わない
+pub async fn rebalance_ring(self_node: ArMu<node_info::NodeInfo>, dry_run: bool) -> Result<RebalanceResult, chord_util::GeneralError> {
+    if REBALANCE_IN_PROGRESS.swap(true, Ordering::SeqCst) {
+        return Err(chord_util::GeneralError::new("rebalance is already in progress".to_string(), chord_util::ERR_CODE_REBALANCE_IN_PROGRESS));
+    }
+    let ret = rebalance_ring_inner(self_node, dry_run).await;
+    REBALANCE_IN_PROGRESS.store(false, Ordering::SeqCst);
+    return ret;
+}
+
+async fn rebalance_ring_inner(self_node: ArMu<node_info::NodeInfo>, dry_run: bool) -> Result<RebalanceResult, chord_util::GeneralError> {
+    let addresses = match router::collect_ring_vnode_addresses(Arc::clone(&self_node)).await {
+        Err(err) => { return Err(err); }
+        Ok(addresses) => addresses
+    };
+
+    let mut loads: Vec<NodeLoad> = vec![];
+    let mut failed_nodes: Vec<String> = vec![];
+    for address in addresses {
+        match endpoints::rrpc_call__get_node_load(&address).await {
+            Err(err) => {
+                chord_util::dprint(&("ERROR at rebalance_ring,".to_string() + address.as_str() + "," + err.message.as_str()));
+                failed_nodes.push(address);
+            }
+            Ok(load) => { loads.push(load); }
+        };
+    }
+    // 担当範囲が正しく求められないため、一部のノードの情報が得られなかった場合は移動を行わない
+    if failed_nodes.len() > 0 {
+        return Err(chord_util::GeneralError::new("failed to get load of nodes: ".to_string() + failed_nodes.join(",").as_str(), chord_util::ERR_CODE_HTTP_REQUEST_ERR));
+    }
+
+    let target_ids = calc_target_ids(&loads);
+    let mut nodes: Vec<RebalanceNode> = loads.iter().zip(target_ids.iter()).map(|(load, target_id)| {
+        RebalanceNode { address: load.address.clone(), node_id: load.node_id, key_count: load.key_count, target_id: *target_id, moved: false }
+    }).collect();
+    if dry_run {
+        return Ok(RebalanceResult { dry_run: dry_run, nodes: nodes, failed_nodes: failed_nodes });
+    }
+
+    let node_num = nodes.len();
+    let mut current_ids: Vec<u32> = loads.iter().map(|load| load.node_id).collect();
+    for _ in 0..gval::REBALANCE_PASS_MAX {
+        let mut is_moved = false;
+        for idx in 1..node_num {
+            let target_id = nodes[idx].target_id;
+            if current_ids[idx] == target_id || failed_nodes.contains(&nodes[idx].address) {
+                continue;
+            }
+            // 移動先が現在の predecessor と successor の間に無い場合は、それらの移動後に再度試みる
+            let prev_id = current_ids[idx - 1];
+            let next_id = current_ids[(idx + 1) % node_num];
+            if chord_util::exist_between_two_nodes_right_mawari(chord_util::overflow_check_and_conv(prev_id as u64 + 1), next_id, target_id) == false {
+                continue;
+            }
+            match endpoints::rrpc_call__move_node_id(&nodes[idx].address, target_id).await {
+                Err(err) => {
+                    chord_util::dprint(&("ERROR at rebalance_ring,".to_string() + nodes[idx].address.as_str() + "," + err.message.as_str()));
+                    failed_nodes.push(nodes[idx].address.clone());
+                    continue;
+                }
+                Ok(_) => {}
+            };
+            current_ids[idx] = target_id;
+            nodes[idx].moved = true;
+            is_moved = true;
+            // 経路情報が新しいIDに追従するのを待ってから次のノードを移動する
+            tokio::time::sleep(Duration::from_millis(gval::REBALANCE_MOVE_INTERVAL_MILLIS)).await;
+        }
+        if is_moved == false {
+            break;
+        }
+    }
+
+    return Ok(RebalanceResult { dry_run: dry_run, nodes: nodes, failed_nodes: failed_nodes });
+}
+
+// 自ノードのIDを new_id に移動し、担当範囲の変化に合わせて successor との間でデータを受け渡す
+// new_id は predecessor と successor の間でなければならない
+pub async fn move_node_id(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::DataStore>, new_id: u32) -> Result<bool, chord_util::GeneralError> {
+    let self_node_deep_cloned;
+    {
+        let self_node_ref = self_node.lock().unwrap();
+        self_node_deep_cloned = node_info::partial_clone_from_ref_strong(&self_node_ref);
+    }
+    if self_node_deep_cloned.predecessor_info.len() == 0 || self_node_deep_cloned.successor_info_list.len() == 0 {
+        return Err(chord_util::GeneralError::new("routing info is not ready".to_string(), chord_util::ERR_CODE_INTERNAL_CONTROL_FLOW_PROBLEM));
+    }
+    let old_id = self_node_deep_cloned.node_id;
+    let pred_id = self_node_deep_cloned.predecessor_info[0].node_id;
+    let successor = node_info::gen_node_info_from_entry(&self_node_deep_cloned.successor_info_list[0]);
+    if new_id == old_id {
+        return Ok(true);
+    }
+    if successor.address_str == self_node_deep_cloned.address_str
+        || chord_util::exist_between_two_nodes_right_mawari(chord_util::overflow_check_and_conv(pred_id as u64 + 1), successor.node_id, new_id) == false {
+        return Err(chord_util::GeneralError::new("new id must be between predecessor and successor".to_string(), chord_util::ERR_CODE_INVALID_ARGUMENT));
+    }
+    // 担当範囲が広がる (successor 側に移動する) か
+    let is_forward = chord_util::exist_between_two_nodes_right_mawari(old_id, successor.node_id, new_id);
+
+    chord_util::dprint(&("move_node_id_1,".to_string() + chord_util::gen_debug_str_of_node(&self_node_deep_cloned).as_str() + ","
+        + chord_util::gen_debug_str_of_data(new_id).as_str()));
+
+    let moved_node_info;
+    {
+        let mut self_node_ref = self_node.lock().unwrap();
+        self_node_ref.node_id = new_id;
+        moved_node_info = node_info::partial_clone_from_ref_strong(&self_node_ref);
+    }
+
+    if is_forward == false {
+        // (new_id, old_id] の範囲のデータは successor の担当となる
+        // 途中で失敗した場合、送り終えていないデータは自身が保持したまま一定間隔で転送を再開するため、移動は取り消さない
+        match transfer::transfer_range(Arc::clone(&data_store), &successor, chord_util::overflow_check_and_conv(new_id as u64 + 1), chord_util::overflow_check_and_conv(old_id as u64 + 1), transfer::HandoffMode::Move).await {
+            Err(err) => {
+                chord_util::dprint(&("ERROR at move_node_id,".to_string() + err.message.as_str()));
+            }
+            Ok(_) => {}
+        };
+    }
+
+    // successor に新しいIDを通知する. 担当範囲が広がる場合は (old_id, new_id] の範囲のデータが委譲される
+    match endpoints::rrpc_call__check_predecessor(&successor, &node_info::gen_entry(&moved_node_info)).await {
+        Err(err) => {
+            if is_forward {
+                let mut self_node_ref = self_node.lock().unwrap();
+                self_node_ref.node_id = old_id;
+                return Err(err);
+            }
+            // データは渡し終えているため、successor の情報は stabilize処理 で更新されるのを待つ
+            chord_util::dprint(&("ERROR at move_node_id,".to_string() + err.message.as_str()));
+        }
+        Ok(_) => {}
+    };
+
+    // check_predecessor は successor が predecessor を切り替えなかった場合も Ok を返すため、
+    // 担当範囲が広がる場合は successor の predecessor が移動後の自身になったことを確認してから移動を確定する
+    if is_forward {
+        match endpoints::rrpc_call__get_predecessor(&successor.address_str).await {
+            Err(err) => {
+                // successor が既にデータを委譲している可能性があるため移動は取り消さず、stabilize処理 での更新を待つ
+                chord_util::dprint(&("ERROR at move_node_id,".to_string() + err.message.as_str()));
+            }
+            Ok(pred_info) => {
+                let is_accepted = match pred_info {
+                    None => false,
+                    Some(pred_info) => pred_info.address_str == moved_node_info.address_str && pred_info.node_id == new_id
+                };
+                if is_accepted == false {
+                    let mut self_node_ref = self_node.lock().unwrap();
+                    self_node_ref.node_id = old_id;
+                    return Err(chord_util::GeneralError::new("successor did not accept the moved node id".to_string(), chord_util::ERR_CODE_INTERNAL_CONTROL_FLOW_PROBLEM));
+                }
+            }
+        };
+    }
+
+    return Ok(true);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gen_load(node_id: u32, key_count: u64) -> NodeLoad {
+        return NodeLoad { node_id: node_id, address: format!("127.0.0.1:{}", node_id), key_count: key_count };
+    }
+
+    fn assert_near(actual: u32, expected: u64) {
+        assert!((actual as i64 - expected as i64).abs() <= 2, "actual: {}, expected: {}", actual, expected);
+    }
+
+    #[test]
+    fn calc_target_ids_keeps_single_node() {
+        assert_eq!(calc_target_ids(&[gen_load(12345, 10)]), vec![12345]);
+    }
+
+    #[test]
+    fn calc_target_ids_splits_range_evenly_without_keys() {
+        let target_ids = calc_target_ids(&[gen_load(100, 0), gen_load(200, 0), gen_load(300, 0), gen_load(400, 0)]);
+        let quarter = gval::ID_SPACE_RANGE as u64 / 4;
+        // 先頭のノードのIDは変えない
+        assert_eq!(target_ids[0], 100);
+        assert_near(target_ids[1], 100 + quarter);
+        assert_near(target_ids[2], 100 + quarter * 2);
+        assert_near(target_ids[3], 100 + quarter * 3);
+    }
+
+    #[test]
+    fn calc_target_ids_follows_key_distribution() {
+        // 既に均等であれば動かさない
+        assert_eq!(calc_target_ids(&[gen_load(0, 100), gen_load(1000, 100)]), vec![0, 1000]);
+
+        // 1番目のノードの担当範囲 (0, 1000] に 100 個、先頭のノードの担当範囲 (1000, 0] に 300 個ある場合は、
+        // 先頭のノードの担当範囲のうち 1/3 を1番目のノードが担当するようにする
+        let target_ids = calc_target_ids(&[gen_load(0, 300), gen_load(1000, 100)]);
+        let head_range_len = chord_util::calc_distance_between_nodes_right_mawari(1000, 0) as u64;
+        assert_eq!(target_ids[0], 0);
+        assert_near(target_ids[1], 1000 + head_range_len / 3);
+    }
+}
@@ -2,27 +2,264 @@ use std::sync::{Arc, Mutex};
 use std::borrow::{Borrow, BorrowMut};
 use std::cell::{RefMut, RefCell, Ref};
 use std::sync::atomic::Ordering;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
 
 use crate::gval;
 use crate::node_info;
 use crate::chord_util;
 use crate::stabilizer;
 use crate::endpoints;
+use crate::vnode;
 use crate::data_store;
+use crate::config;
+use crate::location_cache;
+use crate::failure_detector;
 
 type ArMu<T> = Arc<Mutex<T>>;
 
+// 名前解決で経由したノード1つ分の情報
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct LookupHop {
+    pub address_str: String,
+    pub node_id: u32,
+    // このノードへのRPCの往復時間. 再帰モードでは、このノードが以降のノードへ転送した分を含む
+    pub elapsed_micros: u64,
+    // このノードでの処理と通信のみに要した時間
+    pub latency_micros: u64
+}
+
+// trace_lookup の結果
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct LookupTrace {
+    pub lookup_mode: config::LookupMode,
+    pub target_id: u32,
+    pub successor: node_info::NodeInfoSummary,
+    // 経由した順. 先頭は問い合わせ元のノード自身
+    pub hops: Vec<LookupHop>,
+    pub total_micros: u64
+}
+
+// find_successor_recursive の引数と結果
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct RecursiveLookup {
+    pub id: u32,
+    // ここまでに転送された回数
+    pub hop_count: u32,
+    // 問い合わせ元が結果を待つ残り時間 (ミリ秒). 転送するごとに、経過した時間とマージンの分だけ減らして渡す
+    pub remaining_millis: u64
+}
+
+impl RecursiveLookup {
+    // 問い合わせ元で用いる. 全体の待ち時間は find_successor_recursive のRPCのタイムアウトとする
+    pub fn new(id: u32) -> RecursiveLookup {
+        RecursiveLookup { id: id, hop_count: 0, remaining_millis: config::get_rpc_timeout("find_successor_recursive").request_millis }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct RecursiveLookupResult {
+    pub successor: node_info::NodeInfoSummary,
+    // 転送先のノード. 転送しなかった場合は空
+    pub hops: Vec<LookupHop>
+}
+
+// successor_info_list などに保持している NodeInfoEntry は successor を持たないため、succ0_id は 0 とする
+fn gen_summary_of_successor(succ_info: &node_info::NodeInfoEntry) -> node_info::NodeInfoSummary {
+    return node_info::NodeInfoSummary { node_id: succ_info.node_id, succ0_id: 0, address_str: succ_info.address_str.clone(), zone: succ_info.zone.clone() };
+}
+
+fn elapsed_micros_since(started: Instant) -> u64 {
+    return started.elapsed().as_micros() as u64;
+}
+
 // idで識別されるデータを担当するノードの名前解決を行う
-pub fn find_successor(self_node: ArMu<node_info::NodeInfo>, id : u32) -> Result<node_info::NodeInfoSummary, chord_util::GeneralError> {
-    let mut self_node_ref = self_node.lock().unwrap();
-    let deep_cloned_self_node = node_info::partial_clone_from_ref_strong(&self_node_ref);
-    drop(self_node_ref);
+// 方法は config の lookup_mode による
+pub async fn find_successor(self_node: ArMu<node_info::NodeInfo>, id : u32) -> Result<node_info::NodeInfoSummary, chord_util::GeneralError> {
+    return match config::get_lookup_mode() {
+        config::LookupMode::Iterative => {
+            let mut hops: Vec<LookupHop> = vec![];
+            find_successor_iterative(self_node, id, &mut hops).await
+        }
+        config::LookupMode::Recursive => {
+            match find_successor_recursive(self_node, RecursiveLookup::new(id)).await {
+                Err(err) => Err(err),
+                Ok(result) => Ok(result.successor)
+            }
+        }
+    };
+}
+
+// データの読み書きのための名前解決. location_cache に担当ノードがあればそれを返し、無ければ
+// self_node_ni のノードに find_successor を問い合わせて結果をキャッシュする
+// 経路表の更新など、最新の担当ノードが必要な場合は用いないこと
+pub async fn find_successor_cached(self_node_ni: &node_info::NodeInfo, id: u32) -> Result<node_info::NodeInfoSummary, chord_util::GeneralError> {
+    match location_cache::lookup(id) {
+        Some(owner) => { return Ok(owner); }
+        None => {}
+    };
+    let owner = match endpoints::rrpc_call__find_successor(self_node_ni, id).await {
+        Err(err) => { return Err(err); }
+        Ok(owner) => owner
+    };
+    location_cache::store(id, &owner);
+    return Ok(owner);
+}
+
+// 指定した方法で名前解決を行い、経由したノードと各ノードでの所要時間を返す
+pub async fn trace_lookup(self_node: ArMu<node_info::NodeInfo>, id: u32, lookup_mode: config::LookupMode) -> Result<LookupTrace, chord_util::GeneralError> {
+    let self_hop;
+    {
+        let self_node_ref = self_node.lock().unwrap();
+        self_hop = LookupHop { address_str: self_node_ref.address_str.clone(), node_id: self_node_ref.node_id, elapsed_micros: 0, latency_micros: 0 };
+    }
+
+    let started = Instant::now();
+    let mut hops: Vec<LookupHop> = vec![];
+    let successor = match lookup_mode {
+        config::LookupMode::Iterative => {
+            match find_successor_iterative(Arc::clone(&self_node), id, &mut hops).await {
+                Err(err) => { return Err(err); }
+                Ok(successor) => successor
+            }
+        }
+        config::LookupMode::Recursive => {
+            match find_successor_recursive(Arc::clone(&self_node), RecursiveLookup::new(id)).await {
+                Err(err) => { return Err(err); }
+                Ok(result) => {
+                    hops = result.hops;
+                    // 各ノードへの往復時間には以降のノードの分が含まれているため、差分をそのノードの所要時間とする
+                    for idx in 0..hops.len() {
+                        let next_elapsed = if idx + 1 < hops.len() { hops[idx + 1].elapsed_micros } else { 0 };
+                        hops[idx].latency_micros = hops[idx].elapsed_micros.saturating_sub(next_elapsed);
+                    }
+                    result.successor
+                }
+            }
+        }
+    };
+    let total_micros = elapsed_micros_since(started);
+
+    let mut self_hop = self_hop;
+    // 自ノードでの処理時間は、全体から各ノードへのRPCの分を除いたもの
+    let remote_micros: u64 = hops.iter().map(|hop| hop.latency_micros).sum();
+    self_hop.elapsed_micros = total_micros;
+    self_hop.latency_micros = total_micros.saturating_sub(remote_micros);
+    hops.insert(0, self_hop);
+
+    return Ok(LookupTrace { lookup_mode: lookup_mode, target_id: id, successor: successor, hops: hops, total_micros: total_micros });
+}
+
+// 経由しようとしたノードから受けたエラーが、他の候補を経由して名前解決を続けるべきものか
+// 到達できなかった場合に加えて、相手が successor を見失っていて探索を進められない場合も含む
+fn is_skippable_hop_err(err: &chord_util::GeneralError) -> bool {
+    return chord_util::is_unreachable_err(err) || err.err_code == chord_util::ERR_CODE_NODE_IS_DOWNED;
+}
+
+// 再帰モードの名前解決. id が自身と successor の間にあれば successor を返し、そうでなければ
+// closest_preceding_finger で選んだノードに問い合わせを転送する
+// 転送先がダウンしていた場合は、そのノードを今回の名前解決の候補から除いて次の候補に転送し直す
+// (経路情報からの除去は failure_detector の判定を待つため、それには依らない)
+// 選び直しは gval::LOOKUP_RECURSIVE_FORWARD_ATTEMPT_MAX 回までとする
+// 転送先へのRPCのタイムアウトは lookup の残り時間とし、残り時間が無くなった場合はエラーとする
+pub async fn find_successor_recursive(self_node: ArMu<node_info::NodeInfo>, lookup: RecursiveLookup) -> Result<RecursiveLookupResult, chord_util::GeneralError> {
+    let id = lookup.id;
+    let received = Instant::now();
+    // 転送に失敗したノードと、候補の選択時に応答の無かったノード
+    let mut excluded_addresses: Vec<String> = vec![];
+    let mut forward_failures: u32 = 0;
+    loop {
+        let deep_cloned_self_node;
+        {
+            let self_node_ref = self_node.lock().unwrap();
+            deep_cloned_self_node = node_info::partial_clone_from_ref_strong(&self_node_ref);
+        }
+        if deep_cloned_self_node.successor_info_list.len() == 0 {
+            return Err(chord_util::GeneralError::new("successor is unknown".to_string(), chord_util::ERR_CODE_NODE_IS_DOWNED));
+        }
+
+        chord_util::dprint(&("find_successor_recursive_1,".to_string() + chord_util::gen_debug_str_of_node(&deep_cloned_self_node).as_str() + ","
+                    + chord_util::gen_debug_str_of_data(id).as_str() + "," + lookup.hop_count.to_string().as_str()));
+
+        if id == deep_cloned_self_node.node_id {
+            return Ok(RecursiveLookupResult { successor: node_info::gen_summary_node_info(&deep_cloned_self_node), hops: vec![] });
+        }
+        let succ_info = &deep_cloned_self_node.successor_info_list[0];
+        if id == succ_info.node_id || chord_util::exist_between_two_nodes_right_mawari(deep_cloned_self_node.node_id, succ_info.node_id, id) {
+            return Ok(RecursiveLookupResult { successor: gen_summary_of_successor(succ_info), hops: vec![] });
+        }
+
+        let next_hop = match closest_preceding_finger_excluding(Arc::clone(&self_node), id, &mut excluded_addresses).await {
+            Err(err) => { return Err(err); }
+            Ok(found) => found
+        };
+        if next_hop.address_str == deep_cloned_self_node.address_str {
+            if forward_failures > 0 {
+                // 転送先の候補が全て応答しなかった
+                return Err(chord_util::GeneralError::new("no live node to forward lookup: ".to_string() + id.to_string().as_str(), chord_util::ERR_CODE_APPROPRIATE_NODE_NOT_FOND));
+            }
+            // 自身より id に近いノードを知らない場合、担当ノードは successor となる
+            let self_node_ref = self_node.lock().unwrap();
+            if self_node_ref.successor_info_list.len() == 0 {
+                return Err(chord_util::GeneralError::new("successor is unknown".to_string(), chord_util::ERR_CODE_NODE_IS_DOWNED));
+            }
+            return Ok(RecursiveLookupResult { successor: gen_summary_of_successor(&self_node_ref.successor_info_list[0]), hops: vec![] });
+        }
+
+        if lookup.hop_count + 1 >= gval::LOOKUP_RECURSIVE_HOP_MAX {
+            return Err(chord_util::GeneralError::new("lookup exceeded max hops: ".to_string() + id.to_string().as_str(), chord_util::ERR_CODE_APPROPRIATE_NODE_NOT_FOND));
+        }
+
+        let elapsed_millis = received.elapsed().as_millis() as u64;
+        if elapsed_millis + gval::LOOKUP_RECURSIVE_HOP_MARGIN_MILLIS >= lookup.remaining_millis {
+            return Err(chord_util::GeneralError::new("lookup deadline exceeded: ".to_string() + id.to_string().as_str(), chord_util::ERR_CODE_APPROPRIATE_NODE_NOT_FOND));
+        }
+        let budget_millis = lookup.remaining_millis - elapsed_millis;
+        let next_lookup = RecursiveLookup { id: id, hop_count: lookup.hop_count + 1, remaining_millis: budget_millis - gval::LOOKUP_RECURSIVE_HOP_MARGIN_MILLIS };
+
+        let started = Instant::now();
+        match endpoints::rrpc_call__find_successor_recursive(&next_hop, &next_lookup, budget_millis).await {
+            Err(err) => {
+                if is_skippable_hop_err(&err) == false {
+                    return Err(err);
+                }
+                if chord_util::is_unreachable_err(&err) {
+                    let mut self_node_ref = self_node.lock().unwrap();
+                    node_info::handle_downed_node_info(&mut self_node_ref, &node_info::gen_node_info_from_summary(&next_hop), &err);
+                }
+                // 転送先を候補から除いて選び直す
+                excluded_addresses.push(next_hop.address_str.clone());
+                forward_failures += 1;
+                if forward_failures >= gval::LOOKUP_RECURSIVE_FORWARD_ATTEMPT_MAX {
+                    return Err(chord_util::GeneralError::new("lookup exceeded max forward attempts: ".to_string() + id.to_string().as_str(), chord_util::ERR_CODE_APPROPRIATE_NODE_NOT_FOND));
+                }
+                continue;
+            }
+            Ok(mut result) => {
+                let hop = LookupHop { address_str: next_hop.address_str.clone(), node_id: next_hop.node_id, elapsed_micros: elapsed_micros_since(started), latency_micros: 0 };
+                result.hops.insert(0, hop);
+                return Ok(result);
+            }
+        };
+    }
+}
+
+// 反復モードの名前解決. 経由したノードを hops に加える (問い合わせ元のノード自身は含めない)
+async fn find_successor_iterative(self_node: ArMu<node_info::NodeInfo>, id : u32, hops: &mut Vec<LookupHop>) -> Result<node_info::NodeInfoSummary, chord_util::GeneralError> {
+    let deep_cloned_self_node;
+    {
+        let self_node_ref = self_node.lock().unwrap();
+        deep_cloned_self_node = node_info::partial_clone_from_ref_strong(&self_node_ref);
+    }
 
     chord_util::dprint(&("find_successor_1,".to_string() + chord_util::gen_debug_str_of_node(&deep_cloned_self_node).as_str() + ","
                 + chord_util::gen_debug_str_of_data(id).as_str()));
     
-    let n_dash = match find_predecessor(&deep_cloned_self_node, id){
+    let n_dash = match find_predecessor(Arc::clone(&self_node), &deep_cloned_self_node, id, hops).await{
         Err(err) => {
             return Err(chord_util::GeneralError::new(err.message, err.err_code));
         }
@@ -34,18 +271,21 @@ pub fn find_successor(self_node: ArMu<node_info::NodeInfo>, id : u32) -> Result<
     //                     + chord_util::gen_debug_str_of_node(&deep_cloned_self_node.successor_info_list[0]).as_str() + ","
     //                     + chord_util::gen_debug_str_of_data(id).as_str()));
 
-    let asked_n_dash_info = match endpoints::rrpc_call__get_node_info(&n_dash.address_str) {
+    // successor のアドレスを得るため、n_dash にもう一度問い合わせる
+    let started = Instant::now();
+    let succ_rslt = endpoints::rrpc_call__get_successor(&n_dash.address_str).await;
+    let elapsed = elapsed_micros_since(started);
+    hops.push(LookupHop { address_str: n_dash.address_str.clone(), node_id: n_dash.node_id, elapsed_micros: elapsed, latency_micros: elapsed });
+    let n_dash_succ = match succ_rslt {
         Err(err) => {
-            self_node_ref = self_node.lock().unwrap();
+            let mut self_node_ref = self_node.lock().unwrap();
             node_info::handle_downed_node_info(&mut self_node_ref, &node_info::gen_node_info_from_summary(&n_dash), &err);
-            return Err(chord_util::GeneralError::new(err.message, err.err_code));
-        }
-        Ok(got_node) => {                
-            got_node
+            return Err(chord_util::GeneralError::new(err.message + ": " + n_dash.address_str.as_str(), err.err_code));
         }
+        Ok(succ_info) => succ_info
     };
     
-    return Ok(node_info::NodeInfoSummary { node_id: asked_n_dash_info.successor_info_list[0].node_id, succ0_id: 0, address_str: asked_n_dash_info.successor_info_list[0].address_str.clone()});
+    return Ok(gen_summary_of_successor(&n_dash_succ));
     // match endpoints::rrpc_call__get_node_info(&asked_n_dash_info.successor_info_list[0].address_str) {
     //     Err(err) => {
     //         self_node_ref = self_node.lock().unwrap();
@@ -59,11 +299,21 @@ pub fn find_successor(self_node: ArMu<node_info::NodeInfo>, id : u32) -> Result<
 }
  
 // id の前で一番近い位置に存在するノードを探索する
-pub fn find_predecessor(exnode_ni_ref: &node_info::NodeInfo, id: u32) -> Result<node_info::NodeInfoSummary, chord_util::GeneralError> {
+// 経由するノードがダウンしていた場合は、それまでに経由したノードを新しい方から遡り、その successor_info_list から
+// 代わりのノードを選んで探索を続ける
+// exnode_ni_ref は self_node の内容をコピーしたもの
+// 問い合わせたノードを hops に加える
+pub async fn find_predecessor(self_node: ArMu<node_info::NodeInfo>, exnode_ni_ref: &node_info::NodeInfo, id: u32, hops: &mut Vec<LookupHop>) -> Result<node_info::NodeInfoSummary, chord_util::GeneralError> {
+    if exnode_ni_ref.successor_info_list.len() == 0 {
+        return Err(chord_util::GeneralError::new("successor is unknown".to_string(), chord_util::ERR_CODE_NODE_IS_DOWNED));
+    }
     let mut n_dash: node_info::NodeInfoSummary = node_info::gen_summary_node_info(exnode_ni_ref);
     let mut n_dash_found: node_info::NodeInfoSummary = node_info::gen_summary_node_info(exnode_ni_ref);
+    // n_dash より前に経由したノード. 先頭は自ノードで、末尾が n_dash の1つ前
+    let mut path: Vec<node_info::NodeInfoSummary> = vec![];
+    let mut downed_addresses: Vec<String> = vec![];
     let mut is_first_cpf = true;
-    chord_util::dprint(&("find_predecessor_1,".to_string() + chord_util::gen_debug_str_of_node(&exnode_ni_ref).as_str()));
+    chord_util::dprint(&("find_predecessor_1,".to_string() + chord_util::gen_debug_str_of_node(exnode_ni_ref).as_str()));
     
     // n_dash と n_dashのsuccessorの 間に id が位置するような n_dash を見つけたら、ループを終了し n_dash を return する
     loop {
@@ -81,7 +331,7 @@ pub fn find_predecessor(exnode_ni_ref: &node_info::NodeInfo, id: u32) -> Result<
 
         // 初回は自ノードへの呼出しなのでRPCのインタフェースを介さずに呼び出しを行う
         if is_first_cpf {
-            n_dash_found = match closest_preceding_finger(ArMu_new!(node_info::partial_clone_from_ref_strong(exnode_ni_ref)), id) {
+            n_dash_found = match closest_preceding_finger(Arc::clone(&self_node), id).await {
                     Err(err) => {
                         return Err(chord_util::GeneralError::new(err.message, err.err_code));
                     }
@@ -89,9 +339,31 @@ pub fn find_predecessor(exnode_ni_ref: &node_info::NodeInfo, id: u32) -> Result<
             };
             is_first_cpf = false;
         } else {
-            n_dash_found = match endpoints::rrpc_call__closest_preceding_finger(&n_dash, id){
+            let started = Instant::now();
+            let cpf_rslt = endpoints::rrpc_call__closest_preceding_finger(&n_dash, id).await;
+            let elapsed = elapsed_micros_since(started);
+            hops.push(LookupHop { address_str: n_dash.address_str.clone(), node_id: n_dash.node_id, elapsed_micros: elapsed, latency_micros: elapsed });
+            n_dash_found = match cpf_rslt {
                 Err(err) => {
-                    return Err(chord_util::GeneralError::new(err.message, err.err_code));
+                    if is_skippable_hop_err(&err) == false {
+                        return Err(chord_util::GeneralError::new(err.message, err.err_code));
+                    }
+                    // n_dash を経由できない場合、それまでに経由したノードを起点に、n_dash 以外で探索を先に進められるノードを探す
+                    chord_util::dprint(&("find_predecessor_hop_downed,".to_string() + chord_util::gen_debug_str_of_node(exnode_ni_ref).as_str() + ","
+                                        + n_dash.address_str.as_str()));
+                    downed_addresses.push(n_dash.address_str.clone());
+                    if chord_util::is_unreachable_err(&err) {
+                        let mut self_node_ref = self_node.lock().unwrap();
+                        node_info::handle_downed_node_info(&mut self_node_ref, &node_info::gen_node_info_from_summary(&n_dash), &err);
+                    }
+                    match find_alternative_hop(Arc::clone(&self_node), exnode_ni_ref, &mut path, id, &mut downed_addresses).await {
+                        Err(alt_err) => { return Err(alt_err); }
+                        Ok(alt_hop) => {
+                            // 代わりのノードは path の末尾のノードと id の間にあるため、そこから見て探索は先に進んでいる
+                            n_dash_found = alt_hop;
+                            continue;
+                        }
+                    };
                 }
                 Ok(ninfo) => ninfo
             };
@@ -111,7 +383,7 @@ pub fn find_predecessor(exnode_ni_ref: &node_info::NodeInfo, id: u32) -> Result<
         let distance_old = chord_util::calc_distance_between_nodes_right_mawari(exnode_ni_ref.node_id, n_dash.node_id);
         let distance_found = chord_util::calc_distance_between_nodes_right_mawari(exnode_ni_ref.node_id, n_dash_found.node_id);
         let distance_data_id = chord_util::calc_distance_between_nodes_right_mawari(exnode_ni_ref.node_id, id);
-        if distance_found < distance_old && !(distance_old >= distance_data_id) {
+        if distance_found < distance_old && distance_old < distance_data_id {
             // 探索を続けていくと n_dash は id に近付いていくはずであり、それは上記の前提を踏まえると
             // 自ノードからはより遠い位置の値になっていくということのはずである
             // 従って、そうなっていなかった場合は、繰り返しを継続しても意味が無く、最悪、無限ループになってしまう
@@ -132,13 +404,71 @@ pub fn find_predecessor(exnode_ni_ref: &node_info::NodeInfo, id: u32) -> Result<
 
         // チェックの結果問題ないので n_dashを closest_preceding_fingerで探索して得た
         // ノード情報は次周のループの先頭でn_dash_foundに置き換えられる
+        path.push(n_dash.clone());
     }
 
     return Ok(n_dash.clone());
 }
 
+// find_predecessor で経由しようとしたノードがダウンしていた場合に、代わりに経由するノードを選ぶ
+// path (それまでに経由したノード) の末尾から順に起点とし、起点の successor_info_list のうち起点と id の間にあって
+// 応答のあるノードを返す. 起点自体が応答しない場合や候補が無い場合は、path から取り除いて1つ前のノードを起点とする
+// 起点が自ノードの場合は、応答の無かったノードを除いた自身の経路情報から探索し直す
+// 自ノードまで遡っても見つからなかった場合はエラーとする
+async fn find_alternative_hop(self_node: ArMu<node_info::NodeInfo>, exnode_ni_ref: &node_info::NodeInfo, path: &mut Vec<node_info::NodeInfoSummary>, id: u32, downed_addresses: &mut Vec<String>) -> Result<node_info::NodeInfoSummary, chord_util::GeneralError> {
+    while path.len() > 0 {
+        let base = path[path.len() - 1].clone();
+        if base.address_str == exnode_ni_ref.address_str {
+            let found = match closest_preceding_finger_excluding(Arc::clone(&self_node), id, downed_addresses).await {
+                Err(err) => { return Err(err); }
+                Ok(found) => found
+            };
+            if found.address_str == exnode_ni_ref.address_str || downed_addresses.contains(&found.address_str) {
+                break;
+            }
+            return Ok(found);
+        }
+
+        let base_succ_list = match endpoints::rrpc_call__get_successor_list(&base.address_str).await {
+            Err(err) => {
+                chord_util::dprint(&("find_alternative_hop_base_downed,".to_string() + chord_util::gen_debug_str_of_node(exnode_ni_ref).as_str() + ","
+                                    + base.address_str.as_str()));
+                downed_addresses.push(base.address_str.clone());
+                let mut self_node_ref = self_node.lock().unwrap();
+                node_info::handle_downed_node_info(&mut self_node_ref, &node_info::gen_node_info_from_summary(&base), &err);
+                path.pop();
+                continue;
+            }
+            Ok(succ_list) => succ_list
+        };
+        match pick_live_candidate(&self_node, exnode_ni_ref, filter_alternative_candidates(&base, &base_succ_list, id), downed_addresses).await {
+            None => { path.pop(); }
+            Some(found) => { return Ok(found); }
+        };
+    }
+    return Err(chord_util::GeneralError::new("no live node to route to: ".to_string() + id.to_string().as_str(), chord_util::ERR_CODE_APPROPRIATE_NODE_NOT_FOND));
+}
+
+// base の successor_info_list から、代わりに経由するノードの候補を選ぶ
+// 探索対象のIDを飛び越さないもののうち、遠い方 (探索が先に進む方) から並べる
+fn filter_alternative_candidates(base: &node_info::NodeInfoSummary, base_succ_list: &[node_info::NodeInfoEntry], id: u32) -> Vec<node_info::NodeInfoEntry> {
+    return base_succ_list.iter().rev()
+        .filter(|ninfo| ninfo.node_id != base.node_id && chord_util::exist_between_two_nodes_right_mawari(base.node_id, id, ninfo.node_id))
+        .cloned()
+        .collect();
+}
+
 //  自身の持つ経路情報をもとに,  id から前方向に一番近いノードの情報を返す
-pub fn closest_preceding_finger(self_node: ArMu<node_info::NodeInfo>, id : u32) -> Result<node_info::NodeInfoSummary, chord_util::GeneralError> {
+// 候補のノードがダウンしていた場合は、次に近い finger_table のエントリ、successor_info_list のエントリの順に試し、
+// 応答のあるノードが無ければ自身を返す
+pub async fn closest_preceding_finger(self_node: ArMu<node_info::NodeInfo>, id : u32) -> Result<node_info::NodeInfoSummary, chord_util::GeneralError> {
+    let mut downed_addresses: Vec<String> = vec![];
+    return closest_preceding_finger_excluding(self_node, id, &mut downed_addresses).await;
+}
+
+// closest_preceding_finger と同様だが、downed_addresses に含まれるノードは候補としない
+// 応答の無かった候補は downed_addresses に加える
+async fn closest_preceding_finger_excluding(self_node: ArMu<node_info::NodeInfo>, id : u32, downed_addresses: &mut Vec<String>) -> Result<node_info::NodeInfoSummary, chord_util::GeneralError> {
     // 範囲の広いエントリから探索していく
     // finger_tableはインデックスが小さい方から大きい方に、範囲が大きくなっていく
     // ように構成されているため、リバースしてインデックスの大きな方から小さい方へ
@@ -146,11 +476,14 @@ pub fn closest_preceding_finger(self_node: ArMu<node_info::NodeInfo>, id : u32)
 
     chord_util::dprint(&"closest_preceding_finger_start".to_string());
 
-    let mut self_node_ref = self_node.lock().unwrap();
-    let deep_cloned_self_node = node_info::partial_clone_from_ref_strong(&self_node_ref);
-    drop(self_node_ref);
+    let deep_cloned_self_node;
+    {
+        let self_node_ref = self_node.lock().unwrap();
+        deep_cloned_self_node = node_info::partial_clone_from_ref_strong(&self_node_ref);
+    }
 
-    for node_info in (&deep_cloned_self_node).finger_table.iter().rev() {
+    let mut candidates: Vec<node_info::NodeInfoEntry> = vec![];
+    for node_info in deep_cloned_self_node.finger_table.iter().rev() {
         let conved_node_info = match node_info {
             None => {
                 chord_util::dprint(&("closest_preceding_finger_0,".to_string() + chord_util::gen_debug_str_of_node(&deep_cloned_self_node).as_str()));
@@ -160,37 +493,243 @@ pub fn closest_preceding_finger(self_node: ArMu<node_info::NodeInfo>, id : u32)
         };
 
         chord_util::dprint(&("closest_preceding_finger_1,".to_string() + chord_util::gen_debug_str_of_node(&deep_cloned_self_node).as_str() + ","
-            + chord_util::gen_debug_str_of_node(&conved_node_info).as_str()));
+            + chord_util::gen_debug_str_of_entry(conved_node_info).as_str()));
 
         // テーブル内のエントリが保持しているノードのIDが自身のIDと探索対象のIDの間にあれば
-        // それを返す
+        // それを候補とする
         // (大きな範囲を見た場合、探索対象のIDが自身のIDとエントリが保持しているノードのIDの中に含まれて
         //  しまっている可能性が高く、エントリが保持しているノードが、探索対象のIDを飛び越してしまっている
         //  可能性が高いということになる。そこで探索範囲を狭めていって、飛び越さない範囲で一番近いノードを
         //  見つけるという処理になっていると思われる）
-        if chord_util::exist_between_two_nodes_right_mawari(deep_cloned_self_node.node_id, id, conved_node_info.node_id) {
+        // 同じノードを指すエントリは1つの候補とする
+        if chord_util::exist_between_two_nodes_right_mawari(deep_cloned_self_node.node_id, id, conved_node_info.node_id)
+            && candidates.iter().any(|ninfo| ninfo.address_str == conved_node_info.address_str) == false {
+            candidates.push(conved_node_info.clone());
+        }
+    }
+    // 探索対象のIDに近い方から gval::PROXIMITY_ROUTE_CANDIDATES 個の候補は、RTTの小さい順に試す
+    // (ホップ数が増える場合があるが、1ホップあたりの遅延の小さいノードを経由する). RTTが未計測のものは後に回す
+    let proximity_len = std::cmp::min(candidates.len(), gval::PROXIMITY_ROUTE_CANDIDATES);
+    candidates[..proximity_len].sort_by_key(|ninfo| if ninfo.rtt_micros == 0 { u64::MAX } else { ninfo.rtt_micros });
+    // finger_table の候補が全てダウンしていた場合に備え、successor_info_list のうち探索対象のIDを飛び越さないものを
+    // 遠い方から候補に加える
+    for succ_info in deep_cloned_self_node.successor_info_list.iter().rev() {
+        if chord_util::exist_between_two_nodes_right_mawari(deep_cloned_self_node.node_id, id, succ_info.node_id) {
+            candidates.push(succ_info.clone());
+        }
+    }
 
+    match pick_live_candidate(&self_node, &deep_cloned_self_node, candidates, downed_addresses).await {
+        Some(found) => {
             chord_util::dprint(&("closest_preceding_finger_2,".to_string() + chord_util::gen_debug_str_of_node(&deep_cloned_self_node).as_str() + ","
-                            + chord_util::gen_debug_str_of_node(&conved_node_info).as_str()));
-
-            let gnba_rslt = match endpoints::rrpc_call__get_node_info(&conved_node_info.address_str){
-                Err(err) => {
-                    self_node_ref = self_node.lock().unwrap();
-                    node_info::handle_downed_node_info(&mut self_node_ref, &conved_node_info, &err);
-                    return Err(chord_util::GeneralError::new(err.message, err.err_code));
-                }
-                Ok(got_node) => {                
-                    return Ok(node_info::gen_summary_node_info(&got_node));
-                }
-            };
+                            + found.address_str.as_str()));
+            return Ok(found);
         }
-    }
+        None => {}
+    };
 
     chord_util::dprint(&"closest_preceding_finger_3".to_string());
 
     // どんなに範囲を狭めても探索対象のIDを超えてしまうノードしか存在しなかった場合
     // 自身の知っている情報の中で対象を飛び越さない範囲で一番近いノードは自身という
     // ことになる
-    return Ok(node_info::gen_summary_node_info(&deep_cloned_self_node));
+    {
+        let self_node_ref = self_node.lock().unwrap();
+        if self_node_ref.successor_info_list.len() == 0 {
+            // 候補のダウンが判明した結果 successor が分からなくなった場合
+            return Err(chord_util::GeneralError::new("no live successor is known".to_string(), chord_util::ERR_CODE_NODE_IS_DOWNED));
+        }
+        return Ok(node_info::gen_summary_node_info(&self_node_ref));
+    }
 }
 
+// candidates を先頭から順に successor を問い合わせ、最初に応答のあったノードの情報を返す
+// ただし failure_detector で疑わしいとされているノードは、健全なノードを全て試した後に回す
+// 応答の無かったノードは downed_addresses に加え、handle_downed_node_info で経路情報に反映する
+async fn pick_live_candidate(self_node: &ArMu<node_info::NodeInfo>, self_node_ni: &node_info::NodeInfo, mut candidates: Vec<node_info::NodeInfoEntry>, downed_addresses: &mut Vec<String>) -> Option<node_info::NodeInfoSummary> {
+    candidates.sort_by_key(|candidate| failure_detector::get_route_priority(&candidate.address_str));
+    for candidate in candidates {
+        if candidate.address_str == self_node_ni.address_str || downed_addresses.contains(&candidate.address_str) {
+            continue;
+        }
+        match endpoints::rrpc_call__get_successor(&candidate.address_str).await {
+            // 参加処理の途中のノードなど、successor が分からないノードは経由できない
+            Err(err) if chord_util::is_unreachable_err(&err) == false => { continue; }
+            Err(err) => {
+                chord_util::dprint(&("pick_live_candidate_downed,".to_string() + chord_util::gen_debug_str_of_node(self_node_ni).as_str() + ","
+                    + chord_util::gen_debug_str_of_entry(&candidate).as_str()));
+                downed_addresses.push(candidate.address_str.clone());
+                let mut self_node_ref = self_node.lock().unwrap();
+                node_info::handle_downed_node_info(&mut self_node_ref, &node_info::gen_node_info_from_entry(&candidate), &err);
+            }
+            Ok(succ_info) => {
+                return Some(node_info::gen_summary_from_entry(&candidate, succ_info.node_id));
+            }
+        };
+    }
+    return None;
+}
+
+// 自身から successor を辿ってリングを一周し、自身を含む全ノード (仮想ノード) のアドレスをリング上の順に得る
+// 途中のノードからの応答が無く一周できなかった場合はエラーを返す
+pub async fn collect_ring_vnode_addresses(self_node: ArMu<node_info::NodeInfo>) -> Result<Vec<String>, chord_util::GeneralError> {
+    let self_address;
+    let mut next_address;
+    {
+        let self_node_ref = self_node.lock().unwrap();
+        self_address = self_node_ref.address_str.clone();
+        if self_node_ref.successor_info_list.len() == 0 {
+            return Ok(vec![self_address]);
+        }
+        next_address = self_node_ref.successor_info_list[0].address_str.clone();
+    }
+
+    let mut addresses: Vec<String> = vec![self_address.clone()];
+    while next_address != self_address {
+        if addresses.contains(&next_address) || addresses.len() >= gval::RING_WALK_MAX_NODES {
+            // リングが構成途中などで、自身に戻らないループとなっている
+            return Err(chord_util::GeneralError::new("ring walk did not come back to self node".to_string(), chord_util::ERR_CODE_INTERNAL_CONTROL_FLOW_PROBLEM));
+        }
+        let next_succ = match endpoints::rrpc_call__get_successor(&next_address).await {
+            Err(err) if err.err_code != chord_util::ERR_CODE_HTTP_REQUEST_ERR => {
+                return Err(chord_util::GeneralError::new("successor is unknown: ".to_string() + next_address.as_str(), chord_util::ERR_CODE_INTERNAL_CONTROL_FLOW_PROBLEM));
+            }
+            Err(err) => {
+                chord_util::dprint(&("ERROR at collect_ring_vnode_addresses,".to_string() + next_address.as_str()));
+                return Err(err);
+            }
+            Ok(succ_info) => succ_info
+        };
+        addresses.push(next_address);
+        next_address = next_succ.address_str;
+    }
+
+    return Ok(addresses);
+}
+
+// collect_ring_vnode_addresses と同様だが、仮想ノードをホストするプロセスごとに1つのアドレスにまとめて返す
+// (DataStore はプロセスで共有されているため、データに対する処理ではこちらを用いる)
+pub async fn collect_ring_node_addresses(self_node: ArMu<node_info::NodeInfo>) -> Result<Vec<String>, chord_util::GeneralError> {
+    let addresses = match collect_ring_vnode_addresses(self_node).await {
+        Err(err) => { return Err(err); }
+        Ok(addresses) => addresses
+    };
+
+    let mut physical_addresses: Vec<String> = vec![];
+    for address in addresses {
+        let physical_address = vnode::get_physical_address(&address);
+        if physical_addresses.contains(&physical_address) == false {
+            physical_addresses.push(physical_address);
+        }
+    }
+    return Ok(physical_addresses);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn gen_entry(node_id: u32, address_str: &str) -> node_info::NodeInfoEntry {
+        return node_info::NodeInfoEntry { node_id: node_id, address_str: address_str.to_string(), born_id: node_id as i32, zone: "".to_string(), rtt_micros: 0 };
+    }
+
+    fn gen_summary(node_id: u32, address_str: &str) -> node_info::NodeInfoSummary {
+        return node_info::NodeInfoSummary { node_id: node_id, succ0_id: 0, address_str: address_str.to_string(), zone: "".to_string() };
+    }
+
+    // パスごとに決まったJSONを返すピア. 登録の無いパスには 404 を返す
+    async fn spawn_fake_peer(responses: HashMap<String, String>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = match listener.accept().await {
+                    Err(_) => { return; }
+                    Ok(accepted) => accepted
+                };
+                let mut buf = vec![0u8; 8192];
+                let n = stream.read(&mut buf).await.unwrap_or(0);
+                let req_text = String::from_utf8_lossy(&buf[..n]).to_string();
+                let path = req_text.split_whitespace().nth(1).unwrap_or("").to_string();
+                let (status, body) = match responses.get(&path) {
+                    None => ("404 Not Found", "".to_string()),
+                    Some(body) => ("200 OK", body.clone())
+                };
+                let resp = format!("HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body);
+                let _ = stream.write_all(resp.as_bytes()).await;
+            }
+        });
+        return address;
+    }
+
+    // 接続を受け付けないアドレス
+    async fn gen_closed_address() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        return listener.local_addr().unwrap().to_string();
+    }
+
+    fn gen_self_node(address_str: &str, succ: &node_info::NodeInfoEntry) -> ArMu<node_info::NodeInfo> {
+        let mut self_node = node_info::NodeInfo::new();
+        self_node.node_id = 0;
+        self_node.address_str = address_str.to_string();
+        self_node.successor_info_list.push(succ.clone());
+        return Arc::new(Mutex::new(self_node));
+    }
+
+    // 直前に経由したノードもダウンしていた場合は、さらに1つ前のノードの successor_info_list から代わりのノードを選ぶ
+    #[tokio::test]
+    async fn find_alternative_hop_goes_back_past_downed_prev_hop() {
+        let id: u32 = 1000;
+        let addr_b = gen_closed_address().await;
+        let addr_c = gen_closed_address().await;
+        let addr_d = spawn_fake_peer(HashMap::from([
+            ("/internal/get_successor".to_string(), serde_json::to_string(&Ok::<node_info::NodeInfoEntry, chord_util::GeneralError>(gen_entry(300, &addr_c))).unwrap())
+        ])).await;
+        let succ_list_of_a = vec![gen_entry(200, &addr_b), gen_entry(250, &addr_d), gen_entry(300, &addr_c)];
+        let addr_a = spawn_fake_peer(HashMap::from([
+            ("/internal/get_successor_list".to_string(), serde_json::to_string(&succ_list_of_a).unwrap())
+        ])).await;
+        let addr_s = "127.0.0.1:1".to_string();
+
+        let self_node = gen_self_node(&addr_s, &gen_entry(100, &addr_a));
+        let exnode = node_info::partial_clone_from_ref_strong(&self_node.lock().unwrap());
+        // S -> A -> B と経由し、B から得た C がダウンしていた
+        let mut path = vec![gen_summary(0, &addr_s), gen_summary(100, &addr_a), gen_summary(200, &addr_b)];
+        let mut downed_addresses = vec![addr_c.clone()];
+
+        let found = find_alternative_hop(Arc::clone(&self_node), &exnode, &mut path, id, &mut downed_addresses).await.unwrap();
+        assert_eq!(found.address_str, addr_d);
+        assert_eq!(found.node_id, 250);
+        assert_eq!(path.iter().map(|hop| hop.node_id).collect::<Vec<u32>>(), vec![0, 100]);
+        assert!(downed_addresses.contains(&addr_b));
+    }
+
+    // 自ノードまで遡っても応答のあるノードが無ければエラーとなる
+    #[tokio::test]
+    async fn find_alternative_hop_fails_when_no_live_node_remains() {
+        let id: u32 = 1000;
+        let addr_b = gen_closed_address().await;
+        let addr_s = "127.0.0.1:1".to_string();
+
+        let self_node = gen_self_node(&addr_s, &gen_entry(200, &addr_b));
+        let exnode = node_info::partial_clone_from_ref_strong(&self_node.lock().unwrap());
+        let mut path = vec![gen_summary(0, &addr_s), gen_summary(200, &addr_b)];
+        let mut downed_addresses: Vec<String> = vec![];
+
+        let err = find_alternative_hop(Arc::clone(&self_node), &exnode, &mut path, id, &mut downed_addresses).await.unwrap_err();
+        assert_eq!(err.err_code, chord_util::ERR_CODE_APPROPRIATE_NODE_NOT_FOND);
+        assert!(downed_addresses.contains(&addr_b));
+    }
+
+    #[test]
+    fn filter_alternative_candidates_skips_nodes_past_id() {
+        let addr = "127.0.0.1:1".to_string();
+        let base = gen_summary(100, &addr);
+        let succ_list = vec![gen_entry(100, &addr), gen_entry(200, &addr), gen_entry(500, &addr), gen_entry(1500, &addr)];
+        let candidates = filter_alternative_candidates(&base, &succ_list, 1000);
+        assert_eq!(candidates.iter().map(|entry| entry.node_id).collect::<Vec<u32>>(), vec![500, 200]);
+    }
+}
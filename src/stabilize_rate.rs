@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::gval;
+use crate::config;
+
+// stabilize処理の実行間隔の調整 (仮想ノードごと)
+//
+// 経路表が変化しない状態が gval::STABILIZE_BACKOFF_STABLE_ROUNDS 回続くごとに間隔を2倍に広げ、
+// ノードの参加や離脱 (predecessor や successor の変化、RPCの失敗) を検知した場合は下限に戻す
+// 間隔の下限と上限は config の stabilize_successor_interval, stabilize_finger_interval で指定する
+// 検知は notify_churn で仮想ノードごとの通知回数を増やすことで行い、各ループは前回からの増加の有無で判断する
+
+// 監視用に返す、仮想ノードごとの現在の実行間隔
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StabilizeRate {
+    pub address_str: String,
+    pub successor_interval_millis: u64,
+    pub finger_interval_millis: u64,
+    // これまでに検知したノードの参加や離脱、RPCの失敗の回数
+    pub churn_count: u64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StabilizeKind {
+    Successor,
+    Finger
+}
+
+lazy_static! {
+    // キーは仮想ノードのアドレス
+    static ref STABILIZE_RATES : Mutex<HashMap<String, StabilizeRate>> = Mutex::new(HashMap::new());
+}
+
+fn gen_initial_rate(address_str: &str) -> StabilizeRate {
+    return StabilizeRate {
+        address_str: address_str.to_string(),
+        successor_interval_millis: config::get_stabilize_successor_interval().min_millis,
+        finger_interval_millis: config::get_stabilize_finger_interval().min_millis,
+        churn_count: 0
+    };
+}
+
+// address_str の仮想ノードでノードの参加や離脱、RPCの失敗を検知したことを通知する
+pub fn notify_churn(address_str: &str) {
+    let mut rates_ref = STABILIZE_RATES.lock().unwrap();
+    let rate = rates_ref.entry(address_str.to_string()).or_insert_with(|| gen_initial_rate(address_str));
+    rate.churn_count += 1;
+}
+
+fn get_churn_count(address_str: &String) -> u64 {
+    let rates_ref = STABILIZE_RATES.lock().unwrap();
+    return match rates_ref.get(address_str) {
+        None => 0,
+        Some(rate) => rate.churn_count
+    };
+}
+
+fn record_interval(address_str: &str, kind: StabilizeKind, interval_millis: u64) {
+    let mut rates_ref = STABILIZE_RATES.lock().unwrap();
+    let rate = rates_ref.entry(address_str.to_string()).or_insert_with(|| gen_initial_rate(address_str));
+    match kind {
+        StabilizeKind::Successor => { rate.successor_interval_millis = interval_millis; }
+        StabilizeKind::Finger => { rate.finger_interval_millis = interval_millis; }
+    };
+}
+
+// このプロセスの全仮想ノードの現在の実行間隔. アドレス順
+pub fn get_stabilize_rates() -> Vec<StabilizeRate> {
+    let rates_ref = STABILIZE_RATES.lock().unwrap();
+    let mut rates: Vec<StabilizeRate> = rates_ref.values().cloned().collect();
+    rates.sort_by(|a, b| a.address_str.cmp(&b.address_str));
+    return rates;
+}
+
+// stabilize処理のループごとに1つ保持し、各回の処理の後に next_interval_millis で次回までの待ち時間を得る
+pub struct AdaptiveInterval {
+    address_str: String,
+    kind: StabilizeKind,
+    min_millis: u64,
+    max_millis: u64,
+    current_millis: u64,
+    // 前回の判定時点での通知回数
+    seen_churn_count: u64,
+    // 変化が無かった連続の回数. 間隔を広げるごとに0に戻す
+    stable_rounds: u32
+}
+
+impl AdaptiveInterval {
+    pub fn new(address_str: &String, kind: StabilizeKind) -> AdaptiveInterval {
+        let bounds = match kind {
+            StabilizeKind::Successor => config::get_stabilize_successor_interval(),
+            StabilizeKind::Finger => config::get_stabilize_finger_interval()
+        };
+        record_interval(address_str, kind, bounds.min_millis);
+        return AdaptiveInterval {
+            address_str: address_str.clone(),
+            kind: kind,
+            min_millis: bounds.min_millis,
+            max_millis: bounds.max_millis,
+            current_millis: bounds.min_millis,
+            seen_churn_count: get_churn_count(address_str),
+            stable_rounds: 0
+        };
+    }
+
+    pub fn next_interval_millis(&mut self) -> u64 {
+        let churn_count = get_churn_count(&self.address_str);
+        if churn_count != self.seen_churn_count {
+            self.seen_churn_count = churn_count;
+            self.current_millis = self.min_millis;
+            self.stable_rounds = 0;
+        }else{
+            self.stable_rounds += 1;
+            if self.stable_rounds >= gval::STABILIZE_BACKOFF_STABLE_ROUNDS {
+                self.current_millis = std::cmp::min(self.current_millis * 2, self.max_millis);
+                self.stable_rounds = 0;
+            }
+        }
+        record_interval(&self.address_str, self.kind, self.current_millis);
+        return self.current_millis;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // STABILIZE_RATES はプロセスで共有されるため、テストごとに異なるアドレスを用いる
+    fn find_rate(address_str: &str) -> StabilizeRate {
+        return get_stabilize_rates().into_iter().find(|rate| rate.address_str == address_str).unwrap();
+    }
+
+    #[test]
+    fn next_interval_backs_off_while_stable() {
+        let address = "127.0.0.1:19301".to_string();
+        let bounds = config::get_stabilize_successor_interval();
+        let mut interval = AdaptiveInterval::new(&address, StabilizeKind::Successor);
+        assert_eq!(find_rate(&address).successor_interval_millis, bounds.min_millis);
+
+        let mut expected = bounds.min_millis;
+        for _ in 0..5 {
+            for _ in 1..gval::STABILIZE_BACKOFF_STABLE_ROUNDS {
+                assert_eq!(interval.next_interval_millis(), expected);
+            }
+            // gval::STABILIZE_BACKOFF_STABLE_ROUNDS 回続けて変化が無ければ2倍にする. 上限は超えない
+            expected = std::cmp::min(expected * 2, bounds.max_millis);
+            assert_eq!(interval.next_interval_millis(), expected);
+        }
+        assert_eq!(expected, bounds.max_millis);
+        assert_eq!(find_rate(&address).successor_interval_millis, bounds.max_millis);
+    }
+
+    #[test]
+    fn next_interval_resets_on_churn() {
+        let address = "127.0.0.1:19302".to_string();
+        let bounds = config::get_stabilize_finger_interval();
+        let mut interval = AdaptiveInterval::new(&address, StabilizeKind::Finger);
+        for _ in 0..gval::STABILIZE_BACKOFF_STABLE_ROUNDS {
+            interval.next_interval_millis();
+        }
+        assert_eq!(interval.next_interval_millis(), bounds.min_millis * 2);
+
+        notify_churn(&address);
+        assert_eq!(interval.next_interval_millis(), bounds.min_millis);
+        let rate = find_rate(&address);
+        assert_eq!(rate.finger_interval_millis, bounds.min_millis);
+        assert_eq!(rate.churn_count, 1);
+
+        // 通知を受けた後の判定からは、再び変化が無い回数を数え直す
+        for _ in 1..gval::STABILIZE_BACKOFF_STABLE_ROUNDS {
+            assert_eq!(interval.next_interval_millis(), bounds.min_millis);
+        }
+        assert_eq!(interval.next_interval_millis(), bounds.min_millis * 2);
+    }
+}
@@ -2,20 +2,31 @@ use std::sync::{Arc, Mutex};
 use std::cell::{RefMut, RefCell, Ref};
 use std::sync::atomic::Ordering;
 use std::borrow::{Borrow, BorrowMut};
+use std::time::Instant;
+use std::collections::HashMap;
 
 use crate::gval;
 use crate::chord_node;
 use crate::node_info;
 use crate::chord_util;
 use crate::endpoints;
+use crate::config;
+use crate::vnode;
+use crate::transfer;
 use crate::data_store;
 use crate::router;
+use crate::stabilize_rate;
 
 type ArMu<T> = Arc<Mutex<T>>;
 
+lazy_static! {
+    // check_predecessor_liveness で最後に生存を確認できた predecessor. キーは仮想ノードのアドレス
+    static ref LAST_LIVE_PREDECESSORS : Mutex<HashMap<String, node_info::NodeInfoEntry>> = Mutex::new(HashMap::new());
+}
+
 // 経路表の情報を他ノードから強制的に設定する.
 // joinメソッドの中で、secondノードがfirstノードに対してのみ用いるものであり、他のケースで利用してはならない
-pub fn set_routing_infos_force(self_node: ArMu<node_info::NodeInfo>, predecessor_info: node_info::NodeInfo, successor_info_0: node_info::NodeInfo , ftable_enry_0: node_info::NodeInfo){
+pub async fn set_routing_infos_force(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::DataStore>, predecessor_info: node_info::NodeInfoEntry, successor_info_0: node_info::NodeInfoEntry , ftable_enry_0: node_info::NodeInfoEntry){
     //with self.existing_node.node_info.lock_of_pred_info, self.existing_node.node_info.lock_of_succ_infos:
     let self_node_clone;
     {
@@ -25,116 +36,131 @@ pub fn set_routing_infos_force(self_node: ArMu<node_info::NodeInfo>, predecessor
         self_node_ref.finger_table[0] = Some(ftable_enry_0);
         self_node_clone = (*self_node_ref).clone();
     }
-    node_info::set_pred_info(Arc::clone(&self_node), predecessor_info);
+    node_info::set_pred_info(Arc::clone(&self_node), predecessor_info.clone());
+
+    // secondノードの参加時に用いられる. 新たな predecessor となった secondノードの担当範囲のデータを委譲する
+    // (元の predecessor は自身であるため、自身の担当範囲外のデータを全て委譲することになる)
+    if predecessor_info.address_str != self_node_clone.address_str {
+        let range_from = match self_node_clone.predecessor_info.first() {
+            None => chord_util::overflow_check_and_conv(self_node_clone.node_id as u64 + 1),
+            Some(old_pred) => chord_util::overflow_check_and_conv(old_pred.node_id as u64 + 1)
+        };
+        delegate_datas_to_predecessor(Arc::clone(&self_node), data_store, &predecessor_info, range_from).await;
+    }
 }
 
 // node_addressに対応するノードに問い合わせを行い、教えてもらったノードをsuccessorとして設定する
-pub fn join(new_node: ArMu<node_info::NodeInfo>, self_node_address: &String, tyukai_node_address: &String, born_id: i32){
-    let mut new_node_ref = new_node.lock().unwrap();
-    
-    // ミリ秒精度のUNIXTIMEからChordネットワーク上でのIDを決定する
-    new_node_ref.born_id = born_id;
-    new_node_ref.address_str = (*self_node_address).clone();
-    new_node_ref.node_id = chord_util::hash_str_to_int(&(chord_util::get_unixtime_in_nanos().to_string()));
-
-    let mut deep_cloned_new_node = node_info::partial_clone_from_ref_strong(&new_node_ref);
+pub async fn join(new_node: ArMu<node_info::NodeInfo>, self_node_address: &String, tyukai_node_address: &String, born_id: i32){
+    // ロックを保持したままRPCの完了を待たないよう、経路情報の操作はブロック内で行う
+    let mut deep_cloned_new_node;
     let mut is_second_node:bool = false;
+    {
+        let mut new_node_ref = new_node.lock().unwrap();
 
-    if born_id == 1 { 
-        // first_node の場合
+        // ミリ秒精度のUNIXTIMEからChordネットワーク上でのIDを決定する
+        new_node_ref.born_id = born_id;
+        new_node_ref.address_str = (*self_node_address).clone();
+        new_node_ref.zone = config::get_zone();
+        new_node_ref.node_id = chord_util::hash_str_to_int(&(chord_util::get_unixtime_in_nanos().to_string()));
 
-        // successorとpredecessorは自身として終了する
-        new_node_ref.successor_info_list.push(deep_cloned_new_node.clone());
-        new_node_ref.finger_table[0] = Some(deep_cloned_new_node.clone());
-        drop(deep_cloned_new_node);
         deep_cloned_new_node = node_info::partial_clone_from_ref_strong(&new_node_ref);
-        drop(new_node_ref);
-        node_info::set_pred_info(Arc::clone(&new_node), deep_cloned_new_node.clone());
 
-        println!("first_node at join: {:?}", new_node.lock().unwrap());
-        return;
-    }
+        if born_id == 1 { 
+            // first_node の場合
+
+            // successorとpredecessorは自身として終了する
+            new_node_ref.successor_info_list.push(node_info::gen_entry(&deep_cloned_new_node));
+            new_node_ref.finger_table[0] = Some(node_info::gen_entry(&deep_cloned_new_node));
+            drop(deep_cloned_new_node);
+            deep_cloned_new_node = node_info::partial_clone_from_ref_strong(&new_node_ref);
+            drop(new_node_ref);
+            node_info::set_pred_info(Arc::clone(&new_node), node_info::gen_entry(&deep_cloned_new_node));
 
-    drop(new_node_ref);    
+            println!("first_node at join: {:?}", new_node.lock().unwrap());
+            return;
+        }
+    }
 
     // ダウンしているノードの情報が与えられることは想定しない
-    let tyukai_node = endpoints::rrpc_call__get_node_info(tyukai_node_address).unwrap();
+    let tyukai_node = endpoints::rrpc_call__get_node_info(tyukai_node_address).await.unwrap();
 
     // 仲介ノードに自身のsuccessorになるべきノードを探してもらう
     chord_util::dprint(&("join_1,".to_string() + chord_util::gen_debug_str_of_node(&deep_cloned_new_node).as_str() + ","
         + chord_util::gen_debug_str_of_node(&tyukai_node).as_str()));    
-    let successor = endpoints::rrpc_call__find_successor(&tyukai_node, deep_cloned_new_node.node_id).unwrap();
+    let successor = endpoints::rrpc_call__find_successor(&tyukai_node, deep_cloned_new_node.node_id).await.unwrap();
 
     if deep_cloned_new_node.node_id == successor.node_id {
         chord_util::dprint(&("join_2_5,".to_string() + chord_util::gen_debug_str_of_node(&deep_cloned_new_node).as_str() + ","
                         + chord_util::gen_debug_str_of_node(&tyukai_node).as_str() + ","
-                        + chord_util::gen_debug_str_of_node(&deep_cloned_new_node.successor_info_list[0]).as_str() + ",FOUND_NODE_IS_SAME_WITH_SELF_NODE!!!"));
+                        + chord_util::gen_debug_str_of_entry(&deep_cloned_new_node.successor_info_list[0]).as_str() + ",FOUND_NODE_IS_SAME_WITH_SELF_NODE!!!"));
     }
 
-    new_node_ref = new_node.lock().unwrap();
     if tyukai_node.node_id == tyukai_node.successor_info_list[0].node_id {
         // secondノードの場合の考慮 (仲介ノードは必ずfirst node)
 
         // 2ノードでsuccessorでもpredecessorでも、チェーン構造で正しい環が構成されるよう強制的に全て設定してしまう
         // secondノードの場合の考慮 (仲介ノードは必ずfirst node)
         is_second_node = true;
-        
-        new_node_ref.successor_info_list.push(tyukai_node.clone());
 
-        drop(deep_cloned_new_node);
-        deep_cloned_new_node = node_info::partial_clone_from_ref_strong(&new_node_ref);
-        drop(new_node_ref);
-        node_info::set_pred_info(Arc::clone(&new_node), tyukai_node.clone());
+        {
+            let mut new_node_ref = new_node.lock().unwrap();
+            new_node_ref.successor_info_list.push(node_info::gen_entry(&tyukai_node));
+
+            drop(deep_cloned_new_node);
+            deep_cloned_new_node = node_info::partial_clone_from_ref_strong(&new_node_ref);
+        }
+        node_info::set_pred_info(Arc::clone(&new_node), node_info::gen_entry(&tyukai_node));
         endpoints::rrpc_call__set_routing_infos_force(
             &tyukai_node,
-            deep_cloned_new_node.clone(),
-            deep_cloned_new_node.clone(),
-            deep_cloned_new_node.clone()
-        );
+            node_info::gen_entry(&deep_cloned_new_node),
+            node_info::gen_entry(&deep_cloned_new_node),
+            node_info::gen_entry(&deep_cloned_new_node)
+        ).await;
 
         chord_util::dprint(&("join_3,".to_string() + chord_util::gen_debug_str_of_node(&deep_cloned_new_node).as_str() + ","
         + chord_util::gen_debug_str_of_node(&tyukai_node).as_str() + ","
-        + chord_util::gen_debug_str_of_node(&deep_cloned_new_node.successor_info_list[0]).as_str()));
+        + chord_util::gen_debug_str_of_entry(&deep_cloned_new_node.successor_info_list[0]).as_str()));
         
         return;
-    }else{
-        drop(new_node_ref);
     }
 
-    new_node_ref = new_node.lock().unwrap();
+    {
+        let mut new_node_ref = new_node.lock().unwrap();
 
-    // successorを設定する
-    new_node_ref.successor_info_list.push(node_info::gen_node_info_from_summary(&successor));
+        // successorを設定する
+        new_node_ref.successor_info_list.push(node_info::gen_entry_from_summary(&successor));
 
-    // finger_tableのインデックス0は必ずsuccessorになるはずなので、設定しておく
-    new_node_ref.finger_table[0] = Some(new_node_ref.successor_info_list[0].clone());
+        // finger_tableのインデックス0は必ずsuccessorになるはずなので、設定しておく
+        new_node_ref.finger_table[0] = Some(new_node_ref.successor_info_list[0].clone());
 
-    // successorと、successorノードの情報だけ適切なものとする
+        // successorと、successorノードの情報だけ適切なものとする
+    }
 
-    drop(new_node_ref);
-    match endpoints::rrpc_call__check_predecessor(&node_info::gen_node_info_from_summary(&successor), &deep_cloned_new_node.clone()){
+    match endpoints::rrpc_call__check_predecessor(&node_info::gen_node_info_from_summary(&successor), &node_info::gen_entry(&deep_cloned_new_node)).await{
         Err(err) => {
             // IDを変えてリトライ
             // (これで異なるsuccessorが得られて、そのノードは生きていることを期待する)
-            join(new_node, self_node_address, tyukai_node_address, born_id);
+            Box::pin(join(new_node, self_node_address, tyukai_node_address, born_id)).await;
         }
         Ok(some) => {}
     };
 }
 
-pub fn stabilize_successor(self_node: ArMu<node_info::NodeInfo>) -> Result<bool, chord_util::GeneralError>{
-    let mut self_node_ref = self_node.lock().unwrap();
-    let mut deep_cloned_self_node = node_info::partial_clone_from_ref_strong(&self_node_ref);
-    //println!("P-SELF-S: P: {:?} SELF: {:?} S {:?}", self_node_ref.predecessor_info, *self_node_ref, self_node_ref.successor_info_list);
-    drop(self_node_ref);
+pub async fn stabilize_successor(self_node: ArMu<node_info::NodeInfo>) -> Result<bool, chord_util::GeneralError>{
+    let mut deep_cloned_self_node;
+    {
+        let self_node_ref = self_node.lock().unwrap();
+        deep_cloned_self_node = node_info::partial_clone_from_ref_strong(&self_node_ref);
+        //println!("P-SELF-S: P: {:?} SELF: {:?} S {:?}", self_node_ref.predecessor_info, *self_node_ref, self_node_ref.successor_info_list);
+    }
 
     chord_util::dprint(&("stabilize_successor_1,".to_string() + chord_util::gen_debug_str_of_node(&deep_cloned_self_node).as_str() + ","
-          + chord_util::gen_debug_str_of_node(&deep_cloned_self_node.successor_info_list[0]).as_str()));
+          + chord_util::gen_debug_str_of_entry(&deep_cloned_self_node.successor_info_list[0]).as_str()));
 
     // firstノードだけが存在する状況で、self_nodeがfirst_nodeであった場合に対する考慮
     if deep_cloned_self_node.predecessor_info.len() == 0 && deep_cloned_self_node.node_id == deep_cloned_self_node.successor_info_list[0].node_id {
         chord_util::dprint(&("stabilize_successor_1_5,".to_string() + chord_util::gen_debug_str_of_node(&deep_cloned_self_node).as_str() + ","
-                         + chord_util::gen_debug_str_of_node(&deep_cloned_self_node.successor_info_list[0]).as_str()));
+                         + chord_util::gen_debug_str_of_entry(&deep_cloned_self_node.successor_info_list[0]).as_str()));
 
         // secondノードがjoinしてきた際にチェーン構造は2ノードで適切に構成されるように
         // なっているため、ここでは何もせずに終了する
@@ -147,12 +173,12 @@ pub fn stabilize_successor(self_node: ArMu<node_info::NodeInfo>) -> Result<bool,
     // 場合があるため、successorのChordNodeオブジェクトを引いて、そこから最新のnode_info
     // の参照を得る
     
-    let ret = endpoints::rrpc_call__get_node_info(&deep_cloned_self_node.successor_info_list[0].address_str);
+    let ret = endpoints::rrpc_call__get_node_info(&deep_cloned_self_node.successor_info_list[0].address_str).await;
 
     let successor_info = match ret{
         Err(err) => {
-            self_node_ref = self_node.lock().unwrap();
-            node_info::handle_downed_node_info(&mut self_node_ref, &deep_cloned_self_node.successor_info_list[0], &err);
+            let mut self_node_ref = self_node.lock().unwrap();
+            node_info::handle_downed_node_info(&mut self_node_ref, &node_info::gen_node_info_from_entry(&deep_cloned_self_node.successor_info_list[0]), &err);
             return Err(chord_util::GeneralError::new(err.message, err.err_code));
         }
         Ok(got_node) => {                
@@ -160,14 +186,24 @@ pub fn stabilize_successor(self_node: ArMu<node_info::NodeInfo>) -> Result<bool,
         }
     };
 
+    // successor のIDがリバランスによって移動していた場合は、保持している情報を更新する
+    if successor_info.node_id != deep_cloned_self_node.successor_info_list[0].node_id
+        && successor_info.address_str == deep_cloned_self_node.successor_info_list[0].address_str {
+        let mut self_node_ref = self_node.lock().unwrap();
+        if self_node_ref.successor_info_list.len() > 0 && self_node_ref.successor_info_list[0].address_str == successor_info.address_str {
+            self_node_ref.successor_info_list[0] = node_info::gen_entry(&successor_info);
+        }
+        deep_cloned_self_node = node_info::partial_clone_from_ref_strong(&self_node_ref);
+    }
+
     if successor_info.predecessor_info.len() == 0 {
         //is_successor_has_no_pred = true;
         chord_util::dprint(&("stabilize_successor_2,".to_string() + chord_util::gen_debug_str_of_node(&deep_cloned_self_node).as_str() + ","
-        + chord_util::gen_debug_str_of_node(&deep_cloned_self_node.successor_info_list[0]).as_str()));
+        + chord_util::gen_debug_str_of_entry(&deep_cloned_self_node.successor_info_list[0]).as_str()));
 
-        match endpoints::rrpc_call__check_predecessor(&successor_info, &deep_cloned_self_node.clone()){
+        match endpoints::rrpc_call__check_predecessor(&successor_info, &node_info::gen_entry(&deep_cloned_self_node)).await{
             Err(err) => {
-                self_node_ref = self_node.lock().unwrap();
+                let mut self_node_ref = self_node.lock().unwrap();
                 node_info::handle_downed_node_info(&mut self_node_ref, &successor_info, &err);
                 return Ok(true);
             }
@@ -178,7 +214,7 @@ pub fn stabilize_successor(self_node: ArMu<node_info::NodeInfo>) -> Result<bool,
     }
 
     chord_util::dprint(&("stabilize_successor_3,".to_string() + chord_util::gen_debug_str_of_node(&deep_cloned_self_node).as_str() + ","
-                    + chord_util::gen_debug_str_of_node(&successor_info.successor_info_list[0]).as_str()));
+                    + chord_util::gen_debug_str_of_entry(&successor_info.successor_info_list[0]).as_str()));
 
     let pred_id_of_successor = successor_info.predecessor_info[0].node_id;
 
@@ -190,7 +226,7 @@ pub fn stabilize_successor(self_node: ArMu<node_info::NodeInfo>) -> Result<bool,
         // パターン1
         // 特に訂正は不要なので処理を終了する
         chord_util::dprint(&("stabilize_successor_4,".to_string() + chord_util::gen_debug_str_of_node(&deep_cloned_self_node).as_str() + ","
-        + chord_util::gen_debug_str_of_node(&successor_info.successor_info_list[0]).as_str()));
+        + chord_util::gen_debug_str_of_entry(&successor_info.successor_info_list[0]).as_str()));
         return Ok(true);
     }else{
         // 以下、パターン2およびパターン3に対応する処理
@@ -200,11 +236,11 @@ pub fn stabilize_successor(self_node: ArMu<node_info::NodeInfo>) -> Result<bool,
         // 事前チェックによって避けられるかもしれないが、常に実行する
 
         chord_util::dprint(&("stabilize_successor_5,".to_string() + chord_util::gen_debug_str_of_node(&deep_cloned_self_node).as_str() + ","
-        + chord_util::gen_debug_str_of_node(&successor_info.successor_info_list[0]).as_str()));
+        + chord_util::gen_debug_str_of_entry(&successor_info.successor_info_list[0]).as_str()));
 
-        match endpoints::rrpc_call__check_predecessor(&successor_info, &deep_cloned_self_node.clone()){
+        match endpoints::rrpc_call__check_predecessor(&successor_info, &node_info::gen_entry(&deep_cloned_self_node)).await{
             Err(err) => {
-                self_node_ref = self_node.lock().unwrap();
+                let mut self_node_ref = self_node.lock().unwrap();
                 node_info::handle_downed_node_info(&mut self_node_ref, &successor_info, &err);
                 return Ok(true);
             }
@@ -222,30 +258,22 @@ pub fn stabilize_successor(self_node: ArMu<node_info::NodeInfo>) -> Result<bool,
             // successorから自身に対して前方向にたどった場合の経路中に存在する場合
             // 自身の認識するsuccessorの情報を更新する
 
-            self_node_ref = self_node.lock().unwrap();
-            chord_util::dprint(&("stabilize_successor_SET_SUCCESSOR,".to_string() + chord_util::gen_debug_str_of_node(&deep_cloned_self_node).as_str() + ", from "
-            + chord_util::gen_debug_str_of_node(&self_node_ref.successor_info_list[0]).as_str() + " to "
-            + chord_util::gen_debug_str_of_node(&successor_info.predecessor_info[0]).as_str()));
-            self_node_ref.successor_info_list[0] = successor_info.predecessor_info[0].clone();
-            deep_cloned_self_node = node_info::partial_clone_from_ref_strong(&self_node_ref);
-            drop(self_node_ref);
+            {
+                let mut self_node_ref = self_node.lock().unwrap();
+                chord_util::dprint(&("stabilize_successor_SET_SUCCESSOR,".to_string() + chord_util::gen_debug_str_of_node(&deep_cloned_self_node).as_str() + ", from "
+                + chord_util::gen_debug_str_of_entry(&self_node_ref.successor_info_list[0]).as_str() + " to "
+                + chord_util::gen_debug_str_of_entry(&successor_info.predecessor_info[0]).as_str()));
+                self_node_ref.successor_info_list[0] = successor_info.predecessor_info[0].clone();
+                stabilize_rate::notify_churn(&self_node_ref.address_str);
+                deep_cloned_self_node = node_info::partial_clone_from_ref_strong(&self_node_ref);
+            }
 
             // 新たなsuccessorに対して自身がpredecessorでないか確認を要請し必要であれ
             // ば情報を更新してもらう
-            let new_successor_info = match endpoints::rrpc_call__get_node_info(&deep_cloned_self_node.successor_info_list[0].address_str){
-                Err(err) => {
-                    self_node_ref = self_node.lock().unwrap();
-                    node_info::handle_downed_node_info(&mut self_node_ref, &deep_cloned_self_node.successor_info_list[0], &err);
-                    return Err(chord_util::GeneralError::new(err.message, err.err_code));
-                }
-                Ok(got_node) => {                
-                    got_node
-                }
-            };
-
-            match endpoints::rrpc_call__check_predecessor(&new_successor_info, &deep_cloned_self_node.clone()){
+            let new_successor_info = node_info::gen_node_info_from_entry(&deep_cloned_self_node.successor_info_list[0]);
+            match endpoints::rrpc_call__check_predecessor(&new_successor_info, &node_info::gen_entry(&deep_cloned_self_node)).await{
                 Err(err) => {
-                    self_node_ref = self_node.lock().unwrap();
+                    let mut self_node_ref = self_node.lock().unwrap();
                     node_info::handle_downed_node_info(&mut self_node_ref, &new_successor_info, &err);
                     return Ok(true);
                 }
@@ -253,7 +281,7 @@ pub fn stabilize_successor(self_node: ArMu<node_info::NodeInfo>) -> Result<bool,
             };
 
             chord_util::dprint(&("stabilize_successor_6,".to_string() + chord_util::gen_debug_str_of_node(&deep_cloned_self_node).as_str() + ","
-                             + chord_util::gen_debug_str_of_node(&deep_cloned_self_node.successor_info_list[0]).as_str() + ","
+                             + chord_util::gen_debug_str_of_entry(&deep_cloned_self_node.successor_info_list[0]).as_str() + ","
                              + chord_util::gen_debug_str_of_node(&deep_cloned_self_node).as_str()));
 
             return Ok(true);
@@ -264,73 +292,69 @@ pub fn stabilize_successor(self_node: ArMu<node_info::NodeInfo>) -> Result<bool,
 }
 
 // successor_info_listのインデックス1より後ろを規定数まで埋める
+// 各ノードの successor を get_successor で順に辿る
 // 途中でエラーとなった場合は、規定数に届いていなくとも処理を中断する
-pub fn fill_succ_info_list(self_node: ArMu<node_info::NodeInfo>) -> Result<bool, chord_util::GeneralError>{    
-    let mut self_node_ref = self_node.lock().unwrap();
-    chord_util::dprint(&("fill_succ_info_list_0,".to_string() + chord_util::gen_debug_str_of_node(&self_node_ref).as_str()));
+#[allow(clippy::explicit_counter_loop)]
+pub async fn fill_succ_info_list(self_node: ArMu<node_info::NodeInfo>) -> Result<bool, chord_util::GeneralError>{    
+    let self_node_id;
+    let mut next_succ_id;
+    let mut next_succ_info;
+    {
+        let self_node_ref = self_node.lock().unwrap();
+        chord_util::dprint(&("fill_succ_info_list_0,".to_string() + chord_util::gen_debug_str_of_node(&self_node_ref).as_str()));
 
-    let self_node_id = self_node_ref.node_id;
-    let mut next_succ_id = self_node_ref.node_id;
-    
-    let first_succ = self_node_ref.successor_info_list[0].clone();
-    //let mut next_succ_info = node_info::partial_clone_from_ref_strong(&self_node_ref);
-    drop(self_node_ref);    
-    let mut next_succ_info = match endpoints::rrpc_call__get_node_info(&first_succ.address_str) {
-        Err(err) => {
-            self_node_ref = self_node.lock().unwrap();
-            node_info::handle_downed_node_info(&mut self_node_ref, &first_succ, &err);
-            // 後続を辿れないので、リスト埋めは中止してreturnする
-            return Err(err);
-        }
-        Ok(ninfo) => ninfo
-    };
+        self_node_id = self_node_ref.node_id;
+        next_succ_id = self_node_ref.node_id;
+        
+        next_succ_info = self_node_ref.successor_info_list[0].clone();
+    }
     
     let mut idx_counter = 1;
     for times in 1..(gval::SUCCESSOR_INFO_LIST_LEN){
         next_succ_id = next_succ_info.node_id;
-        next_succ_info = node_info::partial_clone_from_ref_strong(&next_succ_info.successor_info_list[0]);
+        next_succ_info = match endpoints::rrpc_call__get_successor(&next_succ_info.address_str).await {
+            Err(err) => {
+                let mut self_node_ref = self_node.lock().unwrap();
+                node_info::handle_downed_node_info(&mut self_node_ref, &node_info::gen_node_info_from_entry(&next_succ_info), &err);
+                // 後続を辿れないので、リスト埋めは中止してreturnする
+                return Err(err);
+            }
+            Ok(succ_info) => succ_info
+        };
         if next_succ_info.node_id == self_node_id || next_succ_info.node_id == next_succ_id {
             // next_succ_infoがself_nodeと同一もしくは、1つ前の位置のノードを指していた場合
             // 後続を辿っていく処理がループを構成してしまうため抜ける
-            self_node_ref = self_node.lock().unwrap();
+            let self_node_ref = self_node.lock().unwrap();
             chord_util::dprint(
                 &("fill_succ_info_list_1,".to_string() 
                 + chord_util::gen_debug_str_of_node(&self_node_ref).as_str() + ","
-                + chord_util::gen_debug_str_of_node(&next_succ_info).as_str() + ","
+                + chord_util::gen_debug_str_of_entry(&next_succ_info).as_str() + ","
                 + self_node_ref.successor_info_list.len().to_string().as_str()
             ));
             return Ok(true);
         }
-        self_node_ref = self_node.lock().unwrap();
-        if self_node_ref.successor_info_list.len() < (idx_counter + 1) {            
-            self_node_ref.successor_info_list.push(next_succ_info.clone());
-            chord_util::dprint(
-                &("fill_succ_info_list_2,".to_string() 
-                + chord_util::gen_debug_str_of_node(&self_node_ref).as_str() + ","
-                + chord_util::gen_debug_str_of_node(&next_succ_info).as_str() + ","
-                + self_node_ref.successor_info_list.len().to_string().as_str()
-            ));
-        }else{
-            self_node_ref.successor_info_list[idx_counter] = next_succ_info.clone();
-            chord_util::dprint(
-                &("fill_succ_info_list_3,".to_string() 
-                + chord_util::gen_debug_str_of_node(&self_node_ref).as_str() + ","
-                + chord_util::gen_debug_str_of_node(&next_succ_info).as_str() + ","
-                + self_node_ref.successor_info_list.len().to_string().as_str() + ","
-                + idx_counter.to_string().as_str()
-            ));           
-        }
-        idx_counter += 1;
-        drop(self_node_ref);
-        next_succ_info = match endpoints::rrpc_call__get_node_info(&next_succ_info.address_str) {
-            Err(err) => {
-                self_node_ref = self_node.lock().unwrap();
-                node_info::handle_downed_node_info(&mut self_node_ref, &next_succ_info, &err);
-                // 後続を辿れないので、リスト埋めは中止してreturnする
-                return Err(err);
+        {
+            let mut self_node_ref = self_node.lock().unwrap();
+            if self_node_ref.successor_info_list.len() < (idx_counter + 1) {            
+                self_node_ref.successor_info_list.push(next_succ_info.clone());
+                chord_util::dprint(
+                    &("fill_succ_info_list_2,".to_string() 
+                    + chord_util::gen_debug_str_of_node(&self_node_ref).as_str() + ","
+                    + chord_util::gen_debug_str_of_entry(&next_succ_info).as_str() + ","
+                    + self_node_ref.successor_info_list.len().to_string().as_str()
+                ));
+            }else{
+                self_node_ref.successor_info_list[idx_counter] = next_succ_info.clone();
+                chord_util::dprint(
+                    &("fill_succ_info_list_3,".to_string() 
+                    + chord_util::gen_debug_str_of_node(&self_node_ref).as_str() + ","
+                    + chord_util::gen_debug_str_of_entry(&next_succ_info).as_str() + ","
+                    + self_node_ref.successor_info_list.len().to_string().as_str() + ","
+                    + idx_counter.to_string().as_str()
+                ));           
             }
-            Ok(ninfo) => ninfo
-        };
+            idx_counter += 1;
+        }
     }
 
     return Ok(true);
@@ -339,26 +363,29 @@ pub fn fill_succ_info_list(self_node: ArMu<node_info::NodeInfo>) -> Result<bool,
 // FingerTableに関するstabilize処理を行う
 // 一回の呼び出しで1エントリを更新する
 // FingerTableのエントリはこの呼び出しによって埋まっていく
-pub fn stabilize_finger_table(self_node: ArMu<node_info::NodeInfo>, idx: i32) -> Result<bool, chord_util::GeneralError> {
-    let mut self_node_ref = self_node.lock().unwrap();
-    let self_node_deep_cloned = node_info::partial_clone_from_ref_strong(&self_node_ref);
-    //chord_util::dprint_routing_info(self.existing_node, sys._getframe().f_code.co_name);
+pub async fn stabilize_finger_table(self_node: ArMu<node_info::NodeInfo>, idx: i32) -> Result<bool, chord_util::GeneralError> {
+    let self_node_deep_cloned;
+    let update_id;
+    {
+        let self_node_ref = self_node.lock().unwrap();
+        self_node_deep_cloned = node_info::partial_clone_from_ref_strong(&self_node_ref);
+        //chord_util::dprint_routing_info(self.existing_node, sys._getframe().f_code.co_name);
 
-    chord_util::dprint(&("stabilize_finger_table_1,".to_string() + chord_util::gen_debug_str_of_node(&self_node_ref).as_str()));
+        chord_util::dprint(&("stabilize_finger_table_1,".to_string() + chord_util::gen_debug_str_of_node(&self_node_ref).as_str()));
 
-    // FingerTableの各要素はインデックスを idx とすると 2^IDX 先のIDを担当する、もしくは
-    // 担当するノードに最も近いノードが格納される
-    let update_id = chord_util::overflow_check_and_conv((self_node_ref.node_id as u64) + (2u64.pow(idx as u32) as u64));
+        // FingerTableの各要素はインデックスを idx とすると 2^IDX 先のIDを担当する、もしくは
+        // 担当するノードに最も近いノードが格納される
+        update_id = chord_util::overflow_check_and_conv((self_node_ref.node_id as u64) + 2u64.pow(idx as u32));
+    }
 
     println!("update_id: {:?} {:?}", update_id, idx);
 
-    drop(self_node_ref);
     //let find_rslt = endpoints::rrpc_call__find_successor(&self_node_deep_cloned, update_id);
-    let find_rslt = router::find_successor(Arc::clone(&self_node), update_id);
+    let find_rslt = router::find_successor(Arc::clone(&self_node), update_id).await;
 
-    self_node_ref = self_node.lock().unwrap();
-    match find_rslt {
+    let found_node = match find_rslt {
         Err(err) => {
+            let mut self_node_ref = self_node.lock().unwrap();
             // 適切な担当ノードを得ることができなかった
             // 今回のエントリの更新はあきらめるが、例外の発生原因はおおむね見つけたノードがダウンしていた
             // ことであるので、更新対象のエントリには None を設定しておく
@@ -370,93 +397,259 @@ pub fn stabilize_finger_table(self_node: ArMu<node_info::NodeInfo>, idx: i32) ->
 
             return Ok(true);
         },
-        Ok(found_node) => {
-            self_node_ref.finger_table[(idx - 1) as usize] = Some(node_info::gen_node_info_from_summary(&found_node));
+        Ok(found_node) => found_node
+    };
 
-            // chord_util::dprint(&("stabilize_finger_table_3,".to_string() 
-            //         + chord_util::gen_debug_str_of_node(&self_node_ref).as_str() + ","
-            //         + chord_util::gen_debug_str_of_node(&found_node).as_str()));
+    // 次のエントリが担当する範囲の先頭. 最後のエントリの範囲は自身の手前まで
+    let interval_end = if idx as u32 >= gval::ID_SPACE_BITS {
+        self_node_deep_cloned.node_id
+    } else {
+        chord_util::overflow_check_and_conv((self_node_deep_cloned.node_id as u64) + 2u64.pow(idx as u32 + 1))
+    };
+    let selected_node = select_proximate_finger(&self_node_deep_cloned, &found_node, interval_end).await;
 
-            return Ok(true);
+    let mut self_node_ref = self_node.lock().unwrap();
+    self_node_ref.finger_table[(idx - 1) as usize] = Some(selected_node);
+
+    // chord_util::dprint(&("stabilize_finger_table_3,".to_string() 
+    //         + chord_util::gen_debug_str_of_node(&self_node_ref).as_str() + ","
+    //         + chord_util::gen_debug_str_of_node(&found_node).as_str()));
+
+    return Ok(true);
+}
+
+// 近接性を考慮したフィンガーの選択 (proximity neighbor selection)
+// finger_table のエントリが指すノードは、そのエントリが担当する範囲 (update_id から次のエントリの update_id の手前まで) に
+// あればどれであっても探索のホップ数は変わらない. そこで、find_successor で得た範囲の先頭のノードと、その successor の
+// うち範囲内にあるもの (gval::PNS_CANDIDATE_MAX 個まで) のRTTを計測し、最もRTTの小さいノードを選ぶ
+async fn select_proximate_finger(self_node_ni: &node_info::NodeInfo, found_node: &node_info::NodeInfoSummary, interval_end: u32) -> node_info::NodeInfoEntry {
+    let mut selected = node_info::gen_entry_from_summary(found_node);
+    if found_node.address_str == self_node_ni.address_str {
+        return selected;
+    }
+
+    let found_node_succ_list = match measure_rtt(self_node_ni, &selected).await {
+        Err(_) => { return selected; }
+        Ok((rtt_micros, succ_list)) => {
+            selected.rtt_micros = rtt_micros;
+            succ_list
         }
+    };
+    // 範囲内にノードが無く、範囲の外のノードが得られた場合は候補を探さない
+    if chord_util::exist_between_two_nodes_right_mawari(self_node_ni.node_id, interval_end, found_node.node_id) == false {
+        return selected;
+    }
+
+    let candidates: Vec<node_info::NodeInfoEntry> = found_node_succ_list.iter()
+        .take_while(|ninfo| ninfo.address_str != self_node_ni.address_str && chord_util::exist_between_two_nodes_right_mawari(found_node.node_id, interval_end, ninfo.node_id))
+        .take(gval::PNS_CANDIDATE_MAX)
+        .cloned()
+        .collect();
+    for candidate in candidates {
+        match measure_rtt(self_node_ni, &candidate).await {
+            Err(_) => { continue; }
+            Ok((rtt_micros, _)) => {
+                if rtt_micros < selected.rtt_micros {
+                    selected = candidate;
+                    selected.rtt_micros = rtt_micros;
+                }
+            }
+        };
     }
+    chord_util::dprint(&("select_proximate_finger,".to_string() + chord_util::gen_debug_str_of_node(self_node_ni).as_str() + ","
+        + chord_util::gen_debug_str_of_entry(&selected).as_str() + "," + selected.rtt_micros.to_string().as_str()));
+    return selected;
+}
+
+// get_successor_list の往復時間を計測し、以前に計測した値があれば平滑化したものと、得られた successor_info_list を返す
+async fn measure_rtt(self_node_ni: &node_info::NodeInfo, target: &node_info::NodeInfoEntry) -> Result<(u64, Vec<node_info::NodeInfoEntry>), chord_util::GeneralError> {
+    let started = Instant::now();
+    let succ_list = match endpoints::rrpc_call__get_successor_list(&target.address_str).await {
+        Err(err) => { return Err(err); }
+        Ok(succ_list) => succ_list
+    };
+    let sample = started.elapsed().as_micros() as u64;
+
+    let prev_rtt = self_node_ni.finger_table.iter()
+        .filter_map(|entry| entry.as_ref())
+        .find(|entry| entry.address_str == target.address_str && entry.rtt_micros != 0)
+        .map(|entry| entry.rtt_micros);
+    let rtt_micros = match prev_rtt {
+        None => sample,
+        Some(prev) => (prev * (gval::RTT_SMOOTHING_DIVISOR - 1) + sample) / gval::RTT_SMOOTHING_DIVISOR
+    };
+    return Ok((rtt_micros, succ_list));
+}
+
+// predecessor の生死を定期的に確認し、ダウンしていた場合は predecessor を未設定に戻す
+// (新たな predecessor は、そのノードの stabilize_successor から呼び出される check_predecessor で設定される)
+// 生存を確認できていた predecessor が、新たな predecessor との間から居なくなっており、ダウンしていることが
+// 確認できた場合は、そのノードの担当範囲を引き継いだものとして範囲内のデータのコピーを書き直す
+pub async fn check_predecessor_liveness(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::DataStore>) -> Result<bool, chord_util::GeneralError> {
+    let self_node_deep_cloned;
+    {
+        let self_node_ref = self_node.lock().unwrap();
+        self_node_deep_cloned = node_info::partial_clone_from_ref_strong(&self_node_ref);
+    }
+    let pred_info = match self_node_deep_cloned.predecessor_info.first() {
+        // 新たな predecessor の設定を待つ
+        None => { return Ok(true); }
+        Some(pred_info) => pred_info.clone()
+    };
+    if pred_info.address_str == self_node_deep_cloned.address_str {
+        return Ok(true);
+    }
+
+    match endpoints::rrpc_call__get_predecessor(&pred_info.address_str).await {
+        Err(err) => {
+            chord_util::dprint(&("check_predecessor_liveness_downed,".to_string() + chord_util::gen_debug_str_of_node(&self_node_deep_cloned).as_str() + ","
+                + chord_util::gen_debug_str_of_entry(&pred_info).as_str()));
+            let mut self_node_ref = self_node.lock().unwrap();
+            node_info::handle_downed_node_info(&mut self_node_ref, &node_info::gen_node_info_from_entry(&pred_info), &err);
+            return Err(err);
+        }
+        Ok(_) => {}
+    };
+
+    let last_live_pred = {
+        let mut last_preds_ref = LAST_LIVE_PREDECESSORS.lock().unwrap();
+        last_preds_ref.insert(self_node_deep_cloned.address_str.clone(), pred_info.clone())
+    };
+    let downed_pred = match last_live_pred {
+        None => { return Ok(true); }
+        Some(last_pred) => {
+            if last_pred.address_str == pred_info.address_str
+                || chord_util::exist_between_two_nodes_right_mawari(pred_info.node_id, self_node_deep_cloned.node_id, last_pred.node_id) == false {
+                // 変化が無いか、新たなノードが間に参加した
+                return Ok(true);
+            }
+            last_pred
+        }
+    };
+    // IDの移動 (リバランス) などで居なくなった場合は、データは移動済みのため何もしない
+    match endpoints::rrpc_call__get_predecessor(&downed_pred.address_str).await {
+        Err(err) => {}
+        Ok(_) => { return Ok(true); }
+    };
+
+    chord_util::dprint(&("check_predecessor_liveness_rereplicate,".to_string() + chord_util::gen_debug_str_of_node(&self_node_deep_cloned).as_str() + ","
+        + chord_util::gen_debug_str_of_entry(&pred_info).as_str() + "," + chord_util::gen_debug_str_of_entry(&downed_pred).as_str()));
+    chord_node::rereplicate_range(Arc::clone(&self_node), data_store,
+        chord_util::overflow_check_and_conv(pred_info.node_id as u64 + 1),
+        chord_util::overflow_check_and_conv(downed_pred.node_id as u64 + 1)).await;
+    return Ok(true);
 }
 
 // caller_node が自身の正しい predecessor でないかチェックし、そうであった場合、経路表の情報を更新する
 // 本メソッドはstabilize処理の中で用いられる
-pub fn check_predecessor(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::DataStore>, caller_node_ni: node_info::NodeInfo) -> Result<bool, chord_util::GeneralError> {
-    let mut self_node_ref = self_node.lock().unwrap();
-    let self_node_deep_cloned = node_info::partial_clone_from_ref_strong(&self_node_ref);
-    drop(self_node_ref);
+pub async fn check_predecessor(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::DataStore>, caller_node_ni: node_info::NodeInfoEntry) -> Result<bool, chord_util::GeneralError> {
+    let self_node_deep_cloned;
+    {
+        let self_node_ref = self_node.lock().unwrap();
+        self_node_deep_cloned = node_info::partial_clone_from_ref_strong(&self_node_ref);
+    }
 
     chord_util::dprint(&("check_predecessor_1,".to_string() + chord_util::gen_debug_str_of_node(&self_node_deep_cloned).as_str() + ","
-        + chord_util::gen_debug_str_of_node(&caller_node_ni).as_str() + ","
-        + chord_util::gen_debug_str_of_node(&self_node_deep_cloned.successor_info_list[0]).as_str()));
+        + chord_util::gen_debug_str_of_entry(&caller_node_ni).as_str() + ","
+        + chord_util::gen_debug_str_of_entry(&self_node_deep_cloned.successor_info_list[0]).as_str()));
 
     if self_node_deep_cloned.predecessor_info.len() == 0 {
         // predecesorが設定されていなければ無条件にチェックを求められたノードを設定する
         node_info::set_pred_info(Arc::clone(&self_node), caller_node_ni.clone());
         chord_util::dprint(&("check_predecessor_1,".to_string() + chord_util::gen_debug_str_of_node(&self_node_deep_cloned).as_str() + ","
-            + chord_util::gen_debug_str_of_node(&caller_node_ni).as_str() + ","
-            + chord_util::gen_debug_str_of_node(&self_node_deep_cloned.successor_info_list[0]).as_str()));
+            + chord_util::gen_debug_str_of_entry(&caller_node_ni).as_str() + ","
+            + chord_util::gen_debug_str_of_entry(&self_node_deep_cloned.successor_info_list[0]).as_str()));
+        // 参加直後のノードなどで predecessor が未設定であった場合も、担当範囲外のデータを委譲する
+        // 複数の仮想ノードをホストしている場合は、委譲すべき範囲の始点が分からないため行わない
+        if caller_node_ni.address_str != self_node_deep_cloned.address_str && vnode::is_multi_vnode() == false {
+            delegate_datas_to_predecessor(Arc::clone(&self_node), data_store, &caller_node_ni, chord_util::overflow_check_and_conv(self_node_deep_cloned.node_id as u64 + 1)).await;
+        }
         return Ok(true);
     }
 
     // predecessorの生死チェックを行い、ダウンしていた場合 未設定状態に戻して return する
     // (本来 check_predecessor でやる処理ではないと思われるが、finger tableの情報を用いて
     // ノードダウン時の対処を行う場合に、このコードがないとうまくいかなそうなのでここで処理)
-    match endpoints::rrpc_call__get_node_info(&self_node_deep_cloned.predecessor_info[0].address_str){
+    match endpoints::rrpc_call__get_predecessor(&self_node_deep_cloned.predecessor_info[0].address_str).await{
         Err(err) => {
-            self_node_ref = self_node.lock().unwrap();
-            node_info::handle_downed_node_info(&mut self_node_ref, &self_node_deep_cloned.predecessor_info[0], &err);
+            let mut self_node_ref = self_node.lock().unwrap();
+            node_info::handle_downed_node_info(&mut self_node_ref, &node_info::gen_node_info_from_entry(&self_node_deep_cloned.predecessor_info[0]), &err);
             return Ok(true);
         }
         Ok(some) => {}        
     }
 
     chord_util::dprint(&("check_predecessor_2,".to_string() + chord_util::gen_debug_str_of_node(&self_node_deep_cloned).as_str() + ","
-            + chord_util::gen_debug_str_of_node(&self_node_deep_cloned.successor_info_list[0]).as_str()));
+            + chord_util::gen_debug_str_of_entry(&self_node_deep_cloned.successor_info_list[0]).as_str()));
     
-    self_node_ref = self_node.lock().unwrap();
-    let distance_check = chord_util::calc_distance_between_nodes_left_mawari(self_node_ref.node_id, caller_node_ni.node_id);
-    let distance_cur = chord_util::calc_distance_between_nodes_left_mawari(self_node_ref.node_id,
-                                                                        self_node_ref.predecessor_info[0].node_id);
+    let distance_check;
+    let distance_cur;
+    {
+        let self_node_ref = self_node.lock().unwrap();
+        distance_check = chord_util::calc_distance_between_nodes_left_mawari(self_node_ref.node_id, caller_node_ni.node_id);
+        distance_cur = chord_util::calc_distance_between_nodes_left_mawari(self_node_ref.node_id,
+                                                                            self_node_ref.predecessor_info[0].node_id);
+    }
     chord_util::dprint(&("check_predecessor distance_check=".to_string() 
         + distance_check.to_string().as_str() 
         + " distance_cur=" + distance_cur.to_string().as_str())
     );
+    // 現在の predecessor と同じノードからの確認で、そのIDが後方に移動していた場合 (リバランスによる移動) は
+    // predecessor の情報のみ更新する. 前方に移動していた場合はデータの委譲が必要なため下の処理で扱う
+    if caller_node_ni.address_str == self_node_deep_cloned.predecessor_info[0].address_str
+        && caller_node_ni.node_id != self_node_deep_cloned.predecessor_info[0].node_id
+        && distance_check >= distance_cur {
+        node_info::set_pred_info(Arc::clone(&self_node), caller_node_ni.clone());
+        return Ok(true);
+    }
+
     // 確認を求められたノードの方が現在の predecessor より predecessorらしければ
     // 経路表の情報を更新する
     if distance_check < distance_cur {
-        drop(self_node_ref);
         node_info::set_pred_info(Arc::clone(&self_node), caller_node_ni.clone());
 
         // 切り替えたpredecessorに対してデータの委譲を行う
-        let self_id = self_node_deep_cloned.node_id;
-        let new_pred_id = caller_node_ni.node_id;
-        let mut data_store_ref = data_store.lock().unwrap();
-        let delegate_datas: Vec<chord_util::DataIdAndValue>;
-        delegate_datas = data_store_ref.get_and_delete_iv_with_pred_self_id(new_pred_id, self_id);
-        drop(data_store_ref);
-
-        self_node_ref = self_node.lock().unwrap();
-        chord_util::dprint(&("check_predecessor_3,".to_string() + chord_util::gen_debug_str_of_node(&self_node_ref).as_str() + ","
-                + chord_util::gen_debug_str_of_node(&self_node_ref.successor_info_list[0]).as_str() + ","
-                + chord_util::gen_debug_str_of_node(&self_node_ref.predecessor_info[0]).as_str()));
-        drop(self_node_ref);
-
-        match endpoints::rrpc_call__pass_datas(&caller_node_ni, delegate_datas){
-            Err(err) => {
-                self_node_ref = self_node.lock().unwrap();
-                node_info::handle_downed_node_info(&mut self_node_ref, &caller_node_ni, &err);
-                return Ok(true);
-            }
-            Ok(some) => { return Ok(true) }
+        // 仮想ノードを用いない場合は、新たな担当範囲 (新たな predecessor のIDより大きく自身のID以下) の外のデータを全て委譲する
+        // DataStore は同じプロセスの仮想ノードで共有しているため、複数の仮想ノードをホストしている場合は
+        // 元の predecessor から新たな predecessor までの範囲 (新たな predecessor の担当範囲) のデータのみを委譲する
+        let range_from = if vnode::is_multi_vnode() {
+            chord_util::overflow_check_and_conv(self_node_deep_cloned.predecessor_info[0].node_id as u64 + 1)
+        } else {
+            chord_util::overflow_check_and_conv(self_node_deep_cloned.node_id as u64 + 1)
+        };
+
+        {
+            let self_node_ref = self_node.lock().unwrap();
+            chord_util::dprint(&("check_predecessor_3,".to_string() + chord_util::gen_debug_str_of_node(&self_node_ref).as_str() + ","
+                    + chord_util::gen_debug_str_of_entry(&self_node_ref.successor_info_list[0]).as_str() + ","
+                    + chord_util::gen_debug_str_of_entry(&self_node_ref.predecessor_info[0]).as_str()));
         }
+
+        delegate_datas_to_predecessor(Arc::clone(&self_node), data_store, &caller_node_ni, range_from).await;
     }
     return Ok(true);
 }
 
+// [range_from, 新たな predecessor のID] の範囲のデータを新たな predecessor に委譲する
+// 送り終えていないデータは自身が保持したまま、一定間隔で転送を再開する
+async fn delegate_datas_to_predecessor(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::DataStore>, new_pred: &node_info::NodeInfoEntry, range_from: u32) {
+    let new_pred = &node_info::gen_node_info_from_entry(new_pred);
+    let range_end = chord_util::overflow_check_and_conv(new_pred.node_id as u64 + 1);
+    let handoff_mode = match config::get_replication_mode() {
+        config::ReplicationMode::SaltedId => transfer::HandoffMode::Move,
+        // 自身は新たな predecessor の successor となるので、渡したデータもレプリカとして保持し続ける
+        config::ReplicationMode::SuccessorList => transfer::HandoffMode::Demote
+    };
+
+    match transfer::transfer_range(data_store, new_pred, range_from, range_end, handoff_mode).await {
+        Err(err) => {
+            let mut self_node_ref = self_node.lock().unwrap();
+            node_info::handle_downed_node_info(&mut self_node_ref, new_pred, &err);
+        }
+        Ok(_) => {}
+    };
+}
+
 // passed_datasで渡されたデータのリストを自身のDataStoreに加える
 // 基本的に、ノード参加が判明した際に他のノードが self_node に対してデータを委譲
 // する際に利用することを想定する
@@ -0,0 +1,302 @@
+use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+use crate::gval;
+use crate::node_info;
+use crate::chord_util;
+use crate::stabilizer;
+use crate::stabilize_rate;
+use crate::data_store;
+
+type ArMu<T> = Arc<Mutex<T>>;
+
+// stabilize処理を行うバックグラウンドタスクの管理 (プロセスで1つ)
+//
+// 仮想ノードごとに stabilize_successor (successor_info_list の更新を含む)、stabilize_finger_table と
+// check_predecessor_liveness (predecessor の生死の確認) の3つのタスクを起動し、管理用APIからの開始、停止、一時停止、再開と、即時の stabilize の実行を受け付ける
+// 各タスクは処理の回数とエラーの回数、最後のエラーを記録する
+// 状態の変更は watch チャネルで各タスクに伝え、待機中のタスクもすぐに反映する
+// 停止後に再度開始した場合に停止前のタスクが動き続けないよう、開始ごとに世代番号を増やし、
+// タスクは自身の世代と異なる世代が通知された時点で終了する
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceState {
+    Running,
+    Paused,
+    Stopped
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TaskStatus {
+    // "stabilize_successor", "stabilize_finger_table", "check_predecessor_liveness" のいずれか
+    pub name: String,
+    // タスクが担当する仮想ノードのアドレス
+    pub address_str: String,
+    pub rounds: u64,
+    pub error_count: u64,
+    pub last_error: Option<chord_util::GeneralError>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServiceStatus {
+    pub state: ServiceState,
+    pub tasks: Vec<TaskStatus>
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ServiceControl {
+    state: ServiceState,
+    generation: u64
+}
+
+struct StabilizerService {
+    vnode_infos: Vec<ArMu<node_info::NodeInfo>>,
+    data_store: Option<ArMu<data_store::DataStore>>,
+    // 仮想ノードごとに、successor のタスク、フィンガーテーブルのタスク、predecessor の生死の確認のタスクの順
+    tasks: Vec<TaskStatus>,
+    handles: Vec<JoinHandle<()>>
+}
+
+const SUCCESSOR_TASK_NAME : &str = "stabilize_successor";
+const FINGER_TASK_NAME : &str = "stabilize_finger_table";
+const PRED_LIVENESS_TASK_NAME : &str = "check_predecessor_liveness";
+const TASKS_PER_VNODE : usize = 3;
+
+lazy_static! {
+    static ref STABILIZER_SERVICE : Mutex<StabilizerService> = Mutex::new(StabilizerService { vnode_infos: vec![], data_store: None, tasks: vec![], handles: vec![] });
+    static ref SERVICE_CONTROL : watch::Sender<ServiceControl> = watch::channel(ServiceControl { state: ServiceState::Stopped, generation: 0 }).0;
+}
+
+fn gen_task_status(name: &str, address_str: &str) -> TaskStatus {
+    return TaskStatus {
+        name: name.to_string(),
+        address_str: address_str.to_string(),
+        rounds: 0,
+        error_count: 0,
+        last_error: None
+    };
+}
+
+// 管理対象の仮想ノードを登録する. 参加処理を終えた後、start の前に呼び出す
+pub fn init(vnode_infos: &[ArMu<node_info::NodeInfo>], data_store: ArMu<data_store::DataStore>) {
+    let mut service_ref = STABILIZER_SERVICE.lock().unwrap();
+    service_ref.data_store = Some(data_store);
+    service_ref.vnode_infos = vec![];
+    service_ref.tasks = vec![];
+    for vnode_info in vnode_infos.iter() {
+        let address_str = vnode_info.lock().unwrap().address_str.clone();
+        service_ref.vnode_infos.push(Arc::clone(vnode_info));
+        service_ref.tasks.push(gen_task_status(SUCCESSOR_TASK_NAME, &address_str));
+        service_ref.tasks.push(gen_task_status(FINGER_TASK_NAME, &address_str));
+        service_ref.tasks.push(gen_task_status(PRED_LIVENESS_TASK_NAME, &address_str));
+    }
+}
+
+fn record_result(task_idx: usize, result: &Result<bool, chord_util::GeneralError>) {
+    let mut service_ref = STABILIZER_SERVICE.lock().unwrap();
+    let task = &mut service_ref.tasks[task_idx];
+    task.rounds += 1;
+    match result {
+        Err(err) => {
+            chord_util::dprint(&("stabilizer_service: ".to_string() + task.name.as_str() + " of " + task.address_str.as_str() + " failed, " + err.message.as_str()));
+            task.error_count += 1;
+            task.last_error = Some(err.clone());
+        }
+        Ok(_) => {}
+    };
+}
+
+async fn run_successor_round(self_node: ArMu<node_info::NodeInfo>, fill_succ_list: bool) -> Result<bool, chord_util::GeneralError> {
+    match stabilizer::stabilize_successor(Arc::clone(&self_node)).await {
+        Err(err) => { return Err(err); }
+        Ok(_) => {}
+    };
+    if fill_succ_list {
+        // successor_info_listの0番要素以降を規定数まで埋める（埋まらない場合もある）
+        match stabilizer::fill_succ_info_list(Arc::clone(&self_node)).await {
+            Err(err) => { return Err(err); }
+            Ok(_) => {}
+        };
+    }
+    return Ok(true);
+}
+
+// 停止が通知された場合は false を返す. 一時停止中は再開か停止まで待つ
+async fn wait_until_runnable(control_rx: &mut watch::Receiver<ServiceControl>, generation: u64) -> bool {
+    loop {
+        let control = *control_rx.borrow_and_update();
+        if control.generation != generation || control.state == ServiceState::Stopped {
+            return false;
+        }
+        if control.state == ServiceState::Running {
+            return true;
+        }
+        match control_rx.changed().await {
+            Err(_) => { return false; }
+            Ok(_) => {}
+        };
+    }
+}
+
+// interval_millis の経過か、状態の変更の通知まで待つ
+async fn sleep_or_control_changed(control_rx: &mut watch::Receiver<ServiceControl>, interval_millis: u64) {
+    tokio::select! {
+        _ = tokio::time::sleep(std::time::Duration::from_millis(interval_millis)) => {}
+        _ = control_rx.changed() => {}
+    };
+}
+
+async fn successor_task(self_node: ArMu<node_info::NodeInfo>, task_idx: usize, generation: u64) {
+    let address_str = self_node.lock().unwrap().address_str.clone();
+    // 実行間隔は経路表の変化の有無に応じて config で指定した範囲内で調整する
+    let mut interval = stabilize_rate::AdaptiveInterval::new(&address_str, stabilize_rate::StabilizeKind::Successor);
+    let mut control_rx = SERVICE_CONTROL.subscribe();
+    let mut counter: i32 = 0;
+    loop {
+        if wait_until_runnable(&mut control_rx, generation).await == false {
+            return;
+        }
+        counter += 1;
+        let result = run_successor_round(Arc::clone(&self_node), counter % gval::FILL_SUCC_LIST_INTERVAL_TIMES == 0).await;
+        match &result {
+            Err(err) => { stabilize_rate::notify_churn(&address_str); }
+            Ok(_) => {}
+        };
+        record_result(task_idx, &result);
+        sleep_or_control_changed(&mut control_rx, interval.next_interval_millis()).await;
+    }
+}
+
+async fn finger_task(self_node: ArMu<node_info::NodeInfo>, task_idx: usize, generation: u64) {
+    let address_str = self_node.lock().unwrap().address_str.clone();
+    let mut interval = stabilize_rate::AdaptiveInterval::new(&address_str, stabilize_rate::StabilizeKind::Finger);
+    let mut control_rx = SERVICE_CONTROL.subscribe();
+    loop {
+        for idx in 1..(gval::ID_SPACE_BITS + 1){
+            if wait_until_runnable(&mut control_rx, generation).await == false {
+                return;
+            }
+            let result = stabilizer::stabilize_finger_table(Arc::clone(&self_node), idx as i32).await;
+            record_result(task_idx, &result);
+            sleep_or_control_changed(&mut control_rx, interval.next_interval_millis()).await;
+        }
+    }
+}
+
+async fn pred_liveness_task(self_node: ArMu<node_info::NodeInfo>, data_store: ArMu<data_store::DataStore>, task_idx: usize, generation: u64) {
+    let mut control_rx = SERVICE_CONTROL.subscribe();
+    loop {
+        if wait_until_runnable(&mut control_rx, generation).await == false {
+            return;
+        }
+        let result = stabilizer::check_predecessor_liveness(Arc::clone(&self_node), Arc::clone(&data_store)).await;
+        record_result(task_idx, &result);
+        sleep_or_control_changed(&mut control_rx, gval::PREDECESSOR_LIVENESS_CHECK_INTERVAL_MILLIS).await;
+    }
+}
+
+pub fn start() -> Result<ServiceStatus, chord_util::GeneralError> {
+    let mut service_ref = STABILIZER_SERVICE.lock().unwrap();
+    let control = *SERVICE_CONTROL.borrow();
+    if control.state != ServiceState::Stopped {
+        return Err(chord_util::GeneralError::new("stabilizer is already started".to_string(), chord_util::ERR_CODE_INVALID_STABILIZER_STATE));
+    }
+    let generation = control.generation + 1;
+
+    // 停止済みのタスクのハンドルは破棄する (終了処理中のものは世代が異なるため間もなく終了する)
+    service_ref.handles = vec![];
+    let vnode_infos: Vec<ArMu<node_info::NodeInfo>> = service_ref.vnode_infos.clone();
+    let data_store = match &service_ref.data_store {
+        None => { return Err(chord_util::GeneralError::new("stabilizer is not initialized".to_string(), chord_util::ERR_CODE_INVALID_STABILIZER_STATE)); }
+        Some(data_store) => Arc::clone(data_store)
+    };
+    SERVICE_CONTROL.send_replace(ServiceControl { state: ServiceState::Running, generation: generation });
+    for (vnode_idx, vnode_info) in vnode_infos.iter().enumerate() {
+        let task_idx_base = vnode_idx * TASKS_PER_VNODE;
+        let succ_handle = tokio::spawn(successor_task(Arc::clone(vnode_info), task_idx_base, generation));
+        let ftable_handle = tokio::spawn(finger_task(Arc::clone(vnode_info), task_idx_base + 1, generation));
+        let pred_liveness_handle = tokio::spawn(pred_liveness_task(Arc::clone(vnode_info), Arc::clone(&data_store), task_idx_base + 2, generation));
+        service_ref.handles.push(succ_handle);
+        service_ref.handles.push(ftable_handle);
+        service_ref.handles.push(pred_liveness_handle);
+    }
+    drop(service_ref);
+    chord_util::dprint(&("stabilizer_service: started, generation=".to_string() + generation.to_string().as_str()));
+    return Ok(get_status());
+}
+
+// 各タスクの実行中の処理が終わるのを待ってから返る
+pub async fn stop() -> Result<ServiceStatus, chord_util::GeneralError> {
+    let control = *SERVICE_CONTROL.borrow();
+    if control.state == ServiceState::Stopped {
+        return Err(chord_util::GeneralError::new("stabilizer is not started".to_string(), chord_util::ERR_CODE_INVALID_STABILIZER_STATE));
+    }
+    SERVICE_CONTROL.send_replace(ServiceControl { state: ServiceState::Stopped, generation: control.generation });
+
+    let handles: Vec<JoinHandle<()>> = {
+        let mut service_ref = STABILIZER_SERVICE.lock().unwrap();
+        service_ref.handles.drain(..).collect()
+    };
+    for handle in handles {
+        handle.await;
+    }
+    chord_util::dprint(&("stabilizer_service: stopped".to_string()));
+    return Ok(get_status());
+}
+
+pub fn pause() -> Result<ServiceStatus, chord_util::GeneralError> {
+    let control = *SERVICE_CONTROL.borrow();
+    if control.state != ServiceState::Running {
+        return Err(chord_util::GeneralError::new("stabilizer is not running".to_string(), chord_util::ERR_CODE_INVALID_STABILIZER_STATE));
+    }
+    SERVICE_CONTROL.send_replace(ServiceControl { state: ServiceState::Paused, generation: control.generation });
+    chord_util::dprint(&("stabilizer_service: paused".to_string()));
+    return Ok(get_status());
+}
+
+pub fn resume() -> Result<ServiceStatus, chord_util::GeneralError> {
+    let control = *SERVICE_CONTROL.borrow();
+    if control.state != ServiceState::Paused {
+        return Err(chord_util::GeneralError::new("stabilizer is not paused".to_string(), chord_util::ERR_CODE_INVALID_STABILIZER_STATE));
+    }
+    SERVICE_CONTROL.send_replace(ServiceControl { state: ServiceState::Running, generation: control.generation });
+    chord_util::dprint(&("stabilizer_service: resumed".to_string()));
+    return Ok(get_status());
+}
+
+// 全仮想ノードについて、stabilize_successor, fill_succ_info_list、フィンガーテーブルの全エントリの更新と
+// predecessor の生死の確認をその場で1回ずつ行う. サービスの状態によらず実行し、結果は各タスクの記録に加える
+pub async fn run_round_now() -> ServiceStatus {
+    let (vnode_infos, data_store) = {
+        let service_ref = STABILIZER_SERVICE.lock().unwrap();
+        (service_ref.vnode_infos.clone(), service_ref.data_store.clone())
+    };
+    for (vnode_idx, vnode_info) in vnode_infos.iter().enumerate() {
+        let task_idx_base = vnode_idx * TASKS_PER_VNODE;
+        let result = run_successor_round(Arc::clone(vnode_info), true).await;
+        record_result(task_idx_base, &result);
+        for idx in 1..(gval::ID_SPACE_BITS + 1){
+            let result = stabilizer::stabilize_finger_table(Arc::clone(vnode_info), idx as i32).await;
+            record_result(task_idx_base + 1, &result);
+        }
+        match &data_store {
+            None => {}
+            Some(data_store) => {
+                let result = stabilizer::check_predecessor_liveness(Arc::clone(vnode_info), Arc::clone(data_store)).await;
+                record_result(task_idx_base + 2, &result);
+            }
+        };
+    }
+    return get_status();
+}
+
+pub fn get_status() -> ServiceStatus {
+    let service_ref = STABILIZER_SERVICE.lock().unwrap();
+    return ServiceStatus {
+        state: SERVICE_CONTROL.borrow().state,
+        tasks: service_ref.tasks.clone()
+    };
+}
@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::io::ErrorKind;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use serde::{Serialize, Deserialize};
+
+use crate::gval;
+use crate::node_info;
+use crate::chord_util;
+use crate::data_store;
+use crate::endpoints;
+use crate::config;
+use crate::auth;
+use crate::vnode;
+
+type ArMu<T> = Arc<Mutex<T>>;
+
+// ノード間RPC用のバイナリプロトコル
+// フレームは 4byte(ビッグエンディアン)のペイロード長 + bincodeでシリアライズしたペイロード で構成する
+// 1つのTCPコネクション上で リクエスト -> レスポンス のやり取りを繰り返し行い、コネクションは使いまわす
+// HTTP(JSON)のAPIはクライアント向けにそのまま残し、ノード間のRPCはピアごとのネゴシエーションで
+// 双方がバイナリプロトコルに対応している場合にのみこちらを用いる
+
+// 2: リクエストにクラスタトークンを含めるようにした
+// 3: put の引数と保持するデータにネームスペース、有効期限、レプリカの番号を含めるようにした
+// 4: put の引数と保持するデータにレプリケーションファクタを含めるようにした
+// 5: NodeInfo, NodeInfoSummary にゾーンを含めるようにした
+// 6: リクエストに宛先の仮想ノードの番号を含めるようにした
+// 7: NodeInfo にRPCの往復時間を含めるようにした
+// 8: エラーに担当ノードの推測を含めるようにした
+// 9: 経路表のエントリを NodeInfo から NodeInfoEntry に変更し、get_successor, get_predecessor, get_successor_list を追加した
+// 10: probe_peer を追加した
+// 11: 再帰モードの名前解決の引数に残り時間を含めるようにした
+// 12: reindex_replicas を追加した
+// 13: ネームスペースを指定できなかったクライアント向けAPI (global_put, global_get, global_delete) を削除した
+pub const BINARY_RPC_PROTOCOL_VERSION : u32 = 13;
+
+// バイナリプロトコルのリスナのポート番号. 無効の場合は -1
+static BINARY_RPC_PORT : AtomicI32 = AtomicI32::new(-1);
+
+// ピアごとのネゴシエーション結果
+#[derive(Debug, Clone)]
+pub enum PeerRpcProtocol {
+    Http,
+    // 値はバイナリプロトコルのリスナのアドレス ("IPアドレス:ポート番号")
+    Binary(String)
+}
+
+// ネゴシエーション時に /get_rpc_protocol_info で返す情報
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct RpcProtocolInfo {
+    pub binary_rpc_version: u32,
+    // バイナリプロトコルに対応していない場合は -1
+    pub binary_rpc_port: i32
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+struct RpcRequestFrame {
+    method: String,
+    // 設定されていない場合は空文字列
+    cluster_token: String,
+    // 宛先の仮想ノードの番号
+    vnode: u32,
+    payload: Vec<u8>
+}
+
+lazy_static! {
+    // キーはピアの address_str
+    static ref PEER_PROTOCOLS : Mutex<HashMap<String, PeerRpcProtocol>> = Mutex::new(HashMap::new());
+    // キーはバイナリプロトコルのリスナのアドレス. 空いているコネクションを保持する
+    static ref IDLE_CONNS : Mutex<HashMap<String, Vec<TcpStream>>> = Mutex::new(HashMap::new());
+}
+
+// バイナリプロトコルは暗号化を行わないため、TLSが有効な場合は用いずにHTTPS でやり取りする
+pub fn is_binary_rpc_enabled() -> bool {
+    return gval::ENABLE_BINARY_RPC && config::is_tls_enabled() == false;
+}
+
+pub fn get_rpc_protocol_info() -> RpcProtocolInfo {
+    return RpcProtocolInfo { binary_rpc_version: BINARY_RPC_PROTOCOL_VERSION, binary_rpc_port: BINARY_RPC_PORT.load(Ordering::SeqCst) };
+}
+
+pub fn get_cached_peer_protocol(address: &String) -> Option<PeerRpcProtocol> {
+    let protocols_ref = PEER_PROTOCOLS.lock().unwrap();
+    return protocols_ref.get(address).cloned();
+}
+
+// ピアから得た RpcProtocolInfo を元に利用するプロトコルを決定し、キャッシュする
+pub fn set_peer_protocol_from_info(address: &str, info: &RpcProtocolInfo) -> PeerRpcProtocol {
+    let proto = if is_binary_rpc_enabled()
+                    && info.binary_rpc_port >= 0
+                    && info.binary_rpc_version == BINARY_RPC_PROTOCOL_VERSION {
+        PeerRpcProtocol::Binary(gen_binary_rpc_addr(address, info.binary_rpc_port))
+    } else {
+        PeerRpcProtocol::Http
+    };
+    let mut protocols_ref = PEER_PROTOCOLS.lock().unwrap();
+    protocols_ref.insert(address.to_string(), proto.clone());
+    return proto;
+}
+
+// ネゴシエーション結果を破棄し、次回の呼び出し時に再度ネゴシエーションが行われるようにする
+// 保持しているコネクションも閉じる
+pub fn forget_peer(address: &String) {
+    let mut protocols_ref = PEER_PROTOCOLS.lock().unwrap();
+    let removed = protocols_ref.remove(address);
+    drop(protocols_ref);
+    if let Some(PeerRpcProtocol::Binary(bin_addr)) = removed {
+        let mut conns_ref = IDLE_CONNS.lock().unwrap();
+        conns_ref.remove(&bin_addr);
+    }
+}
+
+// "IPアドレス:ポート番号" 形式の address_str のポート番号部分を置き換えたものを返す
+fn gen_binary_rpc_addr(address: &str, port: i32) -> String {
+    let host = match address.rfind(':') {
+        None => address,
+        Some(pos) => &address[..pos]
+    };
+    return host.to_string() + ":" + &port.to_string();
+}
+
+async fn write_frame(stream: &mut TcpStream, payload: &[u8]) -> std::io::Result<()> {
+    let len_bytes = (payload.len() as u32).to_be_bytes();
+    stream.write_all(&len_bytes).await?;
+    stream.write_all(payload).await?;
+    stream.flush().await?;
+    return Ok(());
+}
+
+// 相手がコネクションを閉じていた場合（フレームの先頭でEOF）は None を返す
+async fn read_frame(stream: &mut TcpStream) -> std::io::Result<Option<Vec<u8>>> {
+    let mut len_bytes = [0u8; 4];
+    let mut read_len = 0;
+    while read_len < len_bytes.len() {
+        let n = stream.read(&mut len_bytes[read_len..]).await?;
+        if n == 0 {
+            if read_len == 0 {
+                return Ok(None);
+            }
+            return Err(std::io::Error::new(ErrorKind::UnexpectedEof, "connection closed in frame header"));
+        }
+        read_len += n;
+    }
+
+    let payload_len = u32::from_be_bytes(len_bytes);
+    if payload_len > gval::BINARY_RPC_MAX_FRAME_LEN {
+        return Err(std::io::Error::new(ErrorKind::InvalidData, "frame is too large"));
+    }
+
+    let mut payload = vec![0u8; payload_len as usize];
+    stream.read_exact(&mut payload).await?;
+    return Ok(Some(payload));
+}
+
+async fn connect(bin_addr: &str, timeout: &config::RpcTimeout) -> Result<TcpStream, chord_util::GeneralError> {
+    let stream = match tokio::time::timeout(Duration::from_millis(timeout.connect_millis), TcpStream::connect(bin_addr)).await {
+        Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)); }
+        Ok(Err(err)) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)); }
+        Ok(Ok(stream)) => stream
+    };
+    stream.set_nodelay(true);
+
+    return Ok(stream);
+}
+
+// 1回分の リクエスト -> レスポンス のやり取りを行う
+// 戻り値の Ok(None) は、使いまわしたコネクションが相手側で既に閉じられていたことを示す
+// タイムアウトはRPCの種類ごとに、やり取り全体に対して設定する
+async fn request_on_stream(stream: &mut TcpStream, req_bytes: &[u8], timeout: &config::RpcTimeout) -> std::io::Result<Option<Vec<u8>>> {
+    let exchange = async {
+        write_frame(stream, req_bytes).await?;
+        return read_frame(stream).await;
+    };
+    return match tokio::time::timeout(Duration::from_millis(timeout.request_millis), exchange).await {
+        Err(_) => Err(std::io::Error::new(ErrorKind::TimedOut, "binary rpc timed out")),
+        Ok(result) => result
+    };
+}
+
+// bin_addr のリスナのプロセスがホストする vnode_idx 番の仮想ノードに対してRPCを発行し、レスポンスのペイロードを返す
+pub async fn call(bin_addr: &String, vnode_idx: u32, method: &str, payload: Vec<u8>, timeout: &config::RpcTimeout) -> Result<Vec<u8>, chord_util::GeneralError> {
+    let req_bytes = match bincode::serialize(&RpcRequestFrame { method: method.to_string(), cluster_token: config::get_cluster_token().unwrap_or_default(), vnode: vnode_idx, payload: payload }) {
+        Err(err) => { return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)); }
+        Ok(bytes) => bytes
+    };
+
+    let idle_stream;
+    {
+        let mut conns_ref = IDLE_CONNS.lock().unwrap();
+        idle_stream = match conns_ref.get_mut(bin_addr) {
+            None => None,
+            Some(streams) => streams.pop()
+        };
+    }
+
+    let mut resp_frame: Option<Vec<u8>> = None;
+    let mut stream = match idle_stream {
+        None => None,
+        Some(mut stream) => {
+            // 使いまわしたコネクションが閉じられていた場合は新しいコネクションでやり直す
+            // レスポンスのフレームの先頭でEOFとなった場合、相手はリクエストを処理していないため再送して問題ない
+            // それ以外のエラー (タイムアウトやレスポンスの途中での切断) では相手が処理済みの可能性があるため、再送せずにエラーとする
+            match request_on_stream(&mut stream, &req_bytes, timeout).await {
+                Ok(Some(frame)) => {
+                    resp_frame = Some(frame);
+                    Some(stream)
+                }
+                Ok(None) => None,
+                Err(err) => {
+                    chord_util::dprint(&("ERROR at tcp_rpc::call(0),".to_string() + bin_addr + "," + method));
+                    return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR));
+                }
+            }
+        }
+    };
+
+    if resp_frame.is_none() {
+        let mut new_stream = match connect(bin_addr, timeout).await {
+            Err(err) => {
+                chord_util::dprint(&("ERROR at tcp_rpc::call(1),".to_string() + bin_addr + "," + method));
+                return Err(err);
+            }
+            Ok(stream) => stream
+        };
+        match request_on_stream(&mut new_stream, &req_bytes, timeout).await {
+            Err(err) => {
+                chord_util::dprint(&("ERROR at tcp_rpc::call(2),".to_string() + bin_addr + "," + method));
+                return Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR));
+            }
+            Ok(None) => {
+                chord_util::dprint(&("ERROR at tcp_rpc::call(3),".to_string() + bin_addr + "," + method));
+                return Err(chord_util::GeneralError::new("connection closed by peer".to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR));
+            }
+            Ok(Some(frame)) => {
+                resp_frame = Some(frame);
+            }
+        };
+        stream = Some(new_stream);
+    }
+
+    // レスポンスを受け取れたコネクションは次回以降も使いまわす
+    if let Some(stream) = stream {
+        let mut conns_ref = IDLE_CONNS.lock().unwrap();
+        let streams = conns_ref.entry(bin_addr.clone()).or_insert(vec![]);
+        if streams.len() < gval::BINARY_RPC_MAX_IDLE_CONNS_PER_PEER {
+            streams.push(stream);
+        }
+    }
+
+    return match bincode::deserialize::<Result<Vec<u8>, chord_util::GeneralError>>(&resp_frame.unwrap()) {
+        Err(err) => Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)),
+        Ok(result) => result
+    };
+}
+
+// 1コネクションを担当するタスクで実行される
+// 相手がコネクションを閉じるまでリクエストを処理し続ける
+async fn handle_connection(data_store: ArMu<data_store::DataStore>, mut stream: TcpStream) {
+    stream.set_nodelay(true);
+    loop {
+        let req_bytes = match read_frame(&mut stream).await {
+            Err(err) => {
+                chord_util::dprint(&("ERROR at tcp_rpc::handle_connection(1),".to_string() + err.to_string().as_str()));
+                return;
+            }
+            Ok(None) => { return; }
+            Ok(Some(bytes)) => bytes
+        };
+
+        let result = match bincode::deserialize::<RpcRequestFrame>(&req_bytes) {
+            Err(err) => Err(chord_util::GeneralError::new(err.to_string(), chord_util::ERR_CODE_HTTP_REQUEST_ERR)),
+            Ok(frame) => {
+                if auth::is_valid_cluster_token(frame.cluster_token.as_str()) == false {
+                    chord_util::dprint(&("ERROR at tcp_rpc::handle_connection,invalid cluster token,".to_string() + frame.method.as_str()));
+                    Err(chord_util::GeneralError::new("caller is not a cluster member".to_string(), chord_util::ERR_CODE_UNAUTHORIZED))
+                } else {
+                    match vnode::get(frame.vnode) {
+                        None => Err(chord_util::GeneralError::new("unknown vnode: ".to_string() + frame.vnode.to_string().as_str(), chord_util::ERR_CODE_INVALID_ARGUMENT)),
+                        Some(self_node) => endpoints::dispatch_binary_rpc(self_node, Arc::clone(&data_store), frame.method.as_str(), &frame.payload).await
+                    }
+                }
+            }
+        };
+
+        let resp_bytes = match bincode::serialize(&result) {
+            Err(err) => {
+                chord_util::dprint(&("ERROR at tcp_rpc::handle_connection(2),".to_string() + err.to_string().as_str()));
+                return;
+            }
+            Ok(bytes) => bytes
+        };
+
+        match write_frame(&mut stream, &resp_bytes).await {
+            Err(err) => {
+                chord_util::dprint(&("ERROR at tcp_rpc::handle_connection(3),".to_string() + err.to_string().as_str()));
+                return;
+            }
+            Ok(_) => {}
+        };
+    }
+}
+
+// リクエストは vnode モジュールに登録された仮想ノードのうち、フレームで指定されたものが処理する
+pub async fn binary_rpc_server_start(data_store: ArMu<data_store::DataStore>, bind_addr: String, bind_port_num: i32){
+    let listener = match TcpListener::bind(bind_addr + ":" + &bind_port_num.to_string()).await {
+        Err(err) => {
+            // バイナリプロトコルが使えなくてもHTTPでのやり取りは可能なので、ノードは動作を継続する
+            chord_util::dprint(&("ERROR at binary_rpc_server_start,".to_string() + err.to_string().as_str()));
+            return;
+        }
+        Ok(listener) => listener
+    };
+    BINARY_RPC_PORT.store(bind_port_num, Ordering::SeqCst);
+
+    loop {
+        match listener.accept().await {
+            Err(err) => {
+                chord_util::dprint(&("ERROR at binary_rpc_server_start(accept),".to_string() + err.to_string().as_str()));
+                continue;
+            }
+            Ok((stream, _)) => {
+                let data_store_conn = Arc::clone(&data_store);
+                tokio::spawn(async move {
+                    handle_connection(data_store_conn, stream).await;
+                });
+            }
+        };
+    }
+}
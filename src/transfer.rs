@@ -0,0 +1,362 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
+
+use crate::gval;
+use crate::node_info;
+use crate::chord_util;
+use crate::data_store;
+use crate::endpoints;
+use crate::vnode;
+use crate::router;
+use crate::config;
+
+type ArMu<T> = Arc<Mutex<T>>;
+
+// ノードの参加や担当範囲の移動に伴う、範囲単位のデータの受け渡し
+//
+// 範囲内のデータは gval::DATA_TRANSFER_CHUNK_LEN 件ずつのチャンクに分けて送り、受け手が格納を確認 (ack) した
+// チャンクの分だけを送り手のDataStoreから削除 (または降格) する. 途中で失敗した場合は、残りのデータを
+// 送り手が保持したまま未完了の転送として記録しておき、一定間隔で再開する. 再開時は範囲内に残っている
+// データを送り直すのみで良いため、送り手が停止しない限りデータは失われない
+// 再開時は範囲の担当ノードを改めて名前解決し、その時点の担当ノードに送る
+// 受け手は転送ごとに次に受け取るべきチャンクの通し番号を記録し、欠落したチャンクの後のものは受け付けない
+// 既に受け取ったチャンクの再送は格納せずに ack を返すため、チャンクは再送してよい
+// 受け手が既にマスターデータ相当のものを保持しているデータは、受け手への書き込みの方が新しいため上書きしない
+
+// 送り終えたデータの送り手での扱い
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HandoffMode {
+    // 削除する
+    Move,
+    // successor_list モードのレプリケーション用. レプリカ (replica_idx が 1) として保持し続ける
+    // 送るのはマスターデータ相当のもののみとする
+    // 受け手の successor_info_list の2番目以降のノードが持つレプリカは、受け手が担当ノードとなったことで1つずつ後ろの位置となるため、
+    // reindex_replicas で位置を付け直し、レプリケーションファクタ以上の位置となったものは削除させる
+    Demote
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct DataChunk {
+    pub transfer_id: String,
+    // 転送内でのチャンクの通し番号. 再開した場合は 0 から振り直す
+    pub seq: u32,
+    pub datas: Vec<chord_util::DataIdAndValue>
+}
+
+// reindex_replicas の引数. data_ids のデータのレプリカの位置を replica_idx に付け直す
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct ReindexReplicas {
+    pub data_ids: Vec<u32>,
+    pub replica_idx: u32
+}
+
+// 途中で失敗した転送. [range_from, range_end) の範囲に残っているデータを receiver に送り直す
+#[derive(Debug, Clone)]
+struct PendingTransfer {
+    transfer_id: String,
+    receiver: node_info::NodeInfo,
+    range_from: u32,
+    range_end: u32,
+    mode: HandoffMode,
+    attempts: u32
+}
+
+// 担当ノードが変わった範囲. 受け渡しの途中は送り手にもデータが残っているため、担当範囲のチェックの猶予に用いる
+#[derive(Debug, Clone)]
+struct Handover {
+    receiver_address: String,
+    range_from: u32,
+    range_end: u32,
+    handed_over_at: Instant
+}
+
+static TRANSFER_ID_COUNTER : AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    static ref PENDING_TRANSFERS : Mutex<Vec<PendingTransfer>> = Mutex::new(vec![]);
+    static ref RECENT_HANDOVERS : Mutex<Vec<Handover>> = Mutex::new(vec![]);
+    // 受け手側の記録. キーは transfer_id
+    static ref RECEIVING_TRANSFERS : Mutex<HashMap<String, ReceivingTransfer>> = Mutex::new(HashMap::new());
+}
+
+// 受け手側で記録する、受信中の転送の状態
+struct ReceivingTransfer {
+    // 次に受け取るべきチャンクの通し番号
+    next_seq: u32,
+    last_received_at: Instant
+}
+
+fn record_handover(receiver: &node_info::NodeInfo, range_from: u32, range_end: u32) {
+    let grace = Duration::from_millis(gval::OWNERSHIP_GRACE_MILLIS);
+    let mut handovers_ref = RECENT_HANDOVERS.lock().unwrap();
+    handovers_ref.retain(|handover| handover.handed_over_at.elapsed() <= grace);
+    handovers_ref.push(Handover { receiver_address: receiver.address_str.clone(), range_from: range_from, range_end: range_end, handed_over_at: Instant::now() });
+}
+
+// id を含む範囲を gval::OWNERSHIP_GRACE_MILLIS 以内に他のノードへ渡していれば、受け手のアドレスを返す
+pub fn find_recent_handover(id: u32) -> Option<String> {
+    let grace = Duration::from_millis(gval::OWNERSHIP_GRACE_MILLIS);
+    let handovers_ref = RECENT_HANDOVERS.lock().unwrap();
+    return handovers_ref.iter().rev()
+        .find(|handover| handover.handed_over_at.elapsed() <= grace
+            && (id == handover.range_from || chord_util::exist_between_two_nodes_right_mawari(handover.range_from, handover.range_end, id)))
+        .map(|handover| handover.receiver_address.clone());
+}
+
+fn gen_transfer_id() -> String {
+    return format!("{:x}-{}", chord_util::get_unixtime_in_millis(), TRANSFER_ID_COUNTER.fetch_add(1, Ordering::SeqCst));
+}
+
+// [range_from, range_end) の範囲のデータを receiver に送る. 送ったデータの数を返す
+// 失敗した場合は未完了の転送として記録し、resume_pending_transfers で再開する
+pub async fn transfer_range(data_store: ArMu<data_store::DataStore>, receiver: &node_info::NodeInfo, range_from: u32, range_end: u32, mode: HandoffMode) -> Result<u64, chord_util::GeneralError> {
+    // 同じプロセスの仮想ノードとは DataStore を共有しているため、受け渡しは不要
+    if vnode::is_local_address(&receiver.address_str) {
+        return Ok(0);
+    }
+    record_handover(receiver, range_from, range_end);
+    let transfer_id = gen_transfer_id();
+    return match send_range(Arc::clone(&data_store), &transfer_id, receiver, range_from, range_end, mode).await {
+        Err(err) => {
+            chord_util::dprint(&("ERROR at transfer_range,".to_string() + transfer_id.as_str() + "," + receiver.address_str.as_str() + "," + err.message.as_str()));
+            let mut pendings_ref = PENDING_TRANSFERS.lock().unwrap();
+            pendings_ref.push(PendingTransfer { transfer_id: transfer_id, receiver: receiver.clone(), range_from: range_from, range_end: range_end, mode: mode, attempts: 0 });
+            Err(err)
+        }
+        Ok(sent_count) => Ok(sent_count)
+    };
+}
+
+async fn send_range(data_store: ArMu<data_store::DataStore>, transfer_id: &str, receiver: &node_info::NodeInfo, range_from: u32, range_end: u32, mode: HandoffMode) -> Result<u64, chord_util::GeneralError> {
+    let datas;
+    {
+        let data_store_ref = data_store.lock().unwrap();
+        datas = data_store_ref.get_datas_in_range(range_from, range_end, mode == HandoffMode::Demote);
+    }
+
+    // 降格後にレプリカの位置を付け直すノード. 受け手の successor (降格する自ノード) の successor_info_list のノードとなる
+    // (参加したばかりの受け手の successor_info_list は successor 以外がまだ埋まっていないため、successor のものを用いる)
+    let mut reindex_nodes: Vec<node_info::NodeInfoEntry> = vec![];
+    if mode == HandoffMode::Demote && datas.len() > 0 {
+        reindex_nodes = match collect_reindex_nodes(receiver).await {
+            Err(err) => { return Err(err); }
+            Ok(nodes) => nodes
+        };
+    }
+
+    let mut sent_count: u64 = 0;
+    for (seq, chunk_datas) in datas.chunks(gval::DATA_TRANSFER_CHUNK_LEN).enumerate() {
+        let chunk = DataChunk { transfer_id: transfer_id.to_string(), seq: seq as u32, datas: chunk_datas.to_vec() };
+        match endpoints::rrpc_call__receive_data_chunk(receiver, &chunk).await {
+            Err(err) => { return Err(err); }
+            Ok(acked_seq) => {
+                if acked_seq != chunk.seq {
+                    return Err(chord_util::GeneralError::new("unexpected ack of data chunk: ".to_string() + acked_seq.to_string().as_str(), chord_util::ERR_CODE_INTERNAL_CONTROL_FLOW_PROBLEM));
+                }
+            }
+        };
+
+        // 受け手が格納を確認したデータのみ手放す
+        let data_ids: Vec<u32> = chunk.datas.iter().map(|iv| iv.data_id).collect();
+        {
+            let mut data_store_ref = data_store.lock().unwrap();
+            match mode {
+                HandoffMode::Move => data_store_ref.remove_datas(&data_ids),
+                HandoffMode::Demote => data_store_ref.demote_datas(&data_ids)
+            };
+        }
+        if mode == HandoffMode::Demote {
+            reindex_demoted_replicas(&reindex_nodes, &data_ids).await;
+        }
+        sent_count += data_ids.len() as u64;
+    }
+    return Ok(sent_count);
+}
+
+async fn collect_reindex_nodes(receiver: &node_info::NodeInfo) -> Result<Vec<node_info::NodeInfoEntry>, chord_util::GeneralError> {
+    let receiver_succ = match endpoints::rrpc_call__get_successor(&receiver.address_str).await {
+        Err(err) => { return Err(err); }
+        Ok(succ) => succ
+    };
+    return match endpoints::rrpc_call__get_successor_list(&receiver_succ.address_str).await {
+        Err(err) => Err(err),
+        Ok(succ_list) => Ok(succ_list.into_iter()
+            .filter(|entry| entry.address_str != receiver.address_str)
+            .take(config::get_max_replication_factor() as usize - 1)
+            .collect())
+    };
+}
+
+// 降格したデータについて、reindex_nodes の各ノードにレプリカの位置を付け直させる
+// reindex_nodes の先頭のノードは受け手から数えて3番目 (位置 2) となる
+// 失敗したノードは、後の stabilize 処理やデータの書き込みで位置が正されるまで古い位置のまま残るのみのため、エラーとはしない
+async fn reindex_demoted_replicas(reindex_nodes: &[node_info::NodeInfoEntry], data_ids: &[u32]) {
+    for (idx, node) in reindex_nodes.iter().enumerate() {
+        if vnode::is_local_address(&node.address_str) {
+            continue;
+        }
+        let arg = ReindexReplicas { data_ids: data_ids.to_vec(), replica_idx: idx as u32 + 2 };
+        match endpoints::rrpc_call__reindex_replicas(&node.address_str, &arg).await {
+            Err(err) => {
+                chord_util::dprint(&("ERROR at reindex_demoted_replicas,".to_string() + node.address_str.as_str() + "," + err.message.as_str()));
+            }
+            Ok(_) => {}
+        };
+    }
+}
+
+// reindex_replicas を受けたノードでの処理. 付け直した数と削除した数の合計を返す
+pub fn reindex_replicas(data_store: ArMu<data_store::DataStore>, arg: ReindexReplicas) -> Result<u32, chord_util::GeneralError> {
+    let mut data_store_ref = data_store.lock().unwrap();
+    return Ok(data_store_ref.reindex_replicas(&arg.data_ids, arg.replica_idx));
+}
+
+// 未完了の転送を再開する. gval::DATA_TRANSFER_RESUME_MAX 回失敗した転送は諦め、残りのデータは自ノードに保持したままとする
+pub async fn resume_pending_transfers(data_store: ArMu<data_store::DataStore>) {
+    let pendings: Vec<PendingTransfer>;
+    {
+        let mut pendings_ref = PENDING_TRANSFERS.lock().unwrap();
+        pendings = pendings_ref.drain(..).collect();
+    }
+
+    for mut pending in pendings {
+        match resume_transfer(Arc::clone(&data_store), &mut pending).await {
+            Err(err) => {
+                pending.attempts += 1;
+                chord_util::dprint(&("ERROR at resume_pending_transfers,".to_string() + pending.transfer_id.as_str() + "," + pending.receiver.address_str.as_str() + ","
+                    + pending.attempts.to_string().as_str() + "," + err.message.as_str()));
+                if pending.attempts < gval::DATA_TRANSFER_RESUME_MAX {
+                    let mut pendings_ref = PENDING_TRANSFERS.lock().unwrap();
+                    pendings_ref.push(pending);
+                }
+            }
+            Ok(sent_count) => {
+                chord_util::dprint(&("resume_pending_transfers_done,".to_string() + pending.transfer_id.as_str() + "," + sent_count.to_string().as_str()));
+            }
+        };
+    }
+}
+
+// 未完了の転送の範囲を、先頭から現在の担当ノードごとに区切って送り直す
+// 転送の失敗後にノードの参加や離脱があった場合、元の受け手が範囲の担当ではなくなっていることがあるため、区切りごとに名前解決し直す
+// 自プロセスが担当となった区切りのデータはそのまま保持する
+// 失敗した場合は pending の範囲を送り終えていない部分に縮めてエラーを返す
+async fn resume_transfer(data_store: ArMu<data_store::DataStore>, pending: &mut PendingTransfer) -> Result<u64, chord_util::GeneralError> {
+    let resolver = match vnode::get(0) {
+        None => { return Err(chord_util::GeneralError::new("local node is not initialized".to_string(), chord_util::ERR_CODE_INTERNAL_CONTROL_FLOW_PROBLEM)); }
+        Some(node) => node
+    };
+
+    let mut sent_count: u64 = 0;
+    let mut walked: usize = 0;
+    while pending.range_from != pending.range_end {
+        walked += 1;
+        if walked > gval::RING_WALK_MAX_NODES {
+            return Err(chord_util::GeneralError::new("range owners did not converge".to_string(), chord_util::ERR_CODE_INTERNAL_CONTROL_FLOW_PROBLEM));
+        }
+        let owner = match router::find_successor(Arc::clone(&resolver), pending.range_from).await {
+            Err(err) => { return Err(err); }
+            Ok(owner) => owner
+        };
+        // 担当ノードのIDが範囲内にあれば、そのIDまでが担当ノードの範囲となる
+        let sub_end = if chord_util::exist_between_two_nodes_right_mawari(pending.range_from, pending.range_end, owner.node_id) {
+            chord_util::overflow_check_and_conv(owner.node_id as u64 + 1)
+        } else {
+            pending.range_end
+        };
+
+        if vnode::is_local_address(&owner.address_str) == false {
+            let receiver = node_info::gen_node_info_from_summary(&owner);
+            if receiver.address_str != pending.receiver.address_str {
+                chord_util::dprint(&("resume_transfer_receiver_changed,".to_string() + pending.transfer_id.as_str() + "," + pending.receiver.address_str.as_str() + ","
+                    + receiver.address_str.as_str()));
+            }
+            record_handover(&receiver, pending.range_from, sub_end);
+            pending.receiver = receiver.clone();
+            match send_range(Arc::clone(&data_store), &pending.transfer_id, &receiver, pending.range_from, sub_end, pending.mode).await {
+                Err(err) => { return Err(err); }
+                Ok(count) => { sent_count += count; }
+            };
+        }
+        pending.range_from = sub_end;
+    }
+    return Ok(sent_count);
+}
+
+// 受け取ったチャンクのデータを格納し、ack としてチャンクの通し番号を返す
+// 通し番号が 0 のチャンクは転送の開始 (または再開) とみなす
+pub fn receive_data_chunk(data_store: ArMu<data_store::DataStore>, chunk: DataChunk) -> Result<u32, chord_util::GeneralError> {
+    {
+        let mut receivings_ref = RECEIVING_TRANSFERS.lock().unwrap();
+        let ttl = Duration::from_millis(gval::DATA_TRANSFER_RECEIVE_STATE_TTL_MILLIS);
+        receivings_ref.retain(|_, receiving| receiving.last_received_at.elapsed() <= ttl);
+
+        let expected_seq = if chunk.seq == 0 {
+            0
+        } else {
+            match receivings_ref.get(&chunk.transfer_id) {
+                None => {
+                    return Err(chord_util::GeneralError::new("unknown transfer: ".to_string() + chunk.transfer_id.as_str(), chord_util::ERR_CODE_INVALID_ARGUMENT));
+                }
+                Some(receiving) => receiving.next_seq
+            }
+        };
+        if chunk.seq < expected_seq {
+            // ack が届かずに再送されたチャンク. 格納済みなので ack のみ返す
+            return Ok(chunk.seq);
+        }
+        if chunk.seq > expected_seq {
+            return Err(chord_util::GeneralError::new("data chunk is missing: ".to_string() + expected_seq.to_string().as_str(), chord_util::ERR_CODE_INVALID_ARGUMENT));
+        }
+        receivings_ref.insert(chunk.transfer_id.clone(), ReceivingTransfer { next_seq: chunk.seq + 1, last_received_at: Instant::now() });
+    }
+
+    let mut data_store_ref = data_store.lock().unwrap();
+    data_store_ref.store_transferred_datas(chunk.datas);
+    return Ok(chunk.seq);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gen_chunk(transfer_id: &str, seq: u32, datas: Vec<chord_util::DataIdAndValue>) -> DataChunk {
+        return DataChunk { transfer_id: transfer_id.to_string(), seq: seq, datas: datas };
+    }
+
+    #[test]
+    fn receive_data_chunk_rejects_gap_and_acks_duplicate() {
+        let data_store = Arc::new(Mutex::new(data_store::DataStore::new()));
+        let transfer_id = "test-seq";
+        assert_eq!(receive_data_chunk(Arc::clone(&data_store), gen_chunk(transfer_id, 0, vec![chord_util::DataIdAndValue::new(1, "a".to_string())])).unwrap(), 0);
+        // 1番を飛ばした2番は受け付けない
+        let err = receive_data_chunk(Arc::clone(&data_store), gen_chunk(transfer_id, 2, vec![chord_util::DataIdAndValue::new(3, "c".to_string())])).unwrap_err();
+        assert_eq!(err.err_code, chord_util::ERR_CODE_INVALID_ARGUMENT);
+        assert!(data_store.lock().unwrap().get(3).is_err());
+        assert_eq!(receive_data_chunk(Arc::clone(&data_store), gen_chunk(transfer_id, 1, vec![chord_util::DataIdAndValue::new(2, "b".to_string())])).unwrap(), 1);
+        // 再送された1番は格納し直さずに ack する
+        assert_eq!(receive_data_chunk(Arc::clone(&data_store), gen_chunk(transfer_id, 1, vec![chord_util::DataIdAndValue::new(2, "stale".to_string())])).unwrap(), 1);
+        assert_eq!(data_store.lock().unwrap().get(2).unwrap().val_str, "b");
+    }
+
+    #[test]
+    fn receive_data_chunk_keeps_existing_master() {
+        let data_store = Arc::new(Mutex::new(data_store::DataStore::new()));
+        data_store.lock().unwrap().store_one_iv(chord_util::DataIdAndValue::new(10, "newer".to_string()));
+        let mut replica = chord_util::DataIdAndValue::new(11, "replica".to_string());
+        replica.replica_idx = 1;
+        data_store.lock().unwrap().store_one_iv(replica);
+
+        let datas = vec![chord_util::DataIdAndValue::new(10, "older".to_string()), chord_util::DataIdAndValue::new(11, "master".to_string())];
+        receive_data_chunk(Arc::clone(&data_store), gen_chunk("test-master", 0, datas)).unwrap();
+        let data_store_ref = data_store.lock().unwrap();
+        assert_eq!(data_store_ref.get(10).unwrap().val_str, "newer");
+        assert_eq!(data_store_ref.get(11).unwrap().val_str, "master");
+        assert_eq!(data_store_ref.get(11).unwrap().replica_idx, 0);
+    }
+}
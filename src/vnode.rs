@@ -0,0 +1,164 @@
+use std::sync::{Arc, Mutex, RwLock};
+use std::ops::Deref;
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::outcome::Outcome;
+
+use crate::gval;
+use crate::node_info;
+use crate::chord_util;
+use crate::config;
+
+type ArMu<T> = Arc<Mutex<T>>;
+
+// 1プロセスで複数の仮想ノード (vnode) をホストするための管理
+// 各仮想ノードは独自の NodeInfo (ID, successor_info_list, finger_table など) と stabilize処理を持ち、
+// リスナと DataStore はプロセス内で共有する
+//
+// 仮想ノードのアドレスは "IPアドレス:ポート番号#仮想ノードの番号" とする. 0番の仮想ノードは番号を付けず、
+// 仮想ノードを用いない場合と同じアドレスとなる. RPCの宛先の仮想ノードは、HTTPの場合は X-Vnode ヘッダで、
+// バイナリプロトコルの場合はリクエストのフレームで指定する
+// コネクションプールやサーキットブレーカーなど、通信に関するものはプロセス (物理ノード) 単位で扱う
+
+pub const VNODE_ADDRESS_SEPARATOR : char = '#';
+pub const VNODE_HEADER : &str = "X-Vnode";
+
+lazy_static! {
+    // インデックスが仮想ノードの番号
+    static ref VNODES : RwLock<Vec<ArMu<node_info::NodeInfo>>> = RwLock::new(vec![]);
+}
+
+// 設定からこのプロセスでホストする仮想ノードの数を求める
+// vnode_count に vnode_weight を掛けたものとし、性能の高いマシンほど多くの仮想ノードを受け持つようにできる
+pub fn calc_vnode_count() -> u32 {
+    let conf = config::get_config();
+    let count = (conf.vnode_count as f64 * conf.vnode_weight).round() as u32;
+    return count.clamp(1, gval::VNODE_MAX_PER_PROCESS);
+}
+
+pub fn register(node: ArMu<node_info::NodeInfo>) -> u32 {
+    let mut vnodes_ref = VNODES.write().unwrap();
+    vnodes_ref.push(node);
+    return (vnodes_ref.len() - 1) as u32;
+}
+
+pub fn get(vnode_idx: u32) -> Option<ArMu<node_info::NodeInfo>> {
+    let vnodes_ref = VNODES.read().unwrap();
+    return match vnodes_ref.get(vnode_idx as usize) {
+        None => None,
+        Some(node) => Some(Arc::clone(node))
+    };
+}
+
+pub fn get_all() -> Vec<ArMu<node_info::NodeInfo>> {
+    let vnodes_ref = VNODES.read().unwrap();
+    return vnodes_ref.clone();
+}
+
+// このプロセスが複数の仮想ノードをホストしているか
+pub fn is_multi_vnode() -> bool {
+    let vnodes_ref = VNODES.read().unwrap();
+    return vnodes_ref.len() > 1;
+}
+
+pub fn gen_vnode_address(physical_address: &str, vnode_idx: u32) -> String {
+    if vnode_idx == 0 {
+        return physical_address.to_string();
+    }
+    return physical_address.to_string() + VNODE_ADDRESS_SEPARATOR.to_string().as_str() + vnode_idx.to_string().as_str();
+}
+
+// 1番以降の仮想ノードの born_id (デバッグ用のID) を、プロセスの born_id と仮想ノードの番号から求める
+// born_id * 1000 + 仮想ノードの番号 とし、i32 に収まらない場合は分からないものとして -1 とする
+pub fn gen_vnode_born_id(born_id: i32, vnode_idx: u32) -> i32 {
+    return born_id.checked_mul(1000).and_then(|base| base.checked_add(vnode_idx as i32)).unwrap_or(-1);
+}
+
+// 仮想ノードのアドレスを (物理ノードのアドレス, 仮想ノードの番号) に分ける
+pub fn split_vnode_address(address: &str) -> (String, u32) {
+    return match address.rsplit_once(VNODE_ADDRESS_SEPARATOR) {
+        None => (address.to_string(), 0),
+        Some((physical, idx_str)) => match idx_str.parse::<u32>() {
+            Err(_) => (address.to_string(), 0),
+            Ok(idx) => (physical.to_string(), idx)
+        }
+    };
+}
+
+pub fn get_physical_address(address: &str) -> String {
+    return split_vnode_address(address).0;
+}
+
+// address がこのプロセスでホストしている仮想ノードのものか
+pub fn is_local_address(address: &str) -> bool {
+    let local_address = match get(0) {
+        None => { return false; }
+        Some(node) => node.lock().unwrap().address_str.clone()
+    };
+    return get_physical_address(address) == local_address;
+}
+
+// 内部RPCの宛先の仮想ノードの NodeInfo を得るリクエストガード
+// ハンドラでは &State<ArMu<node_info::NodeInfo>> と同様に扱える
+pub struct TargetVnode(ArMu<node_info::NodeInfo>);
+
+impl Deref for TargetVnode {
+    type Target = ArMu<node_info::NodeInfo>;
+
+    fn deref(&self) -> &Self::Target {
+        return &self.0;
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for TargetVnode {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let vnode_idx = match req.headers().get_one(VNODE_HEADER) {
+            None => 0,
+            Some(idx_str) => match idx_str.parse::<u32>() {
+                Err(_) => { return Outcome::Error((Status::BadRequest, ())); }
+                Ok(idx) => idx
+            }
+        };
+        return match get(vnode_idx) {
+            None => {
+                chord_util::dprint(&("ERROR at TargetVnode::from_request,unknown vnode,".to_string() + vnode_idx.to_string().as_str()));
+                Outcome::Error((Status::NotFound, ()))
+            }
+            Some(node) => Outcome::Success(TargetVnode(node))
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_vnode_address_is_inverse_of_gen_vnode_address() {
+        for vnode_idx in [0, 1, 15] {
+            let address = gen_vnode_address("127.0.0.1:11000", vnode_idx);
+            assert_eq!(split_vnode_address(&address), ("127.0.0.1:11000".to_string(), vnode_idx));
+        }
+        assert_eq!(gen_vnode_address("127.0.0.1:11000", 0), "127.0.0.1:11000");
+        assert_eq!(get_physical_address("127.0.0.1:11000#3"), "127.0.0.1:11000");
+    }
+
+    #[test]
+    fn split_vnode_address_treats_malformed_suffix_as_physical() {
+        assert_eq!(split_vnode_address("127.0.0.1:11000#"), ("127.0.0.1:11000#".to_string(), 0));
+        assert_eq!(split_vnode_address("127.0.0.1:11000#x"), ("127.0.0.1:11000#x".to_string(), 0));
+        assert_eq!(split_vnode_address("127.0.0.1:11000#-1"), ("127.0.0.1:11000#-1".to_string(), 0));
+        // 区切り文字が複数ある場合は最後のものを用いる
+        assert_eq!(split_vnode_address("a#1#2"), ("a#1".to_string(), 2));
+    }
+
+    #[test]
+    fn gen_vnode_born_id_falls_back_on_overflow() {
+        assert_eq!(gen_vnode_born_id(12, 3), 12003);
+        assert_eq!(gen_vnode_born_id(i32::MAX / 1000 + 1, 1), -1);
+        assert_eq!(gen_vnode_born_id(i32::MAX / 1000, 1000), -1);
+    }
+}
@@ -162,7 +162,7 @@ var platform string
 // }
 
 func check_chain_with_successor_info() {
-	const endpoint_path = "/get_node_info"
+	const endpoint_path = "/internal/get_node_info"
 	start_port := 11000
 	start_addr := bind_ip_addr + ":" + strconv.Itoa(start_port)
 